@@ -0,0 +1,90 @@
+-- Initial schema of the database
+-- It matches the scripts that used to be run by hand, so databases created with them can adopt the migrations as they are
+-- Everything uses IF NOT EXISTS for that reason (the index names are the ones Postgres generated for the unnamed indexes)
+
+-- We should use PostgreSQL with TimescaleDB extension for time-series capabilities.
+-- CREATE EXTENSION IF NOT EXISTS timescaledb; <-- TODO
+-- I don't use TimeScaleDB, because I have troubles installing it on my local machine.
+CREATE TABLE IF NOT EXISTS candles (
+    id SERIAL,                             -- Unique identifier for each candle (not primary because of time-series nature)
+    symbol TEXT NOT NULL,                  -- Trading pair symbol (e.g., EURUSD)
+    timerange TEXT NOT NULL,               -- Timerange for the candle (e.g., 1m, 5m, 1h)
+    timestamp TIMESTAMPTZ NOT NULL,        -- Start time of the candle
+    open DOUBLE PRECISION NOT NULL,        -- Opening price of the candle
+    high DOUBLE PRECISION NOT NULL,        -- Highest price during the candle
+    close DOUBLE PRECISION NOT NULL,       -- Closing price of the candle
+    low DOUBLE PRECISION NOT NULL,         -- Lowest price during the candle
+    volume DOUBLE PRECISION NOT NULL,      -- Trading volume during the candle
+    direction TEXT NOT NULL,               -- Direction of the candle (bullish or bearish)
+    UNIQUE(symbol, timerange, timestamp)   -- Ensure unique entries for each symbol, timerange, and timestamp
+);
+
+-- Create a hypertable for time-series data
+-- SELECT create_hypertable('candles', 'timestamp'); <-- TODO
+
+-- Create indexes to optimize queries
+CREATE INDEX IF NOT EXISTS candles_symbol_timerange_timestamp_idx ON candles (symbol, timerange, timestamp DESC);
+
+-- Session-level trading data (e.g., Asian, London, New York)
+CREATE TABLE IF NOT EXISTS sessions (
+    id SERIAL PRIMARY KEY,                 -- Unique identifier
+    symbol TEXT NOT NULL,                  -- Trading symbol (e.g., EURUSD)
+    label TEXT NOT NULL,                   -- Session label (e.g., Asian, London)
+    start_time TIMESTAMPTZ NOT NULL,       -- Start time of the session
+    end_time TIMESTAMPTZ NOT NULL,         -- End time of the session
+    high DOUBLE PRECISION NOT NULL,        -- Highest price during the session
+    low DOUBLE PRECISION NOT NULL,         -- Lowest price during the session
+    open DOUBLE PRECISION NOT NULL,        -- Opening price of the session
+    close DOUBLE PRECISION NOT NULL,       -- Closing price of the session
+    volume DOUBLE PRECISION NOT NULL,      -- Trading volume during the session
+    UNIQUE(label, start_time)              -- Ensure no duplicate sessions
+);
+
+-- Index for fast lookup by label and time
+CREATE INDEX IF NOT EXISTS sessions_label_start_time_idx ON sessions (label, start_time DESC);
+
+-- Structural trading data with time ranges and directional context
+CREATE TABLE IF NOT EXISTS two_d_structures (
+    id SERIAL PRIMARY KEY,                   -- Unique auto-increment identifier
+    symbol TEXT NOT NULL,                    -- Trading symbol (e.g., EURUSD)
+    structure TEXT NOT NULL,                 -- Name/type of the structure (e.g., FVG)
+    timerange TEXT NOT NULL,                 -- Time range label
+    timestamp TIMESTAMPTZ NOT NULL,          -- Precise UTC timestamp of the structure
+    high DOUBLE PRECISION NOT NULL,          -- Highest recorded value within the structure
+    low DOUBLE PRECISION NOT NULL,           -- Lowest recorded value within the structure
+    direction TEXT NOT NULL,                 -- Associated direction (e.g., bullish, bearish)
+    UNIQUE (structure, timerange, timestamp) -- Prevents exact duplicate entries
+);
+
+-- Index to speed up queries by structure, timerange, and descending timestamp
+CREATE INDEX IF NOT EXISTS two_d_structures_structure_timerange_timestamp_idx ON two_d_structures (structure, timerange, timestamp DESC);
+
+CREATE TABLE IF NOT EXISTS one_d_structures (
+    id SERIAL PRIMARY KEY,                   -- Unique auto-increment identifier
+    symbol TEXT NOT NULL,                    -- Trading symbol (e.g., EURUSD)
+    structure TEXT NOT NULL,                 -- Name/type of the structure (e.g., FVG)
+    timerange TEXT NOT NULL,                 -- Time range label
+    timestamp TIMESTAMPTZ NOT NULL,          -- Precise UTC timestamp of the structure
+    price DOUBLE PRECISION NOT NULL,         -- Price at the time of the structure
+    direction TEXT NOT NULL,                 -- Associated direction (e.g., bullish, bearish)
+    UNIQUE (structure, timerange, timestamp) -- Prevents exact duplicate entries
+);
+
+-- Index to speed up queries by structure, timerange, and descending timestamp
+CREATE INDEX IF NOT EXISTS one_d_structures_structure_timerange_timestamp_idx ON one_d_structures (structure, timerange, timestamp DESC);
+
+-- Trend-level data across time ranges
+CREATE TABLE IF NOT EXISTS trends (
+    id SERIAL PRIMARY KEY,                         -- Unique identifier
+    symbol TEXT NOT NULL,                          -- Trading symbol (e.g., EURUSD)
+    timerange TEXT NOT NULL,                       -- Timeframe (e.g., 1h, 4h, 1d)
+    start_time TIMESTAMPTZ NOT NULL,               -- Start time of the trend
+    end_time TIMESTAMPTZ NOT NULL,                 -- End time of the trend
+    direction TEXT NOT NULL,                       -- Trend direction (e.g., bullish, bearish)
+    high DOUBLE PRECISION NOT NULL,                -- Absolute high during the trend
+    low DOUBLE PRECISION NOT NULL,                 -- Absolute low during the trend
+    UNIQUE(symbol, timerange, start_time)          -- Prevent duplicate trends
+);
+
+-- Index for efficient querying by symbol and timeframe
+CREATE INDEX IF NOT EXISTS trends_symbol_timerange_start_time_idx ON trends (symbol, timerange, start_time DESC);
\ No newline at end of file
@@ -0,0 +1,13 @@
+-- Candles are marked as complete or partial (closed before their period was over)
+ALTER TABLE candles ADD COLUMN IF NOT EXISTS complete BOOLEAN NOT NULL DEFAULT TRUE;
+
+-- The unique constraints are used by the upserts, so they have to include the symbol
+-- Unique indexes are used instead of constraints so this can run on databases that already have them
+ALTER TABLE sessions DROP CONSTRAINT IF EXISTS sessions_label_start_time_key;
+CREATE UNIQUE INDEX IF NOT EXISTS sessions_symbol_label_start_time_key ON sessions (symbol, label, start_time);
+
+ALTER TABLE two_d_structures DROP CONSTRAINT IF EXISTS two_d_structures_structure_timerange_timestamp_key;
+CREATE UNIQUE INDEX IF NOT EXISTS two_d_structures_symbol_structure_timerange_timestamp_key ON two_d_structures (symbol, structure, timerange, timestamp);
+
+ALTER TABLE one_d_structures DROP CONSTRAINT IF EXISTS one_d_structures_structure_timerange_timestamp_key;
+CREATE UNIQUE INDEX IF NOT EXISTS one_d_structures_symbol_structure_timerange_timestamp_key ON one_d_structures (symbol, structure, timerange, timestamp);
\ No newline at end of file
@@ -0,0 +1,6 @@
+-- Store every field of the Rust `Trend` struct
+-- They are nullable because the trends stored before this migration don't have them
+ALTER TABLE trends ADD COLUMN IF NOT EXISTS high_datetime TIMESTAMPTZ;       -- When the high was reached
+ALTER TABLE trends ADD COLUMN IF NOT EXISTS low_datetime TIMESTAMPTZ;        -- When the low was reached
+ALTER TABLE trends ADD COLUMN IF NOT EXISTS relative_high DOUBLE PRECISION;  -- Last relative high of the trend
+ALTER TABLE trends ADD COLUMN IF NOT EXISTS relative_low DOUBLE PRECISION;   -- Last relative low of the trend
\ No newline at end of file
@@ -0,0 +1,7 @@
+-- Last checkpoint of the engine state, used to resume after a restart
+-- Only one row is kept, every checkpoint replaces it
+CREATE TABLE IF NOT EXISTS checkpoints (
+    id INTEGER PRIMARY KEY CHECK (id = 1),            -- Always 1
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),    -- When the checkpoint was written
+    state JSONB NOT NULL                              -- Serialized snapshot of the engine state
+);
\ No newline at end of file
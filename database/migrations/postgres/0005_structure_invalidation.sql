@@ -0,0 +1,5 @@
+-- Fair value gaps and order blocks are invalidated once the price goes through them
+-- The retention can drop them some time after that
+ALTER TABLE two_d_structures ADD COLUMN IF NOT EXISTS invalidated_at TIMESTAMPTZ;  -- NULL while the structure is still valid
+
+CREATE INDEX IF NOT EXISTS two_d_structures_invalidated_at_idx ON two_d_structures (invalidated_at) WHERE invalidated_at IS NOT NULL;
\ No newline at end of file
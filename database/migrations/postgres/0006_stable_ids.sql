@@ -0,0 +1,28 @@
+-- Stable ids of the rows and references between them
+-- The `id` columns are only row numbers, the `uid` is computed by the pipeline from the unique key of the row,
+-- So a row has the same uid in every database, after a replay and in the websocket messages
+-- The rows stored before this migration get their uid the next time they're written
+ALTER TABLE candles ADD COLUMN IF NOT EXISTS uid UUID;
+ALTER TABLE sessions ADD COLUMN IF NOT EXISTS uid UUID;
+ALTER TABLE trends ADD COLUMN IF NOT EXISTS uid UUID;
+ALTER TABLE one_d_structures ADD COLUMN IF NOT EXISTS uid UUID;
+ALTER TABLE two_d_structures ADD COLUMN IF NOT EXISTS uid UUID;
+
+-- No unique index on the candles: it would have to include the timestamp once they're a TimescaleDB hypertable
+CREATE UNIQUE INDEX IF NOT EXISTS sessions_uid_key ON sessions (uid);
+CREATE UNIQUE INDEX IF NOT EXISTS trends_uid_key ON trends (uid);
+CREATE UNIQUE INDEX IF NOT EXISTS one_d_structures_uid_key ON one_d_structures (uid);
+CREATE UNIQUE INDEX IF NOT EXISTS two_d_structures_uid_key ON two_d_structures (uid);
+
+-- The references are checked when the transaction commits, so the rows of a batch can be written in any order
+-- The retention can delete the referenced rows, the references are cleared then
+ALTER TABLE one_d_structures ADD COLUMN IF NOT EXISTS broken_uid UUID          -- Swing broken by a BOS or a CHoCH
+    REFERENCES one_d_structures (uid) ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED;
+ALTER TABLE one_d_structures ADD COLUMN IF NOT EXISTS trend_uid UUID           -- Trend the structure was found in
+    REFERENCES trends (uid) ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED;
+ALTER TABLE two_d_structures ADD COLUMN IF NOT EXISTS trend_uid UUID           -- Trend an order block was found in
+    REFERENCES trends (uid) ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED;
+
+-- The candles can't be referenced by a foreign key once they're a hypertable
+ALTER TABLE two_d_structures ADD COLUMN IF NOT EXISTS first_candle_uid UUID;   -- First candle the structure was built from
+ALTER TABLE two_d_structures ADD COLUMN IF NOT EXISTS last_candle_uid UUID;    -- Last candle the structure was built from
\ No newline at end of file
@@ -0,0 +1,7 @@
+-- Several renko bricks can be built from the same 1m candle, they share its timestamp and are told apart by their sequence
+-- The other candles keep the sequence 0
+ALTER TABLE candles ADD COLUMN IF NOT EXISTS sequence INTEGER NOT NULL DEFAULT 0;
+
+-- The upserts of the candles use the new unique key
+ALTER TABLE candles DROP CONSTRAINT IF EXISTS candles_symbol_timerange_timestamp_key;
+CREATE UNIQUE INDEX IF NOT EXISTS candles_symbol_timerange_timestamp_sequence_key ON candles (symbol, timerange, timestamp, sequence);
\ No newline at end of file
@@ -0,0 +1,70 @@
+-- Schema of the SQLite storage, matching the Postgres schema
+-- Timestamps are stored as microseconds since the Unix epoch (UTC)
+
+CREATE TABLE IF NOT EXISTS candles (
+    id INTEGER PRIMARY KEY,
+    symbol TEXT NOT NULL,
+    timerange TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    open REAL NOT NULL,
+    high REAL NOT NULL,
+    low REAL NOT NULL,
+    close REAL NOT NULL,
+    volume REAL NOT NULL,
+    direction TEXT NOT NULL,
+    complete INTEGER NOT NULL DEFAULT 1,
+    UNIQUE(symbol, timerange, timestamp)
+);
+
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY,
+    symbol TEXT NOT NULL,
+    label TEXT NOT NULL,
+    start_time INTEGER NOT NULL,
+    end_time INTEGER NOT NULL,
+    high REAL NOT NULL,
+    low REAL NOT NULL,
+    open REAL NOT NULL,
+    close REAL NOT NULL,
+    volume REAL NOT NULL,
+    UNIQUE(symbol, label, start_time)
+);
+
+CREATE TABLE IF NOT EXISTS two_d_structures (
+    id INTEGER PRIMARY KEY,
+    symbol TEXT NOT NULL,
+    structure TEXT NOT NULL,
+    timerange TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    high REAL NOT NULL,
+    low REAL NOT NULL,
+    direction TEXT NOT NULL,
+    UNIQUE(symbol, structure, timerange, timestamp)
+);
+
+CREATE TABLE IF NOT EXISTS one_d_structures (
+    id INTEGER PRIMARY KEY,
+    symbol TEXT NOT NULL,
+    structure TEXT NOT NULL,
+    timerange TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    price REAL NOT NULL,
+    direction TEXT NOT NULL,
+    UNIQUE(symbol, structure, timerange, timestamp)
+);
+
+CREATE TABLE IF NOT EXISTS trends (
+    id INTEGER PRIMARY KEY,
+    symbol TEXT NOT NULL,
+    timerange TEXT NOT NULL,
+    start_time INTEGER NOT NULL,
+    end_time INTEGER NOT NULL,
+    direction TEXT NOT NULL,
+    high REAL NOT NULL,
+    low REAL NOT NULL,
+    high_datetime INTEGER,
+    low_datetime INTEGER,
+    relative_high REAL,
+    relative_low REAL,
+    UNIQUE(symbol, timerange, start_time)
+);
\ No newline at end of file
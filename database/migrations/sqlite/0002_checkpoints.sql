@@ -0,0 +1,7 @@
+-- Last checkpoint of the engine state, used to resume after a restart
+-- Only one row is kept, every checkpoint replaces it
+CREATE TABLE IF NOT EXISTS checkpoints (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    created_at INTEGER NOT NULL,
+    state TEXT NOT NULL
+);
\ No newline at end of file
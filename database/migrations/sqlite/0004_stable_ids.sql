@@ -0,0 +1,19 @@
+-- Stable ids of the rows and references between them (see the Postgres migration)
+-- The uids are stored as text
+ALTER TABLE candles ADD COLUMN uid TEXT;
+ALTER TABLE sessions ADD COLUMN uid TEXT;
+ALTER TABLE trends ADD COLUMN uid TEXT;
+ALTER TABLE one_d_structures ADD COLUMN uid TEXT;
+ALTER TABLE two_d_structures ADD COLUMN uid TEXT;
+
+CREATE INDEX IF NOT EXISTS candles_uid_idx ON candles (uid);
+CREATE UNIQUE INDEX IF NOT EXISTS sessions_uid_key ON sessions (uid);
+CREATE UNIQUE INDEX IF NOT EXISTS trends_uid_key ON trends (uid);
+CREATE UNIQUE INDEX IF NOT EXISTS one_d_structures_uid_key ON one_d_structures (uid);
+CREATE UNIQUE INDEX IF NOT EXISTS two_d_structures_uid_key ON two_d_structures (uid);
+
+ALTER TABLE one_d_structures ADD COLUMN broken_uid TEXT REFERENCES one_d_structures (uid) ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED;
+ALTER TABLE one_d_structures ADD COLUMN trend_uid TEXT REFERENCES trends (uid) ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED;
+ALTER TABLE two_d_structures ADD COLUMN trend_uid TEXT REFERENCES trends (uid) ON DELETE SET NULL DEFERRABLE INITIALLY DEFERRED;
+ALTER TABLE two_d_structures ADD COLUMN first_candle_uid TEXT;
+ALTER TABLE two_d_structures ADD COLUMN last_candle_uid TEXT;
\ No newline at end of file
@@ -0,0 +1,26 @@
+-- Sequence of the candles built from the same 1m candle (see the Postgres migration)
+-- SQLite can't change a unique constraint, so the table is rebuilt
+CREATE TABLE candles_with_sequence (
+    id INTEGER PRIMARY KEY,
+    symbol TEXT NOT NULL,
+    timerange TEXT NOT NULL,
+    timestamp INTEGER NOT NULL,
+    open REAL NOT NULL,
+    high REAL NOT NULL,
+    low REAL NOT NULL,
+    close REAL NOT NULL,
+    volume REAL NOT NULL,
+    direction TEXT NOT NULL,
+    complete INTEGER NOT NULL DEFAULT 1,
+    uid TEXT,
+    sequence INTEGER NOT NULL DEFAULT 0,
+    UNIQUE(symbol, timerange, timestamp, sequence)
+);
+
+INSERT INTO candles_with_sequence (id, symbol, timerange, timestamp, open, high, low, close, volume, direction, complete, uid)
+    SELECT id, symbol, timerange, timestamp, open, high, low, close, volume, direction, complete, uid FROM candles;
+
+DROP TABLE candles;
+ALTER TABLE candles_with_sequence RENAME TO candles;
+
+CREATE INDEX IF NOT EXISTS candles_uid_idx ON candles (uid);
\ No newline at end of file
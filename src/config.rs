@@ -0,0 +1,456 @@
+// Runtime configuration of the pipeline
+// It's loaded once at startup from a JSON file (see `init_config`)
+// Every field has a default value, so the file and any of its sections can be omitted
+
+use crate::{
+    connections::{storage::{retention::{RetentionRule, TableName}, Backend}, websocket::LaggingPolicy},
+    handlers::{checkpoint::CheckpointTarget, derived::Transform, validation::Policy},
+    BarKind,
+    TIMERANGES,
+};
+
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{fs, path::Path};
+
+// Default location of the configuration file
+// It can be overridden with the PARAGON_CONFIG environment variable
+pub const DEFAULT_CONFIG_PATH: &str = "paragon.json";
+
+pub static CONFIG: OnceCell<Config> = OnceCell::new();
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub candles: CandlesConfig,
+    pub database: DatabaseConfig,
+    // Labels of the timeranges to aggregate (e.g. "5min", "2h", "3d")
+    pub timeranges: Vec<String>,
+    // Non time based bars to build (volume, tick, range, renko)
+    pub bars: Vec<BarConfig>,
+    // Series derived from the timeranges (Heikin-Ashi, smoothed candles)
+    pub derived: Vec<DerivedConfig>,
+    pub validation: ValidationConfig,
+    pub writer: WriterConfig,
+    pub checkpoint: CheckpointConfig,
+    pub warmup: WarmupConfig,
+    pub retention: RetentionConfig,
+    pub websocket: WebsocketConfig,
+    pub http: HttpConfig,
+    // Who can connect to the servers
+    pub auth: AuthConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CandlesConfig {
+    // Close the candles when their period is over on the wall clock,
+    // Instead of waiting for a candle of the next period to arrive
+    // This should only be enabled with live data
+    pub closer: bool,
+    // How long we keep waiting for late 1m candles once the period is over
+    pub grace_period_ms: u64,
+    // How often the closer checks for expired candles
+    pub closer_interval_ms: u64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct DatabaseConfig {
+    // Where the rows are stored: "postgres", "sqlite" or "memory"
+    pub backend: Backend,
+    // Postgres connection
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    pub password: Option<String>,
+    pub dbname: String,
+    // SQLite database file
+    pub path: String,
+    // Apply the pending schema migrations on startup
+    // The TimescaleDB objects are only created then, so they need it too
+    pub migrate: bool,
+    pub timescale: TimescaleConfig,
+}
+
+// TimescaleDB objects created with the migrations, when the extension is available (Postgres only)
+// Nothing is set up while database.migrate is off
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct TimescaleConfig {
+    // Set to false to keep plain tables even if the extension is available
+    pub enabled: bool,
+    // Time covered by each chunk of the candles hypertable (a Postgres interval)
+    pub chunk_interval: String,
+    // Compress the chunks older than this (e.g. "30 days"), disabled by default
+    // The compressed candles are read-only on old TimescaleDB versions
+    pub compress_after: Option<String>,
+    // Timeranges kept up to date as continuous aggregates of the 1min candles
+    pub aggregates: Vec<String>,
+    // Number of periods refreshed by each run of the continuous aggregates (at least 2)
+    pub refresh_window: usize,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct BarConfig {
+    pub kind: BarKind,
+    pub size: f64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct DerivedConfig {
+    // The timerange the series is derived from
+    pub timerange: String,
+    #[serde(flatten)]
+    pub transform: Transform,
+    // Run the trend engine on the derived series
+    #[serde(default)]
+    pub trends: bool,
+}
+
+// Policies applied to the issues found in the incoming 1m candles
+// By default every issue is only flagged, so the data goes through unchanged,
+// Except the duplicates and the out of order candles, which are dropped since they can't be merged into the aggregated candles
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct ValidationConfig {
+    pub missing_minutes: Policy,
+    pub duplicates: Policy,
+    pub out_of_order: Policy,
+    pub inconsistent_ohlc: Policy,
+    pub invalid_prices: Policy,
+    pub spikes: Policy,
+    // Relative move from the previous close above which a candle is a spike (0 disables it)
+    pub spike_threshold: f64,
+    // Gaps longer than this (e.g. weekends) are never forward-filled
+    pub max_filled_minutes: u64,
+}
+
+// Write-behind persistence of the rows
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WriterConfig {
+    // When disabled, every row is written as soon as it's produced
+    pub enabled: bool,
+    // Rows the queue can hold before the pipeline has to wait
+    pub capacity: usize,
+    // Rows written at once
+    pub batch_size: usize,
+    // Maximum time a row stays buffered
+    pub flush_interval_ms: u64,
+    // Failed writes of a batch before it's split to find (and drop) the rows that can't be written
+    pub max_retries: usize,
+}
+
+// Periodic snapshots of the engine state, to resume after a restart
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct CheckpointConfig {
+    pub enabled: bool,
+    // Where the checkpoints are written: "file" or "database"
+    pub target: CheckpointTarget,
+    // Checkpoint file (when the target is "file")
+    pub path: String,
+    pub interval_ms: u64,
+    // Resume from the last checkpoint on startup
+    pub restore: bool,
+}
+
+// Replay of the stored history before processing the live data
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WarmupConfig {
+    pub enabled: bool,
+    pub symbols: Vec<String>,
+    // Full periods of the longest timerange replayed before its open period
+    pub history_periods: usize,
+}
+
+// Deletion of the old rows, see `connections::storage::retention`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct RetentionConfig {
+    // Apply the rules periodically (they can also be applied once from the command line)
+    pub enabled: bool,
+    pub interval_ms: u64,
+    // Only report what would be deleted
+    pub dry_run: bool,
+    pub rules: Vec<RetentionRule>,
+}
+
+// Server streaming the candles and structures to the clients
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct WebsocketConfig {
+    pub address: String,
+    // The connections above this are closed right away
+    pub max_connections: usize,
+    // A connection whose TLS or WebSocket handshake takes longer is closed (it holds one of the connections meanwhile)
+    pub handshake_timeout_ms: u64,
+    // Messages waiting to be written to each client
+    pub client_queue: usize,
+    // Rows replayed at most by a subscription (per symbol, timerange and type), larger histories are capped
+    pub max_history: usize,
+    // What to do with a client whose queue is full: "drop" the messages or "disconnect" it
+    pub lagging_policy: LaggingPolicy,
+    // Messages waiting to be dispatched to the clients, the pipeline waits when it's full
+    pub fanout_queue: usize,
+    // The clients are pinged at this interval (0 to disable),
+    // And disconnected if nothing comes back within the timeout
+    pub ping_interval_ms: u64,
+    pub pong_timeout_ms: u64,
+    // A client without subscription is disconnected after this long without a request (0 to disable)
+    pub idle_timeout_ms: u64,
+    // Interval of the heartbeat messages with the status of the pipeline (0 to disable)
+    pub heartbeat_interval_ms: u64,
+    pub tls: TlsConfig,
+}
+
+// Server answering the queries of the stored history, see `connections::http`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct HttpConfig {
+    pub enabled: bool,
+    pub address: String,
+    // Rows returned at most by a query, the clients page with `offset`
+    pub max_rows: usize,
+    // The connections above this are closed right away
+    pub max_connections: usize,
+    // A connection whose TLS handshake or request headers take longer is closed
+    pub handshake_timeout_ms: u64,
+    pub tls: TlsConfig,
+}
+
+// Certificate and key of a server, PEM files
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct TlsConfig {
+    pub enabled: bool,
+    // The certificate chain, the server's first
+    pub certificate: String,
+    pub key: String,
+}
+
+// Tokens the clients authenticate with, see `connections::auth`
+// When it's disabled, everyone can connect and subscribe to every symbol
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct AuthConfig {
+    pub enabled: bool,
+    pub tokens: Vec<TokenConfig>,
+    // Secret of the signed tokens, they're refused without it
+    pub secret: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TokenConfig {
+    pub token: String,
+    // Who it was given to, for the logs
+    pub name: String,
+    // The symbols it can subscribe to, all of them if empty
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    // Whether it can change the timeranges through the HTTP API
+    #[serde(default)]
+    pub admin: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            candles: CandlesConfig::default(),
+            database: DatabaseConfig::default(),
+            timeranges: TIMERANGES.iter().map(|t| t.label.to_string()).collect(),
+            bars: Vec::new(),
+            derived: Vec::new(),
+            validation: ValidationConfig::default(),
+            writer: WriterConfig::default(),
+            checkpoint: CheckpointConfig::default(),
+            warmup: WarmupConfig::default(),
+            retention: RetentionConfig::default(),
+            websocket: WebsocketConfig::default(),
+            http: HttpConfig::default(),
+            auth: AuthConfig::default(),
+        }
+    }
+}
+
+impl Default for CandlesConfig {
+    fn default() -> Self {
+        CandlesConfig {
+            closer: false,
+            grace_period_ms: 5_000,
+            closer_interval_ms: 1_000,
+        }
+    }
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            backend: Backend::Postgres,
+            host: "localhost".to_string(),
+            port: 5432,
+            user: "enzoblain".to_string(),
+            password: None,
+            dbname: "Paragon".to_string(),
+            path: "paragon.db".to_string(),
+            migrate: false,
+            timescale: TimescaleConfig::default(),
+        }
+    }
+}
+
+impl Default for TimescaleConfig {
+    fn default() -> Self {
+        TimescaleConfig {
+            enabled: true,
+            chunk_interval: "7 days".to_string(),
+            compress_after: None,
+            aggregates: vec!["1h".to_string(), "4h".to_string(), "1d".to_string()],
+            refresh_window: 3,
+        }
+    }
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            missing_minutes: Policy::Flag,
+            duplicates: Policy::Reject,
+            out_of_order: Policy::Reject,
+            inconsistent_ohlc: Policy::Flag,
+            invalid_prices: Policy::Flag,
+            spikes: Policy::Flag,
+            spike_threshold: 0.05,
+            max_filled_minutes: 60,
+        }
+    }
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        WriterConfig {
+            enabled: true,
+            capacity: 10_000,
+            batch_size: 1_000,
+            flush_interval_ms: 1_000,
+            max_retries: 5,
+        }
+    }
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        CheckpointConfig {
+            enabled: false,
+            target: CheckpointTarget::File,
+            path: "paragon.checkpoint.json".to_string(),
+            interval_ms: 60_000,
+            restore: true,
+        }
+    }
+}
+
+impl Default for WarmupConfig {
+    fn default() -> Self {
+        WarmupConfig {
+            enabled: false,
+            symbols: vec!["EURUSD".to_string()],
+            history_periods: 2,
+        }
+    }
+}
+
+// By default the 1m candles are kept 90 days (the higher timeranges forever),
+// And the invalidated fair value gaps and order blocks 30 days
+impl Default for RetentionConfig {
+    fn default() -> Self {
+        RetentionConfig {
+            enabled: false,
+            interval_ms: 3_600_000,
+            dry_run: false,
+            rules: vec![
+                RetentionRule {
+                    table: TableName::Candles,
+                    timerange: Some("1min".to_string()),
+                    kind: None,
+                    keep_days: 90,
+                    invalidated: false,
+                    downsample: None,
+                },
+                RetentionRule {
+                    table: TableName::TwoDStructures,
+                    timerange: None,
+                    kind: None,
+                    keep_days: 30,
+                    invalidated: true,
+                    downsample: None,
+                },
+            ],
+        }
+    }
+}
+
+impl Default for WebsocketConfig {
+    fn default() -> Self {
+        WebsocketConfig {
+            address: "127.0.0.1:8080".to_string(),
+            max_connections: 100,
+            handshake_timeout_ms: 10_000,
+            client_queue: 1024,
+            max_history: 1_000,
+            // A client that reconnects gets a snapshot, a client that misses messages doesn't
+            lagging_policy: LaggingPolicy::Disconnect,
+            fanout_queue: 4096,
+            ping_interval_ms: 20_000,
+            pong_timeout_ms: 10_000,
+            idle_timeout_ms: 300_000,
+            heartbeat_interval_ms: 5_000,
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        HttpConfig {
+            enabled: false,
+            address: "127.0.0.1:8081".to_string(),
+            max_rows: 10_000,
+            max_connections: 100,
+            handshake_timeout_ms: 10_000,
+            tls: TlsConfig::default(),
+        }
+    }
+}
+
+// Load the configuration file and store it in the global state
+pub fn init_config() -> Result<(), String> {
+    CONFIG.set(read_config()?).map_err(|_| "Config already initialized")?;
+    Ok(())
+}
+
+// Reads the configuration file, without storing it (the timeranges are read again on SIGHUP)
+// If the file doesn't exist, the default configuration is used
+pub fn read_config() -> Result<Config, String> {
+    let path = std::env::var("PARAGON_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG_PATH.to_string());
+
+    let config = if Path::new(&path).exists() {
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path, e))?;
+
+        serde_json::from_str(&content)
+            .map_err(|e| format!("Failed to parse config file {}: {}", path, e))?
+    } else {
+        Config::default()
+    };
+
+    Ok(config)
+}
+
+// Facilitate access to the configuration
+// Falls back to the default configuration if it hasn't been initialized
+pub fn get_config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
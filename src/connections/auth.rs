@@ -0,0 +1,261 @@
+// Authentication of the clients
+// A client presents a token in the "Authorization: Bearer <token>" header,
+// Or in the "token" query parameter since the browsers can't set headers on a websocket
+//
+// Two kinds of tokens are accepted:
+// - The static tokens of the configuration, each with the name of its owner and its symbols
+// - Signed tokens: "<claims>.<signature>", both base64url without padding
+//   The claims are {"sub": "alice", "symbols": ["EURUSD"], "exp": 1735689600, "admin": false} ("symbols", "exp" and "admin" are optional)
+//   And the signature is the HMAC-SHA256 of the encoded claims with the configured secret
+//   They're created with `paragon token <name> [--symbols EURUSD,GBPUSD] [--expires-in <seconds>] [--admin]`
+//
+// The admins can also change the timeranges through the HTTP API, everyone is one when the authentication is disabled
+
+use crate::{
+    config::AuthConfig,
+    connections::{messages::ErrorReply, subscriptions::{Filter, Request}},
+};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Claims {
+    // Who the token was given to
+    pub sub: String,
+    // Empty for every symbol
+    #[serde(default)]
+    pub symbols: Vec<String>,
+    // Expiration (unix time in seconds)
+    pub exp: Option<i64>,
+    #[serde(default)]
+    pub admin: bool,
+}
+
+// What an authenticated client is allowed to do
+#[derive(Clone, Debug)]
+pub struct Permissions {
+    pub name: String,
+    // Empty for every symbol
+    pub symbols: Vec<String>,
+    pub admin: bool,
+}
+
+impl Permissions {
+    // When the authentication is disabled
+    pub fn anonymous() -> Self {
+        Permissions {
+            name: "anonymous".to_string(),
+            symbols: Vec::new(),
+            admin: true,
+        }
+    }
+
+    pub fn allows(&self, symbol: &str) -> bool {
+        self.symbols.is_empty() || self.symbols.iter().any(|s| s == symbol)
+    }
+
+    // Fails if the request is about symbols the client can't see
+    // A subscription to every symbol is narrowed to the allowed ones
+    pub fn authorize(&self, request: &mut Request) -> Result<(), ErrorReply> {
+        let Request::Subscribe { id, filter, .. } = request else {
+            return Ok(());
+        };
+
+        self.restrict(filter).map_err(|message| ErrorReply { id: *id, message })
+    }
+
+    pub fn restrict(&self, filter: &mut Filter) -> Result<(), String> {
+        if filter.symbols.is_empty() {
+            filter.symbols = self.symbols.clone();
+        }
+
+        match filter.symbols.iter().find(|symbol| !self.allows(symbol)) {
+            Some(symbol) => Err(format!("{} isn't allowed to subscribe to {}", self.name, symbol)),
+            None => Ok(()),
+        }
+    }
+}
+
+// Checks the token of a client and returns its permissions
+pub fn authenticate(config: &AuthConfig, token: Option<&str>) -> Result<Permissions, String> {
+    if !config.enabled {
+        return Ok(Permissions::anonymous());
+    }
+
+    let token = token.ok_or("Missing token")?;
+
+    // Compared through their hashes, so the time taken doesn't tell how much of the token is right
+    let hash = Sha256::digest(token.as_bytes());
+    if let Some(known) = config.tokens.iter().find(|t| Sha256::digest(t.token.as_bytes()) == hash) {
+        return Ok(Permissions {
+            name: known.name.clone(),
+            symbols: known.symbols.clone(),
+            admin: known.admin,
+        });
+    }
+
+    let Some(secret) = &config.secret else {
+        return Err("Invalid token".to_string());
+    };
+
+    let claims = verify_token(secret, token)?;
+    if claims.exp.is_some_and(|exp| exp <= Utc::now().timestamp()) {
+        return Err(format!("The token of {} has expired", claims.sub));
+    }
+
+    Ok(Permissions {
+        name: claims.sub,
+        symbols: claims.symbols,
+        admin: claims.admin,
+    })
+}
+
+// Creates a signed token
+pub fn sign_token(secret: &str, claims: &Claims) -> Result<String, String> {
+    let claims = serde_json::to_vec(claims)
+        .map_err(|e| format!("Failed to serialize the claims: {}", e))?;
+    let claims = URL_SAFE_NO_PAD.encode(claims);
+
+    let signature = signer(secret)?
+        .chain_update(claims.as_bytes())
+        .finalize()
+        .into_bytes();
+
+    Ok(format!("{}.{}", claims, URL_SAFE_NO_PAD.encode(signature)))
+}
+
+fn verify_token(secret: &str, token: &str) -> Result<Claims, String> {
+    let (claims, signature) = token.split_once('.').ok_or("Invalid token")?;
+    let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| "Invalid token")?;
+
+    // Constant time comparison
+    signer(secret)?
+        .chain_update(claims.as_bytes())
+        .verify_slice(&signature)
+        .map_err(|_| "Invalid token")?;
+
+    let claims = URL_SAFE_NO_PAD.decode(claims).map_err(|_| "Invalid token")?;
+    serde_json::from_slice(&claims).map_err(|e| format!("Invalid token claims: {}", e))
+}
+
+fn signer(secret: &str) -> Result<Hmac<Sha256>, String> {
+    Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|e| format!("Invalid secret: {}", e))
+}
+
+// Finds the token of a request, from its headers or its query
+pub fn request_token<'a>(headers: impl Fn(&str) -> Option<&'a str>, query: Option<&'a str>) -> Option<&'a str> {
+    if let Some(token) = headers("authorization").and_then(|value| value.strip_prefix("Bearer ")) {
+        return Some(token.trim());
+    }
+
+    // The tokens are url safe, no need to decode them
+    query?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("token="))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(exp: Option<i64>) -> Claims {
+        Claims { sub: "alice".to_string(), symbols: vec!["EURUSD".to_string()], exp, admin: false }
+    }
+
+    fn config(json: &str) -> AuthConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn signed_tokens_round_trip() {
+        let token = sign_token("secret", &claims(Some(1_735_689_600))).unwrap();
+        let verified = verify_token("secret", &token).unwrap();
+
+        assert_eq!(verified.sub, "alice");
+        assert_eq!(verified.symbols, vec!["EURUSD"]);
+        assert_eq!(verified.exp, Some(1_735_689_600));
+        assert!(!verified.admin);
+    }
+
+    #[test]
+    fn tokens_signed_with_another_secret_are_refused() {
+        let token = sign_token("other secret", &claims(None)).unwrap();
+
+        assert_eq!(verify_token("secret", &token).unwrap_err(), "Invalid token");
+    }
+
+    #[test]
+    fn tampered_tokens_are_refused() {
+        let token = sign_token("secret", &claims(None)).unwrap();
+        let (_, signature) = token.split_once('.').unwrap();
+
+        // Same signature, other claims
+        let mut forged = claims(None);
+        forged.admin = true;
+        let forged = sign_token("secret", &forged).unwrap();
+        let (forged, _) = forged.split_once('.').unwrap();
+
+        assert!(verify_token("secret", &format!("{}.{}", forged, signature)).is_err());
+        assert!(verify_token("secret", "no signature").is_err());
+        assert!(verify_token("secret", &format!("{}.!!", forged)).is_err());
+    }
+
+    #[test]
+    fn authenticates_the_static_and_signed_tokens() {
+        let config = config(r#"{"enabled": true, "secret": "secret", "tokens": [{"token": "static", "name": "bob", "admin": true}]}"#);
+
+        let bob = authenticate(&config, Some("static")).unwrap();
+        assert_eq!((bob.name.as_str(), bob.admin), ("bob", true));
+        assert!(bob.allows("GBPUSD"));
+
+        let token = sign_token("secret", &claims(Some(Utc::now().timestamp() + 60))).unwrap();
+        let alice = authenticate(&config, Some(&token)).unwrap();
+        assert_eq!((alice.name.as_str(), alice.admin), ("alice", false));
+        assert!(alice.allows("EURUSD") && !alice.allows("GBPUSD"));
+
+        assert_eq!(authenticate(&config, None).unwrap_err(), "Missing token");
+        assert_eq!(authenticate(&config, Some("unknown")).unwrap_err(), "Invalid token");
+    }
+
+    #[test]
+    fn expired_tokens_are_refused() {
+        let config = config(r#"{"enabled": true, "secret": "secret"}"#);
+        let token = sign_token("secret", &claims(Some(Utc::now().timestamp() - 1))).unwrap();
+
+        assert_eq!(authenticate(&config, Some(&token)).unwrap_err(), "The token of alice has expired");
+    }
+
+    #[test]
+    fn signed_tokens_are_refused_without_secret() {
+        let config = config(r#"{"enabled": true}"#);
+        let token = sign_token("secret", &claims(None)).unwrap();
+
+        assert_eq!(authenticate(&config, Some(&token)).unwrap_err(), "Invalid token");
+    }
+
+    #[test]
+    fn subscriptions_are_restricted_to_the_allowed_symbols() {
+        let permissions = Permissions { name: "alice".to_string(), symbols: vec!["EURUSD".to_string()], admin: false };
+
+        let mut filter = Filter::default();
+        permissions.restrict(&mut filter).unwrap();
+        assert_eq!(filter.symbols, vec!["EURUSD"]);
+
+        let mut filter = Filter { symbols: vec!["GBPUSD".to_string()], ..Filter::default() };
+        assert_eq!(permissions.restrict(&mut filter).unwrap_err(), "alice isn't allowed to subscribe to GBPUSD");
+    }
+
+    #[test]
+    fn finds_the_token_of_a_request() {
+        let header = |name: &str| (name == "authorization").then_some("Bearer abc ");
+
+        assert_eq!(request_token(header, Some("token=def")), Some("abc"));
+        assert_eq!(request_token(|_| None, Some("encoding=json&token=def")), Some("def"));
+        assert_eq!(request_token(|_| None, Some("encoding=json")), None);
+        assert_eq!(request_token(|_| None, None), None);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,382 @@
+// Client of the websocket server, for the strategy processes and the integration tests
+// The messages are decoded into the entities of the engine, so the consumers don't parse anything:
+//
+// let mut client = StreamClient::connect(ClientOptions { token: Some(token), ..ClientOptions::new("ws://127.0.0.1:8080") });
+// client.subscribe(Filter { symbols: vec!["EURUSD".to_string()], ..Filter::default() });
+// while let Some(event) = client.next().await {
+//     if let Event::Candle(candle) = event { ... }
+// }
+//
+// The connection lives in a background task: when it's lost, the client reconnects (waiting longer after each failure)
+// And subscribes again to everything, with a snapshot of the current state if `snapshot` is set
+// What was sent while it was disconnected is lost, `Event::Disconnected` and `Event::Connected` tell when it happens
+
+use crate::{
+    connections::{
+        messages::{Action, Encoding, Envelope, ErrorReply, Payload, PipelineStatus, SnapshotReply},
+        subscriptions::{Filter, Request},
+        tls::load_client_tls,
+    },
+    Candle,
+    OneDStructures,
+    Session,
+    Trend,
+    TwoDStructures,
+};
+
+use futures_util::{stream::SplitSink, SinkExt, StreamExt};
+use std::{collections::BTreeMap, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    sync::mpsc,
+    task::JoinHandle,
+    time::{sleep_until, Instant},
+};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_tungstenite::{
+    client_async,
+    tungstenite::{client::IntoClientRequest, Message},
+    WebSocketStream,
+};
+
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    // ws:// or wss://
+    pub url: String,
+    // Sent in the Authorization header, see `auth`
+    pub token: Option<String>,
+    // The CA certificate of the server (PEM) for wss://, the certificates of the system are trusted without it
+    pub ca_certificate: Option<String>,
+    pub encoding: Encoding,
+    // Ask for the current state with each subscription, so a reconnected client is up to date
+    pub snapshot: bool,
+    // Doubled after each failed attempt, up to the max
+    pub reconnect_delay_ms: u64,
+    pub max_reconnect_delay_ms: u64,
+    // Events waiting to be read, nothing is read from the server while it's full
+    pub buffer: usize,
+}
+
+impl ClientOptions {
+    pub fn new(url: &str) -> Self {
+        ClientOptions {
+            url: url.to_string(),
+            token: None,
+            ca_certificate: None,
+            encoding: Encoding::Json,
+            snapshot: false,
+            reconnect_delay_ms: 500,
+            max_reconnect_delay_ms: 30_000,
+            buffer: 1024,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Event {
+    Candle(Candle),
+    Trend(Trend),
+    OneDStructure(OneDStructures),
+    TwoDStructure(TwoDStructures),
+    Session(Session),
+    Heartbeat(PipelineStatus),
+    // Announces the replay of a subscription, the id is the one returned by `subscribe`
+    Snapshot(SnapshotReply),
+    // A request was refused, a refused subscription is forgotten
+    Error(ErrorReply),
+    // A message of the server couldn't be decoded, it's skipped
+    DecodeError(String),
+    // The server dropped this many messages because they weren't read fast enough
+    Missed(u64),
+    // The connection is open, the subscriptions are sent again
+    Connected,
+    // The connection was lost or couldn't be opened, the client will retry
+    Disconnected(String),
+}
+
+enum Command {
+    Subscribe(u64, Filter),
+    Unsubscribe(u64),
+}
+
+pub struct StreamClient {
+    events: mpsc::Receiver<Event>,
+    commands: mpsc::UnboundedSender<Command>,
+    next_id: u64,
+    task: JoinHandle<()>,
+}
+
+impl StreamClient {
+    // Connects in the background, the events are read with `next`
+    pub fn connect(options: ClientOptions) -> Self {
+        let (events, receiver) = mpsc::channel(options.buffer.max(1));
+        let (commands, command_receiver) = mpsc::unbounded_channel();
+
+        StreamClient {
+            events: receiver,
+            commands,
+            next_id: 0,
+            task: tokio::spawn(run(options, command_receiver, events)),
+        }
+    }
+
+    // Returns the id of the subscription, it stays the same across the reconnections
+    pub fn subscribe(&mut self, filter: Filter) -> u64 {
+        self.next_id += 1;
+        let _ = self.commands.send(Command::Subscribe(self.next_id, filter));
+        self.next_id
+    }
+
+    pub fn unsubscribe(&self, subscription: u64) {
+        let _ = self.commands.send(Command::Unsubscribe(subscription));
+    }
+
+    pub async fn next(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+}
+
+impl Drop for StreamClient {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+// What has to survive the reconnections
+struct State {
+    snapshot: bool,
+    encoding: Encoding,
+    // The subscriptions of the client, by their id
+    filters: BTreeMap<u64, Filter>,
+    // Their id on the server, for the current connection
+    subscribed: BTreeMap<u64, u64>,
+    // Sequence number of the next message
+    next_seq: u64,
+    // Whether the last attempt got through the handshake
+    connected: bool,
+}
+
+impl State {
+    // Returns the request to send if connected
+    fn apply(&mut self, command: Command) -> Option<Request> {
+        match command {
+            Command::Subscribe(id, filter) => {
+                self.filters.insert(id, filter.clone());
+                Some(self.subscribe_request(id, filter))
+            }
+            // Not acknowledged yet, it's removed when the ack comes
+            Command::Unsubscribe(id) => {
+                self.filters.remove(&id);
+                self.subscribed.remove(&id).map(unsubscribe_request)
+            }
+        }
+    }
+
+    fn subscribe_request(&self, id: u64, filter: Filter) -> Request {
+        Request::Subscribe {
+            id: Some(id),
+            filter,
+            snapshot: self.snapshot,
+            history: 0,
+            encoding: Some(self.encoding),
+        }
+    }
+}
+
+fn unsubscribe_request(subscription: u64) -> Request {
+    Request::Unsubscribe { id: None, subscription: Some(subscription) }
+}
+
+async fn run(options: ClientOptions, mut commands: mpsc::UnboundedReceiver<Command>, events: mpsc::Sender<Event>) {
+    let mut state = State {
+        snapshot: options.snapshot,
+        encoding: options.encoding,
+        filters: BTreeMap::new(),
+        subscribed: BTreeMap::new(),
+        next_seq: 1,
+        connected: false,
+    };
+    let mut delay = options.reconnect_delay_ms;
+
+    loop {
+        let reason = match connect(&options, &mut state, &mut commands, &events).await {
+            // The client was dropped
+            Ok(()) => return,
+            Err(reason) => reason,
+        };
+
+        // Reset once the connection was up
+        if state.connected {
+            delay = options.reconnect_delay_ms;
+        }
+
+        if events.send(Event::Disconnected(reason)).await.is_err() {
+            return;
+        }
+
+        // The subscriptions can still change meanwhile
+        let deadline = Instant::now() + Duration::from_millis(delay);
+        loop {
+            tokio::select! {
+                _ = sleep_until(deadline) => break,
+                command = commands.recv() => match command {
+                    Some(command) => { state.apply(command); }
+                    None => return,
+                },
+            }
+        }
+
+        delay = (delay * 2).min(options.max_reconnect_delay_ms);
+    }
+}
+
+// Opens a connection and handles it until it's lost (Err) or the client is dropped (Ok)
+async fn connect(options: &ClientOptions, state: &mut State, commands: &mut mpsc::UnboundedReceiver<Command>, events: &mpsc::Sender<Event>) -> Result<(), String> {
+    state.connected = false;
+
+    let mut request = options.url.as_str().into_client_request()
+        .map_err(|e| format!("Invalid url {}: {}", options.url, e))?;
+
+    if let Some(token) = &options.token {
+        let header = format!("Bearer {}", token).parse()
+            .map_err(|e| format!("Invalid token: {}", e))?;
+        request.headers_mut().insert("authorization", header);
+    }
+
+    let secure = request.uri().scheme_str() == Some("wss");
+    let host = request.uri().host().unwrap_or_default().to_string();
+    let port = request.uri().port_u16().unwrap_or(if secure { 443 } else { 80 });
+
+    let stream = TcpStream::connect((host.as_str(), port)).await
+        .map_err(|e| format!("Failed to connect to {}: {}", options.url, e))?;
+
+    if secure {
+        let server_name = ServerName::try_from(host.clone())
+            .map_err(|e| format!("Invalid host {}: {}", host, e))?;
+
+        let stream = load_client_tls(options.ca_certificate.as_deref())?
+            .connect(server_name, stream).await
+            .map_err(|e| format!("TLS handshake with {} failed: {}", options.url, e))?;
+
+        let (stream, _) = client_async(request, stream).await
+            .map_err(|e| format!("WebSocket handshake with {} failed: {}", options.url, e))?;
+        handle_connection(stream, state, commands, events).await
+    } else {
+        let (stream, _) = client_async(request, stream).await
+            .map_err(|e| format!("WebSocket handshake with {} failed: {}", options.url, e))?;
+        handle_connection(stream, state, commands, events).await
+    }
+}
+
+async fn handle_connection<S>(stream: WebSocketStream<S>, state: &mut State, commands: &mut mpsc::UnboundedReceiver<Command>, events: &mpsc::Sender<Event>) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (mut write, mut read) = stream.split();
+
+    // The sequence numbers start again with each connection
+    state.subscribed.clear();
+    state.next_seq = 1;
+    state.connected = true;
+
+    if events.send(Event::Connected).await.is_err() {
+        return Ok(());
+    }
+
+    let requests: Vec<Request> = state.filters.iter()
+        .map(|(id, filter)| state.subscribe_request(*id, filter.clone()))
+        .collect();
+    for request in &requests {
+        send_request(&mut write, request).await?;
+    }
+
+    loop {
+        let message = tokio::select! {
+            command = commands.recv() => {
+                let Some(command) = command else {
+                    let _ = write.send(Message::Close(None)).await;
+                    return Ok(());
+                };
+
+                if let Some(request) = state.apply(command) {
+                    send_request(&mut write, &request).await?;
+                }
+                continue;
+            }
+            message = read.next() => message,
+        };
+
+        // The pings are answered by tungstenite
+        let envelope = match message {
+            Some(Ok(Message::Text(text))) => Envelope::decode(text.as_bytes(), Encoding::Json),
+            // Only sent once the encoding has been set by a subscription
+            Some(Ok(Message::Binary(bytes))) => Envelope::decode(&bytes, state.encoding),
+            Some(Ok(Message::Close(frame))) => {
+                let reason = frame.map(|f| f.reason.to_string()).unwrap_or_default();
+                return Err(format!("Closed by the server {}", reason).trim_end().to_string());
+            }
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(format!("WebSocket error: {}", e)),
+            None => return Err("Closed by the server".to_string()),
+        };
+
+        let envelope = match envelope {
+            Ok(envelope) => envelope,
+            Err(e) => {
+                if events.send(Event::DecodeError(e)).await.is_err() {
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+
+        if envelope.seq > state.next_seq && events.send(Event::Missed(envelope.seq - state.next_seq)).await.is_err() {
+            return Ok(());
+        }
+        state.next_seq = envelope.seq + 1;
+
+        let event = match envelope.payload {
+            Payload::Candle(candle) => Event::Candle(candle.value),
+            Payload::Trend(trend) => Event::Trend(trend.value),
+            Payload::OneDStructure(structure) => Event::OneDStructure(structure.value),
+            Payload::TwoDStructure(structure) => Event::TwoDStructure(structure.value),
+            Payload::Session(session) => Event::Session(session.value),
+            Payload::Heartbeat(status) => Event::Heartbeat(status),
+            Payload::Snapshot(snapshot) => Event::Snapshot(snapshot),
+            Payload::Ack(ack) => {
+                if let (Action::Subscribe, Some(id), Some(&subscription)) = (ack.action, ack.id, ack.subscriptions.first()) {
+                    if state.filters.contains_key(&id) {
+                        state.subscribed.insert(id, subscription);
+                    } else {
+                        // Unsubscribed before the ack
+                        send_request(&mut write, &unsubscribe_request(subscription)).await?;
+                    }
+                }
+                continue;
+            }
+            Payload::Error(error) => {
+                if let Some(id) = error.id.filter(|id| !state.subscribed.contains_key(id)) {
+                    state.filters.remove(&id);
+                }
+                Event::Error(error)
+            }
+        };
+
+        if events.send(event).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+// The requests are always JSON text messages
+async fn send_request<S>(write: &mut SplitSink<WebSocketStream<S>, Message>, request: &Request) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let request = serde_json::to_string(request)
+        .map_err(|e| format!("Failed to serialize the request: {}", e))?;
+
+    write.send(Message::Text(request.into())).await
+        .map_err(|e| format!("WebSocket error: {}", e))
+}
\ No newline at end of file
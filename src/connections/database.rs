@@ -1,123 +1,166 @@
-use crate::{Candle, OneDStructures, Session, Trend, TwoDStructures};
-
-use deadpool_postgres::{Config, ManagerConfig, Pool, RecyclingMethod};
-use once_cell::sync::OnceCell;
-use tokio_postgres::NoTls;
-
-pub static POOL: OnceCell<Pool> = OnceCell::new();
-
-// Initialize the database connection pool
-pub async fn init_pool() -> Result<(), String> {
-    // Configure the database connection
-    let mut cfg = Config::new();
-    cfg.host = Some("localhost".to_string());
-    cfg.user = Some("enzoblain".to_string());
-    cfg.dbname = Some("Paragon".to_string());
-    cfg.manager = Some(ManagerConfig { recycling_method: RecyclingMethod::Fast });
-
-    // Create the pool
-    let pool = cfg.create_pool(None, NoTls).map_err(|e| format!("Failed to create database pool: {}", e))?;
-    POOL.set(pool).map_err(|_| "Pool already initialized")?;
-    Ok(())
+use crate::{
+    connections::{
+        storage::{
+            get_storage,
+            query::{Order, Query},
+        },
+        writer::enqueue,
+    },
+    handlers::warmup::is_warming_up,
+    Candle,
+    OneDStructures,
+    Session,
+    Trend,
+    TwoDStructures,
+};
+
+use chrono::{DateTime, Utc};
+
+// A row waiting to be written in the database
+pub enum Record {
+    Candle(Candle),
+    Session(Session),
+    Trend(Trend),
+    OneDStructure(OneDStructures),
+    TwoDStructure(TwoDStructures),
 }
 
-// Facilitate access to the database client
-pub async fn get_db_client() -> Result<deadpool_postgres::Client, String> {
-    let pool = POOL.get().ok_or("Pool not initialized")?;
-    let client = pool.get().await.map_err(|e| format!("Failed to get database client: {}", e))?;
-    
-    Ok(client)
+// Rows grouped by table, written together in a single transaction
+#[derive(Default)]
+pub struct Batch {
+    pub candles: Vec<Candle>,
+    pub sessions: Vec<Session>,
+    pub trends: Vec<Trend>,
+    pub one_d_structures: Vec<OneDStructures>,
+    pub two_d_structures: Vec<TwoDStructures>,
 }
 
-pub async fn add_candle(candle: &Candle) -> Result<(), String> {
-    let client = get_db_client().await?;
-
-    let query = "INSERT INTO candles (symbol, timerange, timestamp, open, high, low, close, volume, direction) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)";
-    
-    client.query(query, &[
-        &candle.symbol,
-        &candle.timerange,
-        &candle.timestamp,
-        &candle.open,
-        &candle.high,
-        &candle.low,
-        &candle.close,
-        &candle.volume,
-        &candle.direction
-    ]).await.map_err(|e| format!("Failed to insert candle into database: {}", e))?;
+impl Batch {
+    pub fn push(&mut self, record: Record) {
+        match record {
+            Record::Candle(candle) => self.candles.push(candle),
+            Record::Session(session) => self.sessions.push(session),
+            Record::Trend(trend) => self.trends.push(trend),
+            Record::OneDStructure(structure) => self.one_d_structures.push(structure),
+            Record::TwoDStructure(structure) => self.two_d_structures.push(structure),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.candles.len()
+            + self.sessions.len()
+            + self.trends.len()
+            + self.one_d_structures.len()
+            + self.two_d_structures.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The rows of the batch, in the order they are written
+    pub fn into_records(self) -> Vec<Record> {
+        let candles = self.candles.into_iter().map(Record::Candle);
+        let sessions = self.sessions.into_iter().map(Record::Session);
+        let trends = self.trends.into_iter().map(Record::Trend);
+        let one_d_structures = self.one_d_structures.into_iter().map(Record::OneDStructure);
+        let two_d_structures = self.two_d_structures.into_iter().map(Record::TwoDStructure);
+
+        candles.chain(sessions).chain(trends).chain(one_d_structures).chain(two_d_structures).collect()
+    }
+}
 
-    Ok(())
+impl FromIterator<Record> for Batch {
+    fn from_iter<I: IntoIterator<Item = Record>>(records: I) -> Self {
+        let mut batch = Batch::default();
+        for record in records {
+            batch.push(record);
+        }
+        batch
+    }
 }
 
-pub async fn add_session(session: &Session) -> Result<(), String> {
-    let client = get_db_client().await?;
-
-    let query = "INSERT INTO sessions (symbol, label, start_time, end_time, high, low, open, close, volume) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)";
-    
-    client.query(query, &[
-        &session.symbol,
-        &session.label,
-        &session.start,
-        &session.end,
-        &session.high,
-        &session.low,
-        &session.open,
-        &session.close,
-        &session.volume
-    ]).await.map_err(|e| format!("Failed to insert session into database: {}", e))?;
+pub async fn add_candle(candle: &Candle) -> Result<(), String> {
+    write_record(Record::Candle(candle.clone())).await
+}
 
-    Ok(())
+pub async fn add_session(session: &Session) -> Result<(), String> {
+    write_record(Record::Session(session.clone())).await
 }
 
 pub async fn add_2_d_structures(structure: &TwoDStructures) -> Result<(), String> {
-    let client = get_db_client().await?;
-
-    let query = "INSERT INTO two_d_structures (symbol, structure, timerange, timestamp, high, low, direction) VALUES ($1, $2, $3, $4, $5, $6, $7)";
-    
-    client.query(query, &[
-        &structure.symbol,
-        &structure.structure,
-        &structure.timerange,
-        &structure.timestamp,
-        &structure.high,
-        &structure.low,
-        &structure.direction
-    ]).await.map_err(|e| format!("Failed to insert 2D structure into database: {}", e))?;
-
-    Ok(())
-} 
+    write_record(Record::TwoDStructure(structure.clone())).await
+}
 
 pub async fn add_1_d_structures(structure: &OneDStructures) -> Result<(), String> {
-    let client = get_db_client().await?;
-
-    let query = "INSERT INTO one_d_structures (symbol, structure, timerange, timestamp, price, direction) VALUES ($1, $2, $3, $4, $5, $6)";
-    
-    client.query(query, &[
-        &structure.symbol,
-        &structure.structure,
-        &structure.timerange,
-        &structure.timestamp,
-        &structure.price,
-        &structure.direction
-    ]).await.map_err(|e| format!("Failed to insert 1D structure into database: {}", e))?;
-
-    Ok(())
+    write_record(Record::OneDStructure(structure.clone())).await
 }
 
 pub async fn add_trends(trend: &Trend) -> Result<(), String> {
-    let client = get_db_client().await?;
+    write_record(Record::Trend(trend.clone())).await
+}
 
-    let query = "INSERT INTO trends (symbol, timerange, start_time, end_time, direction, high, low) VALUES ($1, $2, $3, $4, $5, $6, $7)";
+// Hands the row to the write-behind queue when it's running
+// Otherwise (not started or shut down) the row is written right away
+async fn write_record(record: Record) -> Result<(), String> {
+    // Everything replayed has already been stored
+    if is_warming_up() {
+        return Ok(());
+    }
 
-    client.query(query, &[
-        &trend.symbol,
-        &trend.timerange,
-        &trend.start_time,
-        &trend.end_time,
-        &trend.direction,
-        &trend.high,
-        &trend.low
-    ]).await.map_err(|e| format!("Failed to insert trend into database: {}", e))?;
+    if let Err(record) = enqueue(record).await {
+        let mut batch = Batch::default();
+        batch.push(record);
+
+        write_batch(&batch).await?;
+    }
 
     Ok(())
+}
+
+// Writes all the rows of the batch at once with the configured storage
+pub async fn write_batch(batch: &Batch) -> Result<(), String> {
+    get_storage()?.write_batch(batch).await
+}
+
+// Load the stored rows matching the query (see `Query`)
+pub async fn get_candles(query: &Query) -> Result<Vec<Candle>, String> {
+    get_storage()?.get_candles(query).await
+}
+
+pub async fn get_sessions(query: &Query) -> Result<Vec<Session>, String> {
+    get_storage()?.get_sessions(query).await
+}
+
+pub async fn get_trends(query: &Query) -> Result<Vec<Trend>, String> {
+    get_storage()?.get_trends(query).await
+}
+
+pub async fn get_1_d_structures(query: &Query) -> Result<Vec<OneDStructures>, String> {
+    get_storage()?.get_one_d_structures(query).await
+}
+
+pub async fn get_2_d_structures(query: &Query) -> Result<Vec<TwoDStructures>, String> {
+    get_storage()?.get_two_d_structures(query).await
+}
+
+// Loads the stored candles of a symbol and timerange, from `from` (included) to `to` (excluded)
+// Sorted from the oldest to the newest
+pub async fn get_candles_between(symbol: &str, timerange: &str, from: DateTime<Utc>, to: DateTime<Utc>) -> Result<Vec<Candle>, String> {
+    get_candles(&Query::new().symbol(symbol).timerange(timerange).between(from, to)).await
+}
+
+// Loads the last `count` stored candles of a symbol and timerange (e.g. to warm up the detectors)
+// Sorted from the oldest to the newest
+pub async fn get_latest_candles(symbol: &str, timerange: &str, count: usize) -> Result<Vec<Candle>, String> {
+    let query = Query::new()
+        .symbol(symbol)
+        .timerange(timerange)
+        .order(Order::Desc)
+        .limit(count);
+
+    let mut candles = get_candles(&query).await?;
+    candles.reverse();
+
+    Ok(candles)
 }
\ No newline at end of file
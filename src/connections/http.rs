@@ -0,0 +1,295 @@
+// HTTP API for the dashboards, to query the stored history instead of waiting for it to be pushed
+// Every endpoint is a GET returning JSON, the filters are the fields of `Query` in the query string:
+// /candles?symbol=EURUSD&timerange=5min&from=2024-01-02T00:00:00Z&to=2024-01-03T00:00:00Z
+// /sessions?symbol=EURUSD&kind=London
+// /trends?symbol=EURUSD&timerange=1h&order=desc&limit=10
+// /structures/one_d?symbol=EURUSD&timerange=15min&kind=Relative High
+// /structures/two_d?symbol=EURUSD&kind=Fair Value Gap&limit=100&offset=100
+// /status returns the status of the pipeline, as in the websocket heartbeats
+//
+// The admins can also change the timeranges aggregated by the pipeline:
+// GET /timeranges lists them, POST /timeranges/2h starts aggregating one and DELETE /timeranges/2h stops
+// They return the timeranges aggregated afterwards
+//
+// The rows are the ones of the websocket messages, with their uid
+// The clients authenticate like on the websocket (see `auth`), and one limited to some symbols has to query one of them
+// The errors are {"message": "..."} with the matching status code
+
+use crate::{
+    config::{get_config, HttpConfig},
+    connections::{
+        auth::{authenticate, request_token, Permissions},
+        database::{get_1_d_structures, get_2_d_structures, get_candles, get_sessions, get_trends},
+        messages::{Identified, PipelineStatus},
+        storage::{query::Query, Table, CANDLES, ONE_D_STRUCTURES, SESSIONS, TRENDS, TWO_D_STRUCTURES},
+        tls::load_tls,
+    },
+    entities::timerange::get_timeranges,
+    handlers::{
+        candle::{add_timerange, remove_timerange},
+        pipeline::{pipeline_status, PIPELINE_LOCK},
+    },
+    Candle,
+    OneDStructures,
+    Session,
+    Trend,
+    TwoDStructures,
+};
+
+use axum::{
+    extract::{self, rejection::QueryRejection, Path, Request, State},
+    http::StatusCode,
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Extension,
+    Json,
+    Router,
+};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto::Builder,
+    service::TowerToHyperService,
+};
+use serde::Serialize;
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader},
+    net::TcpListener,
+    sync::Semaphore,
+    time::timeout,
+};
+use uuid::Uuid;
+
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: String) -> Self {
+        ApiError { status, message }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(ErrorBody { message: self.message })).into_response()
+    }
+}
+
+type Rows<T> = Result<Json<Vec<Identified<T>>>, ApiError>;
+
+pub async fn create_http_server(config: &HttpConfig) -> Result<(), String> {
+    let tls = load_tls(&config.tls)?;
+
+    let listener = TcpListener::bind(&config.address)
+        .await
+        .map_err(|e| format!("Unable to bind TCP listener: {}", e))?;
+
+    let router = Router::new()
+        .route("/candles", get(candles))
+        .route("/sessions", get(sessions))
+        .route("/trends", get(trends))
+        .route("/structures/one_d", get(one_d_structures))
+        .route("/structures/two_d", get(two_d_structures))
+        .route("/status", get(status))
+        .route("/timeranges", get(timeranges))
+        .route("/timeranges/{label}", post(start_timerange).delete(stop_timerange))
+        .layer(middleware::from_fn(authenticate_request))
+        .with_state(Arc::new(config.clone()));
+
+    // Each connection holds a permit until it's closed
+    let permits = Arc::new(Semaphore::new(config.max_connections));
+    let handshake_timeout = Duration::from_millis(config.handshake_timeout_ms);
+
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                eprintln!("Failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+
+        let Ok(permit) = Arc::clone(&permits).try_acquire_owned() else {
+            eprintln!("Rejecting HTTP connection from {}: too many connections", address);
+            continue;
+        };
+
+        // Served like the websocket clients, each in its own task behind the optional TLS
+        let tls = tls.clone();
+        let router = router.clone();
+        tokio::spawn(async move {
+            let result = match tls {
+                Some(acceptor) => match timeout(handshake_timeout, acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => serve_connection(stream, router, handshake_timeout).await,
+                    Ok(Err(e)) => Err(format!("TLS handshake failed: {}", e)),
+                    Err(_) => Err("TLS handshake timed out".to_string()),
+                },
+                None => serve_connection(stream, router, handshake_timeout).await,
+            };
+
+            if let Err(e) = result {
+                eprintln!("HTTP error with {}: {}", address, e);
+            }
+            drop(permit);
+        });
+    }
+}
+
+// The headers of each request have to arrive within the timeout, so an idle client doesn't keep its connection
+async fn serve_connection<S>(stream: S, router: Router, header_timeout: Duration) -> Result<(), String>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // The version of HTTP is read from the first request before hyper applies its timeout, so we wait for it here
+    let mut stream = BufReader::new(stream);
+    match timeout(header_timeout, stream.fill_buf()).await {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => return Err("No request received in time".to_string()),
+    }
+
+    let mut builder = Builder::new(TokioExecutor::new());
+    builder.http1()
+        .timer(TokioTimer::new())
+        .header_read_timeout(header_timeout);
+
+    builder
+        .serve_connection(TokioIo::new(stream), TowerToHyperService::new(router))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+// Checks the token and gives its permissions to the endpoints
+async fn authenticate_request(mut request: Request, next: Next) -> Result<Response, ApiError> {
+    let token = request_token(
+        |name| request.headers().get(name).and_then(|value| value.to_str().ok()),
+        request.uri().query(),
+    );
+
+    let permissions = authenticate(&get_config().auth, token)
+        .map_err(|message| ApiError::new(StatusCode::UNAUTHORIZED, message))?;
+
+    request.extensions_mut().insert(permissions);
+    Ok(next.run(request).await)
+}
+
+// Validates the query of an endpoint, and caps the number of rows
+fn prepare(query: Result<extract::Query<Query>, QueryRejection>, table: &Table, permissions: &Permissions, config: &HttpConfig) -> Result<Query, ApiError> {
+    let extract::Query(mut query) = query
+        .map_err(|rejection| ApiError::new(StatusCode::BAD_REQUEST, rejection.body_text()))?;
+
+    query.check(table)
+        .map_err(|message| ApiError::new(StatusCode::BAD_REQUEST, message))?;
+
+    if !permissions.symbols.is_empty() {
+        match &query.symbol {
+            Some(symbol) if permissions.allows(symbol) => {}
+            Some(symbol) => {
+                let message = format!("{} isn't allowed to query {}", permissions.name, symbol);
+                return Err(ApiError::new(StatusCode::FORBIDDEN, message));
+            }
+            None => {
+                let message = format!("{} has to query one of its symbols", permissions.name);
+                return Err(ApiError::new(StatusCode::FORBIDDEN, message));
+            }
+        }
+    }
+
+    query.limit = Some(query.limit.unwrap_or(config.max_rows).min(config.max_rows));
+    Ok(query)
+}
+
+// The storage errors aren't the client's fault
+fn internal(message: String) -> ApiError {
+    eprintln!("HTTP query failed: {}", message);
+    ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+}
+
+fn identified<T>(rows: Vec<T>, uid: impl Fn(&T) -> Uuid) -> Json<Vec<Identified<T>>> {
+    Json(rows.into_iter().map(|value| Identified { uid: uid(&value), value }).collect())
+}
+
+async fn candles(State(config): State<Arc<HttpConfig>>, Extension(permissions): Extension<Permissions>, query: Result<extract::Query<Query>, QueryRejection>) -> Rows<Candle> {
+    let query = prepare(query, &CANDLES, &permissions, &config)?;
+    let rows = get_candles(&query).await.map_err(internal)?;
+    Ok(identified(rows, |row| row.uid()))
+}
+
+async fn sessions(State(config): State<Arc<HttpConfig>>, Extension(permissions): Extension<Permissions>, query: Result<extract::Query<Query>, QueryRejection>) -> Rows<Session> {
+    let query = prepare(query, &SESSIONS, &permissions, &config)?;
+    let rows = get_sessions(&query).await.map_err(internal)?;
+    Ok(identified(rows, |row| row.uid()))
+}
+
+async fn trends(State(config): State<Arc<HttpConfig>>, Extension(permissions): Extension<Permissions>, query: Result<extract::Query<Query>, QueryRejection>) -> Rows<Trend> {
+    let query = prepare(query, &TRENDS, &permissions, &config)?;
+    let rows = get_trends(&query).await.map_err(internal)?;
+    Ok(identified(rows, |row| row.uid()))
+}
+
+async fn one_d_structures(State(config): State<Arc<HttpConfig>>, Extension(permissions): Extension<Permissions>, query: Result<extract::Query<Query>, QueryRejection>) -> Rows<OneDStructures> {
+    let query = prepare(query, &ONE_D_STRUCTURES, &permissions, &config)?;
+    let rows = get_1_d_structures(&query).await.map_err(internal)?;
+    Ok(identified(rows, |row| row.uid()))
+}
+
+async fn two_d_structures(State(config): State<Arc<HttpConfig>>, Extension(permissions): Extension<Permissions>, query: Result<extract::Query<Query>, QueryRejection>) -> Rows<TwoDStructures> {
+    let query = prepare(query, &TWO_D_STRUCTURES, &permissions, &config)?;
+    let rows = get_2_d_structures(&query).await.map_err(internal)?;
+    Ok(identified(rows, |row| row.uid()))
+}
+
+// The symbols the client can't query are left out
+async fn status(Extension(permissions): Extension<Permissions>) -> Json<PipelineStatus> {
+    let mut status = pipeline_status();
+    status.last_candles.retain(|symbol, _| permissions.allows(symbol));
+    Json(status)
+}
+
+fn active_timeranges() -> Json<Vec<&'static str>> {
+    Json(get_timeranges().into_iter().map(|timerange| timerange.label).collect())
+}
+
+fn require_admin(permissions: &Permissions) -> Result<(), ApiError> {
+    if permissions.admin {
+        return Ok(());
+    }
+
+    let message = format!("{} isn't allowed to change the timeranges", permissions.name);
+    Err(ApiError::new(StatusCode::FORBIDDEN, message))
+}
+
+async fn timeranges() -> Json<Vec<&'static str>> {
+    active_timeranges()
+}
+
+// The pipeline is paused while the timeranges change, so no candle sees them half updated
+async fn start_timerange(Extension(permissions): Extension<Permissions>, Path(label): Path<String>) -> Result<Json<Vec<&'static str>>, ApiError> {
+    require_admin(&permissions)?;
+
+    let _guard = PIPELINE_LOCK.write().await;
+    let timerange = add_timerange(&label).await
+        .map_err(|message| ApiError::new(StatusCode::BAD_REQUEST, message))?;
+
+    eprintln!("{} started aggregating {}", permissions.name, timerange.label);
+    Ok(active_timeranges())
+}
+
+async fn stop_timerange(Extension(permissions): Extension<Permissions>, Path(label): Path<String>) -> Result<Json<Vec<&'static str>>, ApiError> {
+    require_admin(&permissions)?;
+
+    let _guard = PIPELINE_LOCK.write().await;
+    let timerange = remove_timerange(&label)
+        .map_err(|message| ApiError::new(StatusCode::BAD_REQUEST, message))?;
+
+    eprintln!("{} stopped aggregating {}", permissions.name, timerange.label);
+    Ok(active_timeranges())
+}
\ No newline at end of file
@@ -0,0 +1,314 @@
+// Messages sent to the websocket clients
+// Each one is wrapped in an envelope, the content depends on its type:
+// {"version": 1, "seq": 42, "timestamp": "2024-01-02T10:00:00.123Z", "type": "candle", "payload": {"uid": "...", "symbol": "EURUSD", ...}}
+//
+// The JSON Schemas of the messages and of the requests are printed by `paragon schema [requests]`
+// (a copy is kept in documentation/websocket, to validate the clients against)
+//
+// A client can ask for MessagePack or CBOR instead of JSON when subscribing (see `Encoding`)
+// The messages are then binary, with the same fields
+
+use crate::{
+    connections::subscriptions::Request,
+    entities::{structures::{OneDStructures, TwoDStructures}, trend::Trend},
+    Candle,
+    Session,
+};
+
+use chrono::{DateTime, Utc};
+use schemars::{schema_for, JsonSchema, Schema};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+// Bumped on every change the clients have to be updated for
+pub const SCHEMA_VERSION: u32 = 1;
+
+// How the messages of a connection are encoded
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Json,    // Text messages
+    Msgpack, // Binary messages, the structs are maps (like in JSON)
+    Cbor,    // Binary messages
+}
+
+#[derive(Clone, Deserialize, Serialize, JsonSchema)]
+pub struct Envelope {
+    pub version: u32,
+    // Incremented by one for each message of the connection
+    // A gap means messages were dropped because the client was too slow
+    pub seq: u64,
+    // When the server sent the message
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub payload: Payload,
+}
+
+#[derive(Clone, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "type", content = "payload", rename_all = "snake_case")]
+pub enum Payload {
+    // Sent on every update, `complete` is false while the candle is being built
+    Candle(Identified<Candle>),
+    Trend(Identified<Trend>),
+    OneDStructure(Identified<OneDStructures>),
+    TwoDStructure(Identified<TwoDStructures>),
+    Session(Identified<Session>),
+    // Replies to the requests
+    Ack(AckReply),
+    Error(ErrorReply),
+    // Sent after the ack of a subscription with a replay
+    Snapshot(SnapshotReply),
+    // Sent periodically to every client, even without subscription
+    Heartbeat(PipelineStatus),
+}
+
+// An entity with its stable id, so the references of the other messages can be resolved
+#[derive(Clone, Deserialize, Serialize, JsonSchema)]
+#[schemars(rename = "Identified{T}")]
+pub struct Identified<T> {
+    pub uid: Uuid,
+    #[serde(flatten)]
+    pub value: T,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Subscribe,
+    Unsubscribe,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct AckReply {
+    // The id of the request
+    pub id: Option<u64>,
+    pub action: Action,
+    // The subscriptions created or removed
+    pub subscriptions: Vec<u64>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct ErrorReply {
+    pub id: Option<u64>,
+    pub message: String,
+}
+
+// Number of messages replayed before the live ones (the history first)
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+pub struct SnapshotReply {
+    pub id: Option<u64>,
+    pub subscription: u64,
+    pub history: usize,
+    pub state: usize,
+}
+
+// Where the pipeline is, the symbols a client can't subscribe to are left out
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+pub struct PipelineStatus {
+    // While the history is replayed, nothing is sent
+    pub warming_up: bool,
+    // Timestamp of the last 1m candle processed for each symbol
+    pub last_candles: BTreeMap<String, DateTime<Utc>>,
+}
+
+impl Payload {
+    pub fn candle(candle: &Candle) -> Self {
+        Payload::Candle(Identified { uid: candle.uid(), value: candle.clone() })
+    }
+
+    pub fn trend(trend: &Trend) -> Self {
+        Payload::Trend(Identified { uid: trend.uid(), value: trend.clone() })
+    }
+
+    pub fn one_d_structure(structure: &OneDStructures) -> Self {
+        Payload::OneDStructure(Identified { uid: structure.uid(), value: structure.clone() })
+    }
+
+    pub fn two_d_structure(structure: &TwoDStructures) -> Self {
+        Payload::TwoDStructure(Identified { uid: structure.uid(), value: structure.clone() })
+    }
+
+    pub fn session(session: &Session) -> Self {
+        Payload::Session(Identified { uid: session.uid(), value: session.clone() })
+    }
+
+    pub fn error(id: Option<u64>, message: String) -> Self {
+        Payload::Error(ErrorReply { id, message })
+    }
+
+    // The stable id of the row carried by the message (the replies and heartbeats don't have one)
+    pub fn uid(&self) -> Option<Uuid> {
+        match self {
+            Payload::Candle(candle) => Some(candle.uid),
+            Payload::Trend(trend) => Some(trend.uid),
+            Payload::OneDStructure(structure) => Some(structure.uid),
+            Payload::TwoDStructure(structure) => Some(structure.uid),
+            Payload::Session(session) => Some(session.uid),
+            _ => None,
+        }
+    }
+}
+
+impl Envelope {
+    pub fn new(seq: u64, payload: Payload) -> Self {
+        Envelope {
+            version: SCHEMA_VERSION,
+            seq,
+            timestamp: Utc::now(),
+            payload,
+        }
+    }
+
+    // The JSON is valid UTF-8, so it can be sent as text
+    pub fn encode(&self, encoding: Encoding) -> Result<Vec<u8>, String> {
+        match encoding {
+            Encoding::Json => serde_json::to_vec(self)
+                .map_err(|e| format!("Failed to encode the message to JSON: {}", e)),
+            // Human readable, so the ids and dates are strings as in JSON
+            Encoding::Msgpack => {
+                let mut buffer = Vec::new();
+                let mut serializer = rmp_serde::Serializer::new(&mut buffer)
+                    .with_struct_map()
+                    .with_human_readable();
+
+                self.serialize(&mut serializer)
+                    .map_err(|e| format!("Failed to encode the message to MessagePack: {}", e))?;
+                Ok(buffer)
+            }
+            // ciborium can't be made human readable (the ids would be bytes),
+            // So the message goes through its JSON value
+            Encoding::Cbor => {
+                let value = serde_json::to_value(self)
+                    .map_err(|e| format!("Failed to encode the message to CBOR: {}", e))?;
+
+                let mut buffer = Vec::new();
+                ciborium::into_writer(&value, &mut buffer)
+                    .map_err(|e| format!("Failed to encode the message to CBOR: {}", e))?;
+                Ok(buffer)
+            }
+        }
+    }
+
+    // Reads a message sent by the server, for the clients
+    pub fn decode(bytes: &[u8], encoding: Encoding) -> Result<Self, String> {
+        match encoding {
+            Encoding::Json => serde_json::from_slice(bytes)
+                .map_err(|e| format!("Failed to decode the JSON message: {}", e)),
+            Encoding::Msgpack => rmp_serde::from_slice(bytes)
+                .map_err(|e| format!("Failed to decode the MessagePack message: {}", e)),
+            Encoding::Cbor => ciborium::from_reader(bytes)
+                .map_err(|e| format!("Failed to decode the CBOR message: {}", e)),
+        }
+    }
+}
+
+// Schema of the messages sent by the server
+pub fn message_schema() -> Schema {
+    schema_for!(Envelope)
+}
+
+// Schema of the requests sent by the clients
+pub fn request_schema() -> Schema {
+    schema_for!(Request)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENCODINGS: [Encoding; 3] = [Encoding::Json, Encoding::Msgpack, Encoding::Cbor];
+
+    fn time(minute: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_040 + minute * 60, 0).unwrap()
+    }
+
+    fn payloads() -> Vec<Payload> {
+        let candle = Candle::new("EURUSD", "5min", time(0), 1.1, 1.2, 1.05, 1.15, 42.0);
+
+        let structure = TwoDStructures {
+            symbol: "EURUSD",
+            structure: "Fair Value Gap",
+            timerange: "5min",
+            timestamp: time(5),
+            high: 1.2,
+            low: 1.1,
+            direction: "bullish",
+            invalidated_at: Some(time(10)),
+            trend: None,
+            first_candle: Some(candle.uid()),
+            last_candle: None,
+        };
+
+        let status = PipelineStatus {
+            warming_up: false,
+            last_candles: BTreeMap::from([("EURUSD".to_string(), time(15))]),
+        };
+
+        vec![
+            Payload::candle(&candle),
+            Payload::two_d_structure(&structure),
+            Payload::Ack(AckReply { id: Some(1), action: Action::Subscribe, subscriptions: vec![1, 2] }),
+            Payload::error(None, "Unknown subscription 7".to_string()),
+            Payload::Snapshot(SnapshotReply { id: Some(2), subscription: 2, history: 250, state: 12 }),
+            Payload::Heartbeat(status),
+        ]
+    }
+
+    #[test]
+    fn round_trips_in_every_encoding() {
+        for encoding in ENCODINGS {
+            for (seq, payload) in payloads().into_iter().enumerate() {
+                let envelope = Envelope::new(seq as u64, payload);
+
+                let bytes = envelope.encode(encoding).unwrap();
+                let decoded = Envelope::decode(&bytes, encoding).unwrap();
+
+                assert_eq!(
+                    serde_json::to_value(&decoded).unwrap(),
+                    serde_json::to_value(&envelope).unwrap(),
+                    "{:?}",
+                    encoding
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn the_ids_and_dates_are_strings_in_every_encoding() {
+        let envelope = Envelope::new(1, payloads().remove(0));
+        let expected = serde_json::to_value(&envelope).unwrap();
+        assert!(expected["payload"]["uid"].is_string());
+
+        let msgpack: serde_json::Value = rmp_serde::from_slice(&envelope.encode(Encoding::Msgpack).unwrap()).unwrap();
+        let cbor: serde_json::Value = ciborium::from_reader(&envelope.encode(Encoding::Cbor).unwrap()[..]).unwrap();
+
+        assert_eq!(msgpack, expected);
+        assert_eq!(cbor, expected);
+    }
+
+    #[test]
+    fn json_messages_have_the_documented_shape() {
+        let candle = Candle::new("EURUSD", "5min", time(0), 1.1, 1.2, 1.05, 1.15, 42.0);
+        let envelope = Envelope::new(42, Payload::candle(&candle));
+        let value: serde_json::Value = serde_json::from_slice(&envelope.encode(Encoding::Json).unwrap()).unwrap();
+
+        assert_eq!(value["version"], SCHEMA_VERSION);
+        assert_eq!(value["seq"], 42);
+        assert_eq!(value["type"], "candle");
+        assert_eq!(value["payload"]["symbol"], "EURUSD");
+        assert_eq!(value["payload"]["uid"], candle.uid().to_string());
+    }
+
+    #[test]
+    fn decoding_fails_with_the_wrong_encoding() {
+        let bytes = Envelope::new(1, payloads().remove(0)).encode(Encoding::Msgpack).unwrap();
+
+        let Err(error) = Envelope::decode(&bytes, Encoding::Json) else {
+            panic!("A MessagePack message was decoded as JSON");
+        };
+        assert!(error.starts_with("Failed to decode the JSON message"));
+        assert!(Envelope::decode(b"{}", Encoding::Json).is_err());
+    }
+}
\ No newline at end of file
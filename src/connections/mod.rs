@@ -1,2 +1,11 @@
+pub mod auth;
+pub mod client;
 pub mod database;
-pub mod websocket;
\ No newline at end of file
+pub mod http;
+pub mod messages;
+pub mod replay;
+pub mod storage;
+pub mod subscriptions;
+pub mod tls;
+pub mod websocket;
+pub mod writer;
\ No newline at end of file
@@ -0,0 +1,124 @@
+// What a new subscription receives before the live messages
+// The history comes from the storage, the snapshot from the state of the engine
+// The snapshot must be built while the pipeline is paused (see `PIPELINE_LOCK`),
+// The history is loaded before, the websocket holds the live messages produced meanwhile
+
+use crate::{
+    connections::{
+        database::{get_1_d_structures, get_2_d_structures, get_candles},
+        messages::Payload,
+        storage::query::{Order, Query},
+        subscriptions::{Filter, MessageKind, Topic},
+        writer::flush_writer,
+    },
+    handlers::{
+        bars::{get_bar_types, BARS},
+        candle::{candle_message, CANDLES},
+        sessions::{session_message, SESSION},
+        structures::{one_d_structure_message, two_d_structure_message, ACTIVE_ZONES},
+        trends::{trend_message, TRENDS},
+    },
+    BarKind,
+};
+
+// Loads the last `count` stored candles and structures of each symbol and timerange of the filter
+// Sorted from the oldest to the newest, the candles first
+pub async fn load_history(filter: &Filter, count: usize) -> Result<Vec<Payload>, String> {
+    // We can't list every symbol and timerange ever stored
+    if filter.symbols.is_empty() || filter.timeranges.is_empty() {
+        return Err("The history needs the symbols and timeranges of the filter".to_string());
+    }
+
+    // The rows still waiting in the writer wouldn't be found
+    flush_writer().await?;
+
+    let wants = |kind| filter.types.is_empty() || filter.types.contains(&kind);
+    let mut messages = Vec::new();
+
+    for symbol in &filter.symbols {
+        for timerange in &filter.timeranges {
+            let query = Query::new()
+                .symbol(symbol)
+                .timerange(timerange)
+                .order(Order::Desc)
+                .limit(count);
+
+            if wants(MessageKind::Candle) {
+                let candles = get_candles(&query).await?;
+                messages.extend(candles.iter().rev().map(|c| candle_message(c).1));
+            }
+
+            if wants(MessageKind::OneDStructure) {
+                let mut structures = Vec::new();
+                for query in structure_queries(&query, filter) {
+                    structures.extend(get_1_d_structures(&query).await?);
+                }
+
+                // Keep the last ones of all the kinds
+                structures.sort_by_key(|s| s.timestamp);
+                let skipped = structures.len().saturating_sub(count);
+                messages.extend(structures[skipped..].iter().map(|s| one_d_structure_message(s).1));
+            }
+
+            if wants(MessageKind::TwoDStructure) {
+                let mut structures = Vec::new();
+                for query in structure_queries(&query, filter) {
+                    structures.extend(get_2_d_structures(&query).await?);
+                }
+
+                structures.sort_by_key(|s| s.timestamp);
+                let skipped = structures.len().saturating_sub(count);
+                messages.extend(structures[skipped..].iter().map(|s| two_d_structure_message(s).1));
+            }
+        }
+    }
+
+    Ok(messages)
+}
+
+// One query per structure of the filter (the stored names have to match exactly)
+fn structure_queries(query: &Query, filter: &Filter) -> Vec<Query> {
+    if filter.structures.is_empty() {
+        return vec![query.clone()];
+    }
+
+    filter.structures.iter().map(|structure| query.clone().kind(structure)).collect()
+}
+
+// Copies the messages of the current state of the engine matching the filter:
+// The sessions, the candles and bars being built, the trends and the zones still active
+pub fn current_state(filter: &Filter) -> Vec<Payload> {
+    let mut messages = Vec::new();
+
+    let mut add = |(topic, message): (Topic, Payload)| {
+        if filter.matches(&topic) {
+            messages.push(message);
+        }
+    };
+
+    for session in SESSION.iter() {
+        add(session_message(session.value()));
+    }
+
+    for candle in CANDLES.iter().filter(|c| !c.complete) {
+        add(candle_message(candle.value()));
+    }
+
+    // The renko state only holds the reference of the next brick
+    let renko = |label: &str| get_bar_types().iter().any(|b| b.label == label && b.kind == BarKind::Renko);
+    for bar in BARS.iter().filter(|b| !b.candle.complete && !renko(b.candle.timerange)) {
+        add(candle_message(&bar.candle));
+    }
+
+    for trend in TRENDS.iter() {
+        add(trend_message(trend.value()));
+    }
+
+    for zones in ACTIVE_ZONES.iter() {
+        for zone in zones.value() {
+            add(two_d_structure_message(zone));
+        }
+    }
+
+    messages
+}
\ No newline at end of file
@@ -0,0 +1,180 @@
+// In memory backend, nothing survives a restart
+// Useful for tests and backtests that don't need to keep the results
+
+use crate::{
+    connections::{
+        database::Batch,
+        storage::{
+            latest_rows,
+            query::Query,
+            Storage,
+            Table,
+            CANDLES,
+            ONE_D_STRUCTURES,
+            SESSIONS,
+            TRENDS,
+            TWO_D_STRUCTURES,
+        },
+    },
+    Candle,
+    OneDStructures,
+    Session,
+    Trend,
+    TwoDStructures,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::{collections::BTreeMap, sync::Mutex};
+
+// Each table is keyed by its unique constraint, so inserting an existing row replaces it
+type Key3 = (&'static str, &'static str, DateTime<Utc>);
+type CandleKey = (&'static str, &'static str, DateTime<Utc>, i32);
+type Key4 = (&'static str, &'static str, &'static str, DateTime<Utc>);
+
+#[derive(Default)]
+pub struct MemoryStorage {
+    tables: Mutex<Tables>,
+}
+
+#[derive(Default)]
+struct Tables {
+    candles: BTreeMap<CandleKey, Candle>,
+    sessions: BTreeMap<Key3, Session>,
+    trends: BTreeMap<Key3, Trend>,
+    one_d_structures: BTreeMap<Key4, OneDStructures>,
+    two_d_structures: BTreeMap<Key4, TwoDStructures>,
+    checkpoint: Option<String>,
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    async fn migrate(&self) -> Result<(), String> {
+        // There is no schema
+        Ok(())
+    }
+
+    async fn write_batch(&self, batch: &Batch) -> Result<(), String> {
+        let rows = latest_rows(batch);
+        let mut tables = self.tables.lock().map_err(|_| "Memory storage poisoned")?;
+
+        for c in rows.candles {
+            tables.candles.insert((c.symbol, c.timerange, c.timestamp, c.sequence), c.clone());
+        }
+        for s in rows.sessions {
+            tables.sessions.insert((s.symbol, s.label, s.start), s.clone());
+        }
+        for t in rows.trends {
+            tables.trends.insert((t.symbol, t.timerange, t.start_time), t.clone());
+        }
+        for s in rows.one_d_structures {
+            tables.one_d_structures.insert((s.symbol, s.structure, s.timerange, s.timestamp), s.clone());
+        }
+        for s in rows.two_d_structures {
+            tables.two_d_structures.insert((s.symbol, s.structure, s.timerange, s.timestamp), s.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn get_candles(&self, query: &Query) -> Result<Vec<Candle>, String> {
+        query.check(&CANDLES)?;
+        let tables = self.tables.lock().map_err(|_| "Memory storage poisoned")?;
+
+        let rows = tables.candles.values().filter(|c| candle_matches(query, c)).cloned().collect();
+        Ok(query.page(rows, |c| (c.timestamp, c.sequence, c.uid())))
+    }
+
+    async fn get_sessions(&self, query: &Query) -> Result<Vec<Session>, String> {
+        query.check(&SESSIONS)?;
+        let tables = self.tables.lock().map_err(|_| "Memory storage poisoned")?;
+
+        let rows = tables.sessions.values().filter(|s| session_matches(query, s)).cloned().collect();
+        Ok(query.page(rows, |s| (s.start, s.uid())))
+    }
+
+    async fn get_trends(&self, query: &Query) -> Result<Vec<Trend>, String> {
+        query.check(&TRENDS)?;
+        let tables = self.tables.lock().map_err(|_| "Memory storage poisoned")?;
+
+        let rows = tables.trends.values().filter(|t| trend_matches(query, t)).cloned().collect();
+        Ok(query.page(rows, |t| (t.start_time, t.uid())))
+    }
+
+    async fn get_one_d_structures(&self, query: &Query) -> Result<Vec<OneDStructures>, String> {
+        query.check(&ONE_D_STRUCTURES)?;
+        let tables = self.tables.lock().map_err(|_| "Memory storage poisoned")?;
+
+        let rows = tables.one_d_structures.values().filter(|s| one_d_structure_matches(query, s)).cloned().collect();
+        Ok(query.page(rows, |s| (s.timestamp, s.uid())))
+    }
+
+    async fn get_two_d_structures(&self, query: &Query) -> Result<Vec<TwoDStructures>, String> {
+        query.check(&TWO_D_STRUCTURES)?;
+        let tables = self.tables.lock().map_err(|_| "Memory storage poisoned")?;
+
+        let rows = tables.two_d_structures.values().filter(|s| two_d_structure_matches(query, s)).cloned().collect();
+        Ok(query.page(rows, |s| (s.timestamp, s.uid())))
+    }
+
+    async fn delete_rows(&self, table: &Table, query: &Query, dry_run: bool) -> Result<u64, String> {
+        query.check(table)?;
+        let mut tables = self.tables.lock().map_err(|_| "Memory storage poisoned")?;
+
+        let count = match table.name {
+            "candles" => delete_matching(&mut tables.candles, |c| candle_matches(query, c), dry_run),
+            "sessions" => delete_matching(&mut tables.sessions, |s| session_matches(query, s), dry_run),
+            "trends" => delete_matching(&mut tables.trends, |t| trend_matches(query, t), dry_run),
+            "one_d_structures" => delete_matching(&mut tables.one_d_structures, |s| one_d_structure_matches(query, s), dry_run),
+            "two_d_structures" => delete_matching(&mut tables.two_d_structures, |s| two_d_structure_matches(query, s), dry_run),
+            name => return Err(format!("Unknown table {}", name)),
+        };
+
+        Ok(count)
+    }
+
+    async fn save_checkpoint(&self, state: &str) -> Result<(), String> {
+        let mut tables = self.tables.lock().map_err(|_| "Memory storage poisoned")?;
+        tables.checkpoint = Some(state.to_string());
+
+        Ok(())
+    }
+
+    async fn load_checkpoint(&self) -> Result<Option<String>, String> {
+        let tables = self.tables.lock().map_err(|_| "Memory storage poisoned")?;
+
+        Ok(tables.checkpoint.clone())
+    }
+}
+
+fn candle_matches(query: &Query, c: &Candle) -> bool {
+    query.matches(c.symbol, Some(c.timerange), None, Some(c.direction), c.timestamp, None)
+}
+
+fn session_matches(query: &Query, s: &Session) -> bool {
+    query.matches(s.symbol, None, Some(s.label), None, s.start, None)
+}
+
+fn trend_matches(query: &Query, t: &Trend) -> bool {
+    query.matches(t.symbol, Some(t.timerange), None, Some(t.direction), t.start_time, None)
+}
+
+fn one_d_structure_matches(query: &Query, s: &OneDStructures) -> bool {
+    query.matches(s.symbol, Some(s.timerange), Some(s.structure), Some(s.direction), s.timestamp, None)
+}
+
+fn two_d_structure_matches(query: &Query, s: &TwoDStructures) -> bool {
+    query.matches(s.symbol, Some(s.timerange), Some(s.structure), Some(s.direction), s.timestamp, s.invalidated_at)
+}
+
+// Removes the matching rows (or only counts them) and returns how many there are
+fn delete_matching<K: Ord, V>(rows: &mut BTreeMap<K, V>, matches: impl Fn(&V) -> bool, dry_run: bool) -> u64 {
+    let before = rows.len();
+
+    if dry_run {
+        return rows.values().filter(|row| matches(row)).count() as u64;
+    }
+
+    rows.retain(|_, row| !matches(row));
+    (before - rows.len()) as u64
+}
\ No newline at end of file
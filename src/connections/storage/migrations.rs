@@ -0,0 +1,154 @@
+// Embedded schema migrations
+// The migrations are applied in order and only once, the applied versions are stored in the database
+// They are forward-only: to change the schema, add a new migration instead of editing an existing one
+
+use deadpool_postgres::Client;
+use rusqlite::Connection;
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+pub static POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../../../database/migrations/postgres/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "idempotent_writes",
+        sql: include_str!("../../../database/migrations/postgres/0002_idempotent_writes.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "trend_extremes",
+        sql: include_str!("../../../database/migrations/postgres/0003_trend_extremes.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "checkpoints",
+        sql: include_str!("../../../database/migrations/postgres/0004_checkpoints.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "structure_invalidation",
+        sql: include_str!("../../../database/migrations/postgres/0005_structure_invalidation.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "stable_ids",
+        sql: include_str!("../../../database/migrations/postgres/0006_stable_ids.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "candle_sequence",
+        sql: include_str!("../../../database/migrations/postgres/0007_candle_sequence.sql"),
+    },
+];
+
+// The SQLite schema started with the columns added by the Postgres migrations
+pub static SQLITE_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../../../database/migrations/sqlite/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "checkpoints",
+        sql: include_str!("../../../database/migrations/sqlite/0002_checkpoints.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "structure_invalidation",
+        sql: include_str!("../../../database/migrations/sqlite/0003_structure_invalidation.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "stable_ids",
+        sql: include_str!("../../../database/migrations/sqlite/0004_stable_ids.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "candle_sequence",
+        sql: include_str!("../../../database/migrations/sqlite/0005_candle_sequence.sql"),
+    },
+];
+
+// Arbitrary key of the advisory lock taken while migrating
+// So two processes starting at the same time don't apply the same migration
+const MIGRATION_LOCK: i64 = 0x0050_4152_4147_4f4e; // "PARAGON" in ASCII
+
+// Applies the Postgres migrations that haven't been applied yet
+// The pending migrations run in a single transaction, so a failing one leaves the schema untouched
+pub async fn migrate_postgres(client: &mut Client) -> Result<(), String> {
+    client.batch_execute("CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    )").await.map_err(|e| format!("Failed to create schema_migrations table: {}", e))?;
+
+    let transaction = client.transaction().await.map_err(|e| format!("Failed to start transaction: {}", e))?;
+
+    transaction.execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+        .await
+        .map_err(|e| format!("Failed to lock the migrations: {}", e))?;
+
+    let current: i32 = transaction.query_one("SELECT COALESCE(MAX(version), 0) FROM schema_migrations", &[])
+        .await
+        .map_err(|e| format!("Failed to read the schema version: {}", e))?
+        .get(0);
+
+    for migration in pending(POSTGRES_MIGRATIONS, current)? {
+        transaction.batch_execute(migration.sql)
+            .await
+            .map_err(|e| format!("Failed to apply migration {} ({}): {}", migration.version, migration.name, e))?;
+
+        transaction.execute("INSERT INTO schema_migrations (version, name) VALUES ($1, $2)", &[&migration.version, &migration.name])
+            .await
+            .map_err(|e| format!("Failed to record migration {}: {}", migration.version, e))?;
+
+        println!("Applied migration {} ({})", migration.version, migration.name);
+    }
+
+    transaction.commit().await.map_err(|e| format!("Failed to commit the migrations: {}", e))?;
+
+    Ok(())
+}
+
+// Applies the SQLite migrations that haven't been applied yet
+// The schema version is kept in SQLite's `user_version`
+pub fn migrate_sqlite(connection: &mut Connection) -> Result<(), String> {
+    let transaction = connection.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+
+    let current: i32 = transaction.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .map_err(|e| format!("Failed to read the schema version: {}", e))?;
+
+    for migration in pending(SQLITE_MIGRATIONS, current)? {
+        transaction.execute_batch(migration.sql)
+            .map_err(|e| format!("Failed to apply migration {} ({}): {}", migration.version, migration.name, e))?;
+
+        transaction.pragma_update(None, "user_version", migration.version)
+            .map_err(|e| format!("Failed to record migration {}: {}", migration.version, e))?;
+
+        println!("Applied migration {} ({})", migration.version, migration.name);
+    }
+
+    transaction.commit().map_err(|e| format!("Failed to commit the migrations: {}", e))?;
+
+    Ok(())
+}
+
+// Returns the migrations newer than the current version
+// Fails if the database was migrated by a newer binary
+fn pending(migrations: &'static [Migration], current: i32) -> Result<impl Iterator<Item = &'static Migration>, String> {
+    let latest = migrations.last().map(|m| m.version).unwrap_or(0);
+    if current > latest {
+        return Err(format!("Database schema version {} is newer than this binary (version {})", current, latest));
+    }
+
+    Ok(migrations.iter().filter(move |m| m.version > current))
+}
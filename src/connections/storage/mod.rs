@@ -0,0 +1,275 @@
+// Persistence backends
+// The pipeline only talks to the `Storage` trait, the backend is selected in the configuration:
+// Postgres for production, SQLite (single file) or in memory for local research and tests
+
+pub mod memory;
+pub mod migrations;
+pub mod postgres;
+pub mod query;
+pub mod retention;
+pub mod sqlite;
+pub mod timescale;
+
+use crate::{
+    config::DatabaseConfig,
+    connections::{database::Batch, storage::query::Query},
+    Candle,
+    OneDStructures,
+    Session,
+    Trend,
+    TwoDStructures,
+};
+
+use async_trait::async_trait;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::{collections::HashSet, hash::Hash, sync::Arc};
+
+pub static STORAGE: OnceCell<Arc<dyn Storage>> = OnceCell::new();
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Postgres,
+    Sqlite,
+    Memory,
+}
+
+#[async_trait]
+pub trait Storage: Send + Sync {
+    // Brings the schema up to date
+    async fn migrate(&self) -> Result<(), String>;
+
+    // Inserts all the rows of the batch at once
+    // Rows that already exist are updated, so writing the same data twice is safe
+    async fn write_batch(&self, batch: &Batch) -> Result<(), String>;
+
+    // Load the rows matching the query
+    async fn get_candles(&self, query: &Query) -> Result<Vec<Candle>, String>;
+    async fn get_sessions(&self, query: &Query) -> Result<Vec<Session>, String>;
+    async fn get_trends(&self, query: &Query) -> Result<Vec<Trend>, String>;
+    async fn get_one_d_structures(&self, query: &Query) -> Result<Vec<OneDStructures>, String>;
+    async fn get_two_d_structures(&self, query: &Query) -> Result<Vec<TwoDStructures>, String>;
+
+    // Deletes the rows of the table matching the query (the pagination is ignored)
+    // When `dry_run` is set, they are only counted
+    // Returns the number of rows
+    async fn delete_rows(&self, table: &Table, query: &Query, dry_run: bool) -> Result<u64, String>;
+
+    // Replaces the stored checkpoint of the engine state (serialized as JSON)
+    async fn save_checkpoint(&self, state: &str) -> Result<(), String>;
+
+    // Loads the stored checkpoint, if any
+    async fn load_checkpoint(&self) -> Result<Option<String>, String>;
+}
+
+// Create the configured backend and store it in the global state
+pub async fn init_storage(config: &DatabaseConfig) -> Result<(), String> {
+    let storage: Arc<dyn Storage> = match config.backend {
+        Backend::Postgres => {
+            // The TimescaleDB objects are created with the migrations
+            if config.timescale.enabled && !config.migrate {
+                eprintln!("TimescaleDB setup skipped, it only runs with database.migrate");
+            }
+
+            Arc::new(postgres::PostgresStorage::new(config)?)
+        }
+        Backend::Sqlite => Arc::new(sqlite::SqliteStorage::open(&config.path)?),
+        Backend::Memory => Arc::new(memory::MemoryStorage::default()),
+    };
+
+    STORAGE.set(storage).map_err(|_| "Storage already initialized")?;
+    Ok(())
+}
+
+// Facilitate access to the storage
+pub fn get_storage() -> Result<&'static Arc<dyn Storage>, String> {
+    STORAGE.get().ok_or_else(|| "Storage not initialized".to_string())
+}
+
+// Description of a table shared by the SQL backends
+pub struct Table {
+    pub name: &'static str,
+    // The stable id of the row ("uid") is always the last column, it's computed from the row when it's written
+    pub columns: &'static [&'static str],
+    // The unique constraint identifying a row
+    // The other columns are the ones updated when the row already exists
+    pub key: &'static [&'static str],
+    // The columns the queries filter and sort on
+    pub time: &'static str,
+    // Then the rows with the same time are sorted on these, so the order (and the pages) is the same in every backend
+    pub tiebreakers: &'static [&'static str],
+    pub timerange: Option<&'static str>,
+    pub kind: Option<&'static str>,
+    pub direction: Option<&'static str>,
+    pub invalidated: Option<&'static str>,
+}
+
+pub const CANDLES: Table = Table {
+    name: "candles",
+    columns: &["symbol", "timerange", "timestamp", "open", "high", "low", "close", "volume", "direction", "complete", "sequence", "uid"],
+    key: &["symbol", "timerange", "timestamp", "sequence"],
+    time: "timestamp",
+    tiebreakers: &["sequence", "uid"],
+    timerange: Some("timerange"),
+    kind: None,
+    direction: Some("direction"),
+    invalidated: None,
+};
+
+pub const SESSIONS: Table = Table {
+    name: "sessions",
+    columns: &["symbol", "label", "start_time", "end_time", "high", "low", "open", "close", "volume", "uid"],
+    key: &["symbol", "label", "start_time"],
+    time: "start_time",
+    tiebreakers: &["uid"],
+    timerange: None,
+    kind: Some("label"),
+    direction: None,
+    invalidated: None,
+};
+
+pub const TRENDS: Table = Table {
+    name: "trends",
+    columns: &["symbol", "timerange", "start_time", "end_time", "direction", "high", "low", "high_datetime", "low_datetime", "relative_high", "relative_low", "uid"],
+    key: &["symbol", "timerange", "start_time"],
+    time: "start_time",
+    tiebreakers: &["uid"],
+    timerange: Some("timerange"),
+    kind: None,
+    direction: Some("direction"),
+    invalidated: None,
+};
+
+pub const ONE_D_STRUCTURES: Table = Table {
+    name: "one_d_structures",
+    columns: &["symbol", "structure", "timerange", "timestamp", "price", "direction", "broken_uid", "trend_uid", "uid"],
+    key: &["symbol", "structure", "timerange", "timestamp"],
+    time: "timestamp",
+    tiebreakers: &["uid"],
+    timerange: Some("timerange"),
+    kind: Some("structure"),
+    direction: Some("direction"),
+    invalidated: None,
+};
+
+pub const TWO_D_STRUCTURES: Table = Table {
+    name: "two_d_structures",
+    columns: &["symbol", "structure", "timerange", "timestamp", "high", "low", "direction", "invalidated_at", "trend_uid", "first_candle_uid", "last_candle_uid", "uid"],
+    key: &["symbol", "structure", "timerange", "timestamp"],
+    time: "timestamp",
+    tiebreakers: &["uid"],
+    timerange: Some("timerange"),
+    kind: Some("structure"),
+    direction: Some("direction"),
+    invalidated: Some("invalidated_at"),
+};
+
+impl Table {
+    // Builds a multi-row "INSERT ... ON CONFLICT DO UPDATE" query for `rows` rows
+    // The placeholder of each parameter is built from its (1-based) index
+    pub fn upsert_query(&self, rows: usize, placeholder: fn(usize) -> String) -> String {
+        let values = (0..rows)
+            .map(|row| {
+                let placeholders = (1..=self.columns.len())
+                    .map(|column| placeholder(row * self.columns.len() + column))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+
+                format!("({})", placeholders)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        let updates = self.columns
+            .iter()
+            .filter(|column| !self.key.contains(column))
+            .map(|column| format!("{} = EXCLUDED.{}", column, column))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        format!(
+            "INSERT INTO {} ({}) VALUES {} ON CONFLICT ({}) DO UPDATE SET {}",
+            self.name,
+            self.columns.join(", "),
+            values,
+            self.key.join(", "),
+            updates
+        )
+    }
+}
+
+// The rows of a batch, with only the last version of each row
+pub struct LatestRows<'a> {
+    pub candles: Vec<&'a Candle>,
+    pub sessions: Vec<&'a Session>,
+    pub trends: Vec<&'a Trend>,
+    pub one_d_structures: Vec<&'a OneDStructures>,
+    pub two_d_structures: Vec<&'a TwoDStructures>,
+}
+
+pub fn latest_rows(batch: &Batch) -> LatestRows<'_> {
+    LatestRows {
+        candles: latest(&batch.candles, |c| (c.symbol, c.timerange, c.timestamp, c.sequence)),
+        sessions: latest(&batch.sessions, |s| (s.symbol, s.label, s.start)),
+        trends: latest(&batch.trends, |t| (t.symbol, t.timerange, t.start_time)),
+        one_d_structures: latest(&batch.one_d_structures, |s| (s.symbol, s.structure, s.timerange, s.timestamp)),
+        two_d_structures: latest(&batch.two_d_structures, |s| (s.symbol, s.structure, s.timerange, s.timestamp)),
+    }
+}
+
+// Keeps only the last version of each row, in the order they were produced
+// A single INSERT ... ON CONFLICT DO UPDATE can't update the same row twice
+pub fn latest<T, K: Eq + Hash>(rows: &[T], key: impl Fn(&T) -> K) -> Vec<&T> {
+    let mut seen = HashSet::new();
+
+    let mut latest: Vec<&T> = rows
+        .iter()
+        .rev()
+        .filter(|row| seen.insert(key(row)))
+        .collect();
+
+    latest.reverse();
+    latest
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upsert_query_of_a_single_row() {
+        let query = SESSIONS.upsert_query(1, |index| format!("${}", index));
+
+        assert_eq!(
+            query,
+            "INSERT INTO sessions (symbol, label, start_time, end_time, high, low, open, close, volume, uid) \
+             VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) \
+             ON CONFLICT (symbol, label, start_time) DO UPDATE SET \
+             end_time = EXCLUDED.end_time, high = EXCLUDED.high, low = EXCLUDED.low, open = EXCLUDED.open, \
+             close = EXCLUDED.close, volume = EXCLUDED.volume, uid = EXCLUDED.uid"
+        );
+    }
+
+    #[test]
+    fn upsert_query_numbers_the_parameters_of_every_row() {
+        let query = CANDLES.upsert_query(3, |index| format!("?{}", index));
+        let columns = CANDLES.columns.len();
+
+        assert!(query.contains(&format!("(?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12), (?{}, ", columns + 1)));
+        assert!(query.contains(&format!(", ?{}) ON CONFLICT (symbol, timerange, timestamp, sequence)", columns * 3)));
+        assert!(!query.contains(&format!("?{}", columns * 3 + 1)));
+        // The key isn't updated
+        assert!(!query.contains("timestamp = EXCLUDED"));
+        assert!(query.ends_with("complete = EXCLUDED.complete, uid = EXCLUDED.uid"));
+    }
+
+    #[test]
+    fn latest_keeps_the_last_version_of_each_row_in_order() {
+        let rows = [("a", 1), ("b", 1), ("a", 2), ("c", 1), ("b", 2)];
+        let latest = latest(&rows, |row| row.0);
+
+        assert_eq!(latest, vec![&("a", 2), &("c", 1), &("b", 2)]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,378 @@
+// PostgreSQL backend, through a deadpool connection pool
+
+use crate::{
+    config::{DatabaseConfig, TimescaleConfig},
+    connections::{
+        database::Batch,
+        storage::{
+            latest_rows,
+            migrations::migrate_postgres,
+            query::{Param, Query},
+            timescale::setup_timescale,
+            Storage,
+            Table,
+            CANDLES,
+            ONE_D_STRUCTURES,
+            SESSIONS,
+            TRENDS,
+            TWO_D_STRUCTURES,
+        },
+    },
+    utils::utils::intern,
+    Candle,
+    OneDStructures,
+    Session,
+    Trend,
+    TwoDStructures,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use deadpool_postgres::{Config, GenericClient, ManagerConfig, Pool, RecyclingMethod, Transaction};
+use tokio_postgres::{types::ToSql, NoTls, Row};
+use uuid::Uuid;
+
+// The protocol counts the parameters of a query on 16 bits (signed)
+const MAX_PARAMETERS: usize = 32_767;
+
+pub struct PostgresStorage {
+    pool: Pool,
+    timescale: TimescaleConfig,
+}
+
+impl PostgresStorage {
+    // Create the connection pool (the connections are opened lazily)
+    pub fn new(config: &DatabaseConfig) -> Result<Self, String> {
+        // Configure the database connection
+        let mut cfg = Config::new();
+        cfg.host = Some(config.host.clone());
+        cfg.port = Some(config.port);
+        cfg.user = Some(config.user.clone());
+        cfg.password = config.password.clone();
+        cfg.dbname = Some(config.dbname.clone());
+        cfg.manager = Some(ManagerConfig { recycling_method: RecyclingMethod::Fast });
+
+        // Create the pool
+        let pool = cfg.create_pool(None, NoTls).map_err(|e| format!("Failed to create database pool: {}", e))?;
+
+        Ok(PostgresStorage {
+            pool,
+            timescale: config.timescale.clone(),
+        })
+    }
+
+    // Facilitate access to the database client
+    pub async fn get_client(&self) -> Result<deadpool_postgres::Client, String> {
+        self.pool.get().await.map_err(|e| format!("Failed to get database client: {}", e))
+    }
+
+    // Loads the rows of the table matching the query
+    async fn select<T>(&self, table: &Table, query: &Query, from_row: fn(&Row) -> T) -> Result<Vec<T>, String> {
+        let (sql, params) = table.select_query(query, |index| format!("${}", index))?;
+
+        let params = to_sql_params(&params);
+
+        let client = self.get_client().await?;
+        let rows = client.query(sql.as_str(), &params)
+            .await
+            .map_err(|e| format!("Failed to load {} from database: {}", table.name, e))?;
+
+        Ok(rows.iter().map(from_row).collect())
+    }
+}
+
+#[async_trait]
+impl Storage for PostgresStorage {
+    async fn migrate(&self) -> Result<(), String> {
+        let mut client = self.get_client().await?;
+
+        migrate_postgres(&mut client).await?;
+
+        // The hypertables are created once the tables exist
+        setup_timescale(&client, &self.timescale).await?;
+
+        Ok(())
+    }
+
+    async fn write_batch(&self, batch: &Batch) -> Result<(), String> {
+        let rows = latest_rows(batch);
+
+        let mut client = self.get_client().await?;
+        let transaction = client.transaction().await.map_err(|e| format!("Failed to start transaction: {}", e))?;
+
+        upsert_rows(&transaction, &CANDLES, &rows.candles, candle_params, Candle::uid).await?;
+        upsert_rows(&transaction, &SESSIONS, &rows.sessions, session_params, Session::uid).await?;
+        upsert_rows(&transaction, &TRENDS, &rows.trends, trend_params, Trend::uid).await?;
+        upsert_rows(&transaction, &ONE_D_STRUCTURES, &rows.one_d_structures, one_d_structure_params, OneDStructures::uid).await?;
+        upsert_rows(&transaction, &TWO_D_STRUCTURES, &rows.two_d_structures, two_d_structure_params, TwoDStructures::uid).await?;
+
+        transaction.commit().await.map_err(|e| format!("Failed to commit transaction: {}", e))?;
+
+        Ok(())
+    }
+
+    async fn get_candles(&self, query: &Query) -> Result<Vec<Candle>, String> {
+        self.select(&CANDLES, query, candle_from_row).await
+    }
+
+    async fn get_sessions(&self, query: &Query) -> Result<Vec<Session>, String> {
+        self.select(&SESSIONS, query, session_from_row).await
+    }
+
+    async fn get_trends(&self, query: &Query) -> Result<Vec<Trend>, String> {
+        self.select(&TRENDS, query, trend_from_row).await
+    }
+
+    async fn get_one_d_structures(&self, query: &Query) -> Result<Vec<OneDStructures>, String> {
+        self.select(&ONE_D_STRUCTURES, query, one_d_structure_from_row).await
+    }
+
+    async fn get_two_d_structures(&self, query: &Query) -> Result<Vec<TwoDStructures>, String> {
+        self.select(&TWO_D_STRUCTURES, query, two_d_structure_from_row).await
+    }
+
+    async fn delete_rows(&self, table: &Table, query: &Query, dry_run: bool) -> Result<u64, String> {
+        let (sql, params) = match dry_run {
+            true => table.count_query(query, |index| format!("${}", index))?,
+            false => table.delete_query(query, |index| format!("${}", index))?,
+        };
+        let params = to_sql_params(&params);
+
+        let client = self.get_client().await?;
+
+        if dry_run {
+            let count: i64 = client.query_one(sql.as_str(), &params)
+                .await
+                .map_err(|e| format!("Failed to count {}: {}", table.name, e))?
+                .get(0);
+
+            return Ok(count as u64);
+        }
+
+        client.execute(sql.as_str(), &params)
+            .await
+            .map_err(|e| format!("Failed to delete from {}: {}", table.name, e))
+    }
+
+    async fn save_checkpoint(&self, state: &str) -> Result<(), String> {
+        let client = self.get_client().await?;
+
+        client.execute(
+            "INSERT INTO checkpoints (id, created_at, state) VALUES (1, now(), $1::text::jsonb)
+            ON CONFLICT (id) DO UPDATE SET created_at = EXCLUDED.created_at, state = EXCLUDED.state",
+            &[&state],
+        ).await.map_err(|e| format!("Failed to save checkpoint: {}", e))?;
+
+        Ok(())
+    }
+
+    async fn load_checkpoint(&self) -> Result<Option<String>, String> {
+        let client = self.get_client().await?;
+
+        let row = client.query_opt("SELECT state::text FROM checkpoints WHERE id = 1", &[])
+            .await
+            .map_err(|e| format!("Failed to load checkpoint: {}", e))?;
+
+        Ok(row.map(|row| row.get(0)))
+    }
+}
+
+fn to_sql_params(params: &[Param]) -> Vec<&(dyn ToSql + Sync)> {
+    params
+        .iter()
+        .map(|param| match param {
+            Param::Text(text) => text as &(dyn ToSql + Sync),
+            Param::Time(time) => time,
+            Param::Integer(integer) => integer,
+        })
+        .collect()
+}
+
+// The columns are selected in the order of the table description
+fn candle_from_row(row: &Row) -> Candle {
+    Candle {
+        symbol: intern(row.get(0)),
+        timerange: intern(row.get(1)),
+        timestamp: row.get(2),
+        open: row.get(3),
+        high: row.get(4),
+        low: row.get(5),
+        close: row.get(6),
+        volume: row.get(7),
+        direction: intern(row.get(8)),
+        complete: row.get(9),
+        sequence: row.get(10),
+    }
+}
+
+fn session_from_row(row: &Row) -> Session {
+    Session {
+        symbol: intern(row.get(0)),
+        label: intern(row.get(1)),
+        start: row.get(2),
+        end: row.get(3),
+        high: row.get(4),
+        low: row.get(5),
+        open: row.get(6),
+        close: row.get(7),
+        volume: row.get(8),
+    }
+}
+
+// The extremes of the trends written before they were stored are missing,
+// The start of the trend and its high/low are used instead
+fn trend_from_row(row: &Row) -> Trend {
+    let start_time = row.get(2);
+    let high = row.get(5);
+    let low = row.get(6);
+
+    Trend {
+        symbol: intern(row.get(0)),
+        timerange: intern(row.get(1)),
+        start_time,
+        end_time: row.get(3),
+        direction: intern(row.get(4)),
+        high,
+        low,
+        high_datetime: row.get::<_, Option<DateTime<Utc>>>(7).unwrap_or(start_time),
+        low_datetime: row.get::<_, Option<DateTime<Utc>>>(8).unwrap_or(start_time),
+        relative_high: row.get::<_, Option<f64>>(9).unwrap_or(high),
+        relative_low: row.get::<_, Option<f64>>(10).unwrap_or(low),
+    }
+}
+
+fn one_d_structure_from_row(row: &Row) -> OneDStructures {
+    OneDStructures {
+        symbol: intern(row.get(0)),
+        structure: intern(row.get(1)),
+        timerange: intern(row.get(2)),
+        timestamp: row.get(3),
+        price: row.get(4),
+        direction: intern(row.get(5)),
+        broken: row.get(6),
+        trend: row.get(7),
+    }
+}
+
+fn two_d_structure_from_row(row: &Row) -> TwoDStructures {
+    TwoDStructures {
+        symbol: intern(row.get(0)),
+        structure: intern(row.get(1)),
+        timerange: intern(row.get(2)),
+        timestamp: row.get(3),
+        high: row.get(4),
+        low: row.get(5),
+        direction: intern(row.get(6)),
+        invalidated_at: row.get(7),
+        trend: row.get(8),
+        first_candle: row.get(9),
+        last_candle: row.get(10),
+    }
+}
+
+fn candle_params(candle: &Candle) -> Vec<&(dyn ToSql + Sync)> {
+    vec![
+        &candle.symbol,
+        &candle.timerange,
+        &candle.timestamp,
+        &candle.open,
+        &candle.high,
+        &candle.low,
+        &candle.close,
+        &candle.volume,
+        &candle.direction,
+        &candle.complete,
+        &candle.sequence
+    ]
+}
+
+fn session_params(session: &Session) -> Vec<&(dyn ToSql + Sync)> {
+    vec![
+        &session.symbol,
+        &session.label,
+        &session.start,
+        &session.end,
+        &session.high,
+        &session.low,
+        &session.open,
+        &session.close,
+        &session.volume
+    ]
+}
+
+fn trend_params(trend: &Trend) -> Vec<&(dyn ToSql + Sync)> {
+    vec![
+        &trend.symbol,
+        &trend.timerange,
+        &trend.start_time,
+        &trend.end_time,
+        &trend.direction,
+        &trend.high,
+        &trend.low,
+        &trend.high_datetime,
+        &trend.low_datetime,
+        &trend.relative_high,
+        &trend.relative_low
+    ]
+}
+
+fn one_d_structure_params(structure: &OneDStructures) -> Vec<&(dyn ToSql + Sync)> {
+    vec![
+        &structure.symbol,
+        &structure.structure,
+        &structure.timerange,
+        &structure.timestamp,
+        &structure.price,
+        &structure.direction,
+        &structure.broken,
+        &structure.trend
+    ]
+}
+
+fn two_d_structure_params(structure: &TwoDStructures) -> Vec<&(dyn ToSql + Sync)> {
+    vec![
+        &structure.symbol,
+        &structure.structure,
+        &structure.timerange,
+        &structure.timestamp,
+        &structure.high,
+        &structure.low,
+        &structure.direction,
+        &structure.invalidated_at,
+        &structure.trend,
+        &structure.first_candle,
+        &structure.last_candle
+    ]
+}
+
+// Inserts or updates the rows in the table with as few queries as possible
+// (split in chunks to stay under the parameters limit)
+// The uid of each row is added after its other parameters
+async fn upsert_rows<T>(
+    transaction: &Transaction<'_>,
+    table: &Table,
+    rows: &[&T],
+    params: fn(&T) -> Vec<&(dyn ToSql + Sync)>,
+    uid: fn(&T) -> Uuid,
+) -> Result<(), String> {
+    for chunk in rows.chunks(MAX_PARAMETERS / table.columns.len()) {
+        let query = table.upsert_query(chunk.len(), |index| format!("${}", index));
+
+        let uids: Vec<Uuid> = chunk.iter().map(|row| uid(row)).collect();
+        let chunk_params: Vec<&(dyn ToSql + Sync)> = chunk
+            .iter()
+            .zip(&uids)
+            .flat_map(|(row, uid)| {
+                let mut row_params = params(row);
+                row_params.push(uid);
+                row_params
+            })
+            .collect();
+
+        transaction.execute(query.as_str(), &chunk_params)
+            .await
+            .map_err(|e| format!("Failed to upsert into {}: {}", table.name, e))?;
+    }
+
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,340 @@
+// Read side of the storage
+// A `Query` describes which rows to load, every filter is optional
+
+use crate::connections::storage::Table;
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Query {
+    pub symbol: Option<String>,
+    pub timerange: Option<String>,
+    // Structure kind (e.g. "FVG", "OB") or session label
+    pub kind: Option<String>,
+    pub direction: Option<String>,
+    // Time range, `from` is included and `to` excluded
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    // Only the structures invalidated before this time
+    pub invalidated_before: Option<DateTime<Utc>>,
+    pub order: Order,
+    // Pagination
+    pub limit: Option<usize>,
+    pub offset: usize,
+}
+
+// Order of the rows, by time
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+// A parameter of a generated query, converted to the native type by each backend
+pub enum Param {
+    Text(String),
+    Time(DateTime<Utc>),
+    Integer(i64),
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Query::default()
+    }
+
+    pub fn symbol(mut self, symbol: &str) -> Self {
+        self.symbol = Some(symbol.to_string());
+        self
+    }
+
+    pub fn timerange(mut self, timerange: &str) -> Self {
+        self.timerange = Some(timerange.to_string());
+        self
+    }
+
+    pub fn kind(mut self, kind: &str) -> Self {
+        self.kind = Some(kind.to_string());
+        self
+    }
+
+    pub fn direction(mut self, direction: &str) -> Self {
+        self.direction = Some(direction.to_string());
+        self
+    }
+
+    pub fn between(mut self, from: DateTime<Utc>, to: DateTime<Utc>) -> Self {
+        self.from = Some(from);
+        self.to = Some(to);
+        self
+    }
+
+    pub fn invalidated_before(mut self, before: DateTime<Utc>) -> Self {
+        self.invalidated_before = Some(before);
+        self
+    }
+
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = order;
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    // Fails if the query filters on a column the table doesn't have
+    // (e.g. sessions don't have a timerange)
+    pub fn check(&self, table: &Table) -> Result<(), String> {
+        let filters = [
+            ("timerange", self.timerange.is_some(), table.timerange),
+            ("kind", self.kind.is_some(), table.kind),
+            ("direction", self.direction.is_some(), table.direction),
+            ("invalidation", self.invalidated_before.is_some(), table.invalidated),
+        ];
+
+        for (filter, used, column) in filters {
+            if used && column.is_none() {
+                return Err(format!("The {} can't be filtered by {}", table.name, filter));
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether a row matches the filters, used by the backends that don't speak SQL
+    // The values the table doesn't have are None (the query has been checked before)
+    pub fn matches(&self, symbol: &str, timerange: Option<&str>, kind: Option<&str>, direction: Option<&str>, time: DateTime<Utc>, invalidated_at: Option<DateTime<Utc>>) -> bool {
+        fn matches(filter: &Option<String>, value: Option<&str>) -> bool {
+            filter.as_deref().is_none_or(|filter| value == Some(filter))
+        }
+
+        matches(&self.symbol, Some(symbol))
+            && matches(&self.timerange, timerange)
+            && matches(&self.kind, kind)
+            && matches(&self.direction, direction)
+            && self.from.is_none_or(|from| time >= from)
+            && self.to.is_none_or(|to| time < to)
+            && self.invalidated_before.is_none_or(|before| invalidated_at.is_some_and(|at| at < before))
+    }
+
+    // Sorts and paginates the matching rows, used by the backends that don't speak SQL
+    // The key is the time then the tiebreakers of the table, so the rows are in the same order as with SQL
+    pub fn page<T, K: Ord>(&self, mut rows: Vec<T>, key: fn(&T) -> K) -> Vec<T> {
+        rows.sort_by_cached_key(key);
+        if self.order == Order::Desc {
+            rows.reverse();
+        }
+
+        rows.into_iter()
+            .skip(self.offset)
+            .take(self.limit.unwrap_or(usize::MAX))
+            .collect()
+    }
+}
+
+impl Table {
+    // Builds the "WHERE ..." part of the queries matching the filters (empty without filters)
+    // The placeholder of each parameter is built from its (1-based) index
+    fn where_clause(&self, query: &Query, placeholder: fn(usize) -> String) -> Result<(String, Vec<Param>), String> {
+        query.check(self)?;
+
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+
+        let mut condition = |column: &str, operator: &str, param: Param| {
+            params.push(param);
+            conditions.push(format!("{} {} {}", column, operator, placeholder(params.len())));
+        };
+
+        if let Some(symbol) = &query.symbol {
+            condition("symbol", "=", Param::Text(symbol.clone()));
+        }
+        if let (Some(timerange), Some(column)) = (&query.timerange, self.timerange) {
+            condition(column, "=", Param::Text(timerange.clone()));
+        }
+        if let (Some(kind), Some(column)) = (&query.kind, self.kind) {
+            condition(column, "=", Param::Text(kind.clone()));
+        }
+        if let (Some(direction), Some(column)) = (&query.direction, self.direction) {
+            condition(column, "=", Param::Text(direction.clone()));
+        }
+        if let Some(from) = query.from {
+            condition(self.time, ">=", Param::Time(from));
+        }
+        if let Some(to) = query.to {
+            condition(self.time, "<", Param::Time(to));
+        }
+        if let (Some(before), Some(column)) = (query.invalidated_before, self.invalidated) {
+            condition(column, "<", Param::Time(before));
+        }
+
+        if conditions.is_empty() {
+            return Ok((String::new(), params));
+        }
+
+        Ok((format!(" WHERE {}", conditions.join(" AND ")), params))
+    }
+
+    // Builds the "SELECT ... WHERE ... ORDER BY ... LIMIT ..." query matching the filters
+    pub fn select_query(&self, query: &Query, placeholder: fn(usize) -> String) -> Result<(String, Vec<Param>), String> {
+        let (conditions, mut params) = self.where_clause(query, placeholder)?;
+
+        let mut sql = format!("SELECT {} FROM {}{}", self.columns.join(", "), self.name, conditions);
+
+        let order = match query.order {
+            Order::Asc => "ASC",
+            Order::Desc => "DESC",
+        };
+        let sorting = std::iter::once(self.time)
+            .chain(self.tiebreakers.iter().copied())
+            .map(|column| format!("{} {}", column, order))
+            .collect::<Vec<_>>()
+            .join(", ");
+        sql.push_str(&format!(" ORDER BY {}", sorting));
+
+        // SQLite doesn't accept an OFFSET without a LIMIT
+        if query.limit.is_some() || query.offset > 0 {
+            // Past i64::MAX, there are no more rows anyway
+            let limit = query.limit.map(|limit| i64::try_from(limit).unwrap_or(i64::MAX)).unwrap_or(i64::MAX);
+            let offset = i64::try_from(query.offset).unwrap_or(i64::MAX);
+
+            params.push(Param::Integer(limit));
+            params.push(Param::Integer(offset));
+            sql.push_str(&format!(" LIMIT {} OFFSET {}", placeholder(params.len() - 1), placeholder(params.len())));
+        }
+
+        Ok((sql, params))
+    }
+
+    // Builds the query counting the rows matching the filters (the pagination is ignored)
+    pub fn count_query(&self, query: &Query, placeholder: fn(usize) -> String) -> Result<(String, Vec<Param>), String> {
+        let (conditions, params) = self.where_clause(query, placeholder)?;
+
+        Ok((format!("SELECT COUNT(*) FROM {}{}", self.name, conditions), params))
+    }
+
+    // Builds the query deleting the rows matching the filters (the pagination is ignored)
+    pub fn delete_query(&self, query: &Query, placeholder: fn(usize) -> String) -> Result<(String, Vec<Param>), String> {
+        let (conditions, params) = self.where_clause(query, placeholder)?;
+
+        Ok((format!("DELETE FROM {}{}", self.name, conditions), params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connections::storage::{CANDLES, SESSIONS, TWO_D_STRUCTURES};
+
+    use chrono::Duration;
+
+    fn time(minute: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(1_700_000_040 + minute * 60, 0).unwrap()
+    }
+
+    fn placeholder(index: usize) -> String {
+        format!("${}", index)
+    }
+
+    #[test]
+    fn check_refuses_the_filters_on_missing_columns() {
+        assert!(Query::new().symbol("EURUSD").kind("Asian Session").check(&SESSIONS).is_ok());
+        assert!(Query::new().invalidated_before(time(0)).check(&TWO_D_STRUCTURES).is_ok());
+
+        assert_eq!(
+            Query::new().timerange("5min").check(&SESSIONS),
+            Err("The sessions can't be filtered by timerange".to_string())
+        );
+        assert_eq!(
+            Query::new().kind("Fair Value Gap").check(&CANDLES),
+            Err("The candles can't be filtered by kind".to_string())
+        );
+        assert_eq!(
+            Query::new().invalidated_before(time(0)).check(&CANDLES),
+            Err("The candles can't be filtered by invalidation".to_string())
+        );
+    }
+
+    #[test]
+    fn matches_every_filter() {
+        let query = Query::new().symbol("EURUSD").timerange("5min").direction("bullish").between(time(0), time(10));
+
+        assert!(query.matches("EURUSD", Some("5min"), None, Some("bullish"), time(0), None));
+        assert!(query.matches("EURUSD", Some("5min"), None, Some("bullish"), time(9), None));
+        // `to` is excluded
+        assert!(!query.matches("EURUSD", Some("5min"), None, Some("bullish"), time(10), None));
+        assert!(!query.matches("EURUSD", Some("5min"), None, Some("bullish"), time(0) - Duration::seconds(1), None));
+        assert!(!query.matches("GBPUSD", Some("5min"), None, Some("bullish"), time(0), None));
+        assert!(!query.matches("EURUSD", Some("1h"), None, Some("bullish"), time(0), None));
+        assert!(!query.matches("EURUSD", Some("5min"), None, Some("bearish"), time(0), None));
+    }
+
+    #[test]
+    fn matches_the_invalidated_rows() {
+        let query = Query::new().invalidated_before(time(5));
+
+        assert!(query.matches("EURUSD", None, None, None, time(0), Some(time(4))));
+        assert!(!query.matches("EURUSD", None, None, None, time(0), Some(time(5))));
+        // Still active
+        assert!(!query.matches("EURUSD", None, None, None, time(0), None));
+    }
+
+    #[test]
+    fn page_sorts_then_paginates() {
+        let rows = vec![(3, 'a'), (1, 'b'), (2, 'a'), (1, 'a'), (4, 'a')];
+
+        assert_eq!(Query::new().page(rows.clone(), |row| *row), vec![(1, 'a'), (1, 'b'), (2, 'a'), (3, 'a'), (4, 'a')]);
+        assert_eq!(Query::new().offset(1).limit(2).page(rows.clone(), |row| *row), vec![(1, 'b'), (2, 'a')]);
+        assert_eq!(
+            Query::new().order(Order::Desc).limit(3).page(rows.clone(), |row| *row),
+            vec![(4, 'a'), (3, 'a'), (2, 'a')]
+        );
+        assert!(Query::new().offset(10).page(rows, |row| *row).is_empty());
+    }
+
+    #[test]
+    fn select_query_with_filters_and_pagination() {
+        let query = Query::new().symbol("EURUSD").timerange("5min").between(time(0), time(10)).order(Order::Desc).limit(20).offset(40);
+        let (sql, params) = CANDLES.select_query(&query, placeholder).unwrap();
+
+        assert!(sql.ends_with(
+            " FROM candles WHERE symbol = $1 AND timerange = $2 AND timestamp >= $3 AND timestamp < $4 \
+             ORDER BY timestamp DESC, sequence DESC, uid DESC LIMIT $5 OFFSET $6"
+        ));
+        assert_eq!(params.len(), 6);
+        assert!(matches!(params[4], Param::Integer(20)));
+        assert!(matches!(params[5], Param::Integer(40)));
+    }
+
+    #[test]
+    fn select_query_with_an_offset_only() {
+        let (sql, params) = SESSIONS.select_query(&Query::new().offset(usize::MAX), placeholder).unwrap();
+
+        assert!(sql.ends_with(" FROM sessions ORDER BY start_time ASC, uid ASC LIMIT $1 OFFSET $2"));
+        assert!(matches!(params[..], [Param::Integer(i64::MAX), Param::Integer(i64::MAX)]));
+    }
+
+    #[test]
+    fn count_and_delete_queries_ignore_the_pagination() {
+        let query = Query::new().symbol("EURUSD").limit(10);
+
+        let (sql, params) = CANDLES.count_query(&query, placeholder).unwrap();
+        assert_eq!(sql, "SELECT COUNT(*) FROM candles WHERE symbol = $1");
+        assert_eq!(params.len(), 1);
+
+        let (sql, _) = CANDLES.delete_query(&Query::new(), placeholder).unwrap();
+        assert_eq!(sql, "DELETE FROM candles");
+
+        assert!(CANDLES.delete_query(&Query::new().kind("Order Block"), placeholder).is_err());
+    }
+}
\ No newline at end of file
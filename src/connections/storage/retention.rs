@@ -0,0 +1,231 @@
+// Retention of the stored rows
+// Each rule deletes the rows of a table (optionally of a timerange or a structure kind) older than a number of days
+// The deleted candles can first be downsampled into a higher timerange, so the history isn't lost
+// The rules run periodically, or once from the command line (`paragon retention [--dry-run]`)
+
+use crate::{
+    config::RetentionConfig,
+    connections::{
+        database::Batch,
+        storage::{
+            get_storage,
+            query::Query,
+            Table,
+            CANDLES,
+            ONE_D_STRUCTURES,
+            SESSIONS,
+            TRENDS,
+            TWO_D_STRUCTURES,
+        },
+    },
+    entities::{candle::get_direction, timerange::find_aggregated_timerange},
+    handlers::candle::{merge_candle, open_candle},
+    Candle,
+    Timerange,
+};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TableName {
+    Candles,
+    Sessions,
+    Trends,
+    OneDStructures,
+    TwoDStructures,
+}
+
+impl TableName {
+    pub fn table(&self) -> &'static Table {
+        match self {
+            TableName::Candles => &CANDLES,
+            TableName::Sessions => &SESSIONS,
+            TableName::Trends => &TRENDS,
+            TableName::OneDStructures => &ONE_D_STRUCTURES,
+            TableName::TwoDStructures => &TWO_D_STRUCTURES,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct RetentionRule {
+    pub table: TableName,
+    // Only the rows of this timerange (all of them otherwise)
+    #[serde(default)]
+    pub timerange: Option<String>,
+    // Only the structures of this kind (e.g. "Fair Value Gap")
+    #[serde(default)]
+    pub kind: Option<String>,
+    pub keep_days: u64,
+    // Only the structures invalidated more than `keep_days` ago (2D structures only)
+    #[serde(default)]
+    pub invalidated: bool,
+    // Build the candles of this timerange from the deleted ones first (candles only)
+    #[serde(default)]
+    pub downsample: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RetentionReport {
+    pub dry_run: bool,
+    pub rules: Vec<RuleReport>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct RuleReport {
+    pub table: &'static str,
+    pub timerange: Option<String>,
+    pub kind: Option<String>,
+    // The rows before this time (or invalidated before it) are dropped
+    pub cutoff: DateTime<Utc>,
+    // Rows deleted (or that would be deleted on a dry run)
+    pub rows: u64,
+    // Candles written by the downsampling (0 on a dry run)
+    pub downsampled: u64,
+}
+
+// Applies every rule once
+// On a dry run nothing is written, the report tells what would be deleted
+pub async fn run_retention(config: &RetentionConfig, dry_run: bool) -> Result<RetentionReport, String> {
+    let mut report = RetentionReport {
+        dry_run,
+        rules: Vec::new(),
+    };
+
+    for rule in &config.rules {
+        report.rules.push(apply_rule(rule, dry_run).await?);
+    }
+
+    Ok(report)
+}
+
+// Applies the rules every interval
+pub async fn run_retention_task(config: RetentionConfig) {
+    let mut ticker = tokio::time::interval(std::time::Duration::from_millis(config.interval_ms.max(1)));
+
+    loop {
+        ticker.tick().await;
+
+        match run_retention(&config, config.dry_run).await {
+            Ok(report) => match serde_json::to_string(&report) {
+                Ok(report) => println!("Retention report: {}", report),
+                Err(e) => eprintln!("Failed to serialize the retention report: {}", e),
+            },
+            Err(e) => eprintln!("Failed to apply the retention rules: {}", e),
+        }
+    }
+}
+
+async fn apply_rule(rule: &RetentionRule, dry_run: bool) -> Result<RuleReport, String> {
+    let table = rule.table.table();
+
+    if rule.downsample.is_some() && rule.table != TableName::Candles {
+        return Err(format!("Only the candles can be downsampled, not the {}", table.name));
+    }
+
+    if rule.downsample.is_some() && rule.timerange.is_none() {
+        return Err("The timerange of the downsampled candles is missing".to_string());
+    }
+
+    let mut cutoff = Utc::now() - Duration::days(rule.keep_days as i64);
+    let mut downsampled = 0;
+
+    // The cutoff is moved back to the start of a period, so every downsampled candle is complete
+    let target = rule.downsample.as_deref().map(find_aggregated_timerange).transpose()?;
+    if let Some(target) = target {
+        cutoff = period_start(cutoff, target)?;
+    }
+
+    let mut query = Query::new();
+    query.timerange = rule.timerange.clone();
+    query.kind = rule.kind.clone();
+
+    if rule.invalidated {
+        query = query.invalidated_before(cutoff);
+    } else {
+        query.to = Some(cutoff);
+    }
+
+    if let (Some(target), false) = (target, dry_run) {
+        downsampled = downsample(&query, target).await?;
+    }
+
+    let rows = get_storage()?.delete_rows(table, &query, dry_run).await?;
+
+    Ok(RuleReport {
+        table: table.name,
+        timerange: rule.timerange.clone(),
+        kind: rule.kind.clone(),
+        cutoff,
+        rows,
+        downsampled,
+    })
+}
+
+// Aggregates the candles matching the query into the target timerange and stores them
+// They are processed window by window so a long history doesn't have to fit in memory
+async fn downsample(query: &Query, target: &'static Timerange) -> Result<u64, String> {
+    let storage = get_storage()?;
+    let Some(to) = query.to else {
+        return Ok(0);
+    };
+
+    // Start from the oldest candle to delete
+    let mut oldest = query.clone();
+    oldest.limit = Some(1);
+
+    let Some(first) = storage.get_candles(&oldest).await?.pop() else {
+        return Ok(0);
+    };
+
+    // Windows of about a day, made of whole periods of the target timerange
+    let duration = target.duration_ms as i64;
+    let window = Duration::milliseconds(duration * (86_400_000 / duration).max(1));
+
+    let mut start = period_start(first.timestamp, target)?;
+    let mut written = 0;
+
+    while start < to {
+        let end = (start + window).min(to);
+
+        let mut page = query.clone();
+        page.from = Some(start);
+        page.to = Some(end);
+
+        let mut candles: BTreeMap<(&'static str, DateTime<Utc>), Candle> = BTreeMap::new();
+        for candle in storage.get_candles(&page).await? {
+            let bucket = period_start(candle.timestamp, target)?;
+
+            candles
+                .entry((candle.symbol, bucket))
+                .and_modify(|aggregated| merge_candle(aggregated, &candle))
+                .or_insert_with(|| open_candle(&candle, target));
+        }
+
+        let mut batch = Batch::default();
+        for mut candle in candles.into_values() {
+            candle.direction = get_direction(candle.open, candle.close);
+            candle.complete = true;
+
+            batch.candles.push(candle);
+        }
+
+        written += batch.len() as u64;
+        storage.write_batch(&batch).await?;
+
+        start = end;
+    }
+
+    Ok(written)
+}
+
+fn period_start(time: DateTime<Utc>, timerange: &Timerange) -> Result<DateTime<Utc>, String> {
+    let duration = timerange.duration_ms as i64;
+
+    Utc.timestamp_millis_opt((time.timestamp_millis() / duration) * duration)
+        .single()
+        .ok_or_else(|| "Failed to compute the period start".to_string())
+}
\ No newline at end of file
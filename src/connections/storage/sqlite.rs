@@ -0,0 +1,392 @@
+// SQLite backend, everything is stored in a single file
+// Handy for local research: no server to run, the file can be copied around
+// rusqlite is blocking, so every access runs on the blocking thread pool
+
+use crate::{
+    connections::{
+        database::Batch,
+        storage::{
+            latest_rows,
+            migrations::migrate_sqlite,
+            query::{Param, Query},
+            Storage,
+            Table,
+            CANDLES,
+            ONE_D_STRUCTURES,
+            SESSIONS,
+            TRENDS,
+            TWO_D_STRUCTURES,
+        },
+    },
+    utils::utils::intern,
+    Candle,
+    OneDStructures,
+    Session,
+    Trend,
+    TwoDStructures,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use rusqlite::{params, params_from_iter, types::Value, Connection, OptionalExtension, Row, Transaction};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+// SQLite doesn't accept more parameters than this in a single query
+const MAX_PARAMETERS: usize = 32_766;
+
+pub struct SqliteStorage {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    // Open (or create) the database file
+    pub fn open(path: &str) -> Result<Self, String> {
+        let connection = Connection::open(path).map_err(|e| format!("Failed to open SQLite database {}: {}", path, e))?;
+
+        // Readers don't block the writer
+        connection.pragma_update(None, "journal_mode", "WAL")
+            .map_err(|e| format!("Failed to enable WAL: {}", e))?;
+
+        // The references between the rows are only checked when enabled
+        connection.pragma_update(None, "foreign_keys", true)
+            .map_err(|e| format!("Failed to enable the foreign keys: {}", e))?;
+
+        Ok(SqliteStorage { connection: Arc::new(Mutex::new(connection)) })
+    }
+
+    // Runs the closure with the connection on the blocking thread pool
+    async fn with_connection<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, String> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().map_err(|_| "SQLite connection poisoned")?;
+            f(&mut connection)
+        })
+        .await
+        .map_err(|e| format!("SQLite task panic: {}", e))?
+    }
+
+    // Loads the rows of the table matching the query
+    async fn select<T: Send + 'static>(&self, table: &Table, query: &Query, from_row: fn(&Row) -> rusqlite::Result<T>) -> Result<Vec<T>, String> {
+        let (sql, params) = table.select_query(query, |index| format!("?{}", index))?;
+        let name = table.name;
+
+        let params = to_values(params);
+
+        self.with_connection(move |connection| {
+            let mut statement = connection.prepare_cached(&sql)
+                .map_err(|e| format!("Failed to load {} from database: {}", name, e))?;
+
+            let rows = statement.query_map(params_from_iter(params), from_row)
+                .map_err(|e| format!("Failed to load {} from database: {}", name, e))?;
+
+            rows.collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("Failed to load {} from database: {}", name, e))
+        }).await
+    }
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn migrate(&self) -> Result<(), String> {
+        self.with_connection(migrate_sqlite).await
+    }
+
+    async fn write_batch(&self, batch: &Batch) -> Result<(), String> {
+        // The rows are converted before moving to the blocking thread
+        let rows = latest_rows(batch);
+
+        let candles: Vec<Vec<Value>> = rows.candles.into_iter().map(candle_values).collect();
+        let sessions: Vec<Vec<Value>> = rows.sessions.into_iter().map(session_values).collect();
+        let trends: Vec<Vec<Value>> = rows.trends.into_iter().map(trend_values).collect();
+        let one_d_structures: Vec<Vec<Value>> = rows.one_d_structures.into_iter().map(one_d_structure_values).collect();
+        let two_d_structures: Vec<Vec<Value>> = rows.two_d_structures.into_iter().map(two_d_structure_values).collect();
+
+        self.with_connection(move |connection| {
+            let transaction = connection.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+
+            upsert_rows(&transaction, &CANDLES, candles)?;
+            upsert_rows(&transaction, &SESSIONS, sessions)?;
+            upsert_rows(&transaction, &TRENDS, trends)?;
+            upsert_rows(&transaction, &ONE_D_STRUCTURES, one_d_structures)?;
+            upsert_rows(&transaction, &TWO_D_STRUCTURES, two_d_structures)?;
+
+            transaction.commit().map_err(|e| format!("Failed to commit transaction: {}", e))
+        }).await
+    }
+
+    async fn get_candles(&self, query: &Query) -> Result<Vec<Candle>, String> {
+        self.select(&CANDLES, query, candle_from_row).await
+    }
+
+    async fn get_sessions(&self, query: &Query) -> Result<Vec<Session>, String> {
+        self.select(&SESSIONS, query, session_from_row).await
+    }
+
+    async fn get_trends(&self, query: &Query) -> Result<Vec<Trend>, String> {
+        self.select(&TRENDS, query, trend_from_row).await
+    }
+
+    async fn get_one_d_structures(&self, query: &Query) -> Result<Vec<OneDStructures>, String> {
+        self.select(&ONE_D_STRUCTURES, query, one_d_structure_from_row).await
+    }
+
+    async fn get_two_d_structures(&self, query: &Query) -> Result<Vec<TwoDStructures>, String> {
+        self.select(&TWO_D_STRUCTURES, query, two_d_structure_from_row).await
+    }
+
+    async fn delete_rows(&self, table: &Table, query: &Query, dry_run: bool) -> Result<u64, String> {
+        let (sql, params) = match dry_run {
+            true => table.count_query(query, |index| format!("?{}", index))?,
+            false => table.delete_query(query, |index| format!("?{}", index))?,
+        };
+        let params = to_values(params);
+        let name = table.name;
+
+        self.with_connection(move |connection| {
+            if dry_run {
+                let count: i64 = connection.query_row(&sql, params_from_iter(params), |row| row.get(0))
+                    .map_err(|e| format!("Failed to count {}: {}", name, e))?;
+
+                return Ok(count as u64);
+            }
+
+            connection.execute(&sql, params_from_iter(params))
+                .map(|count| count as u64)
+                .map_err(|e| format!("Failed to delete from {}: {}", name, e))
+        }).await
+    }
+
+    async fn save_checkpoint(&self, state: &str) -> Result<(), String> {
+        let state = state.to_string();
+
+        self.with_connection(move |connection| {
+            connection.execute(
+                "INSERT INTO checkpoints (id, created_at, state) VALUES (1, ?1, ?2)
+                ON CONFLICT (id) DO UPDATE SET created_at = excluded.created_at, state = excluded.state",
+                params![Utc::now().timestamp_micros(), state],
+            ).map_err(|e| format!("Failed to save checkpoint: {}", e))?;
+
+            Ok(())
+        }).await
+    }
+
+    async fn load_checkpoint(&self) -> Result<Option<String>, String> {
+        self.with_connection(|connection| {
+            connection.query_row("SELECT state FROM checkpoints WHERE id = 1", [], |row| row.get(0))
+                .optional()
+                .map_err(|e| format!("Failed to load checkpoint: {}", e))
+        }).await
+    }
+}
+
+fn to_values(params: Vec<Param>) -> Vec<Value> {
+    params
+        .into_iter()
+        .map(|param| match param {
+            Param::Text(text) => Value::Text(text),
+            Param::Time(time) => micros(&time),
+            Param::Integer(integer) => Value::Integer(integer),
+        })
+        .collect()
+}
+
+// The columns are selected in the order of the table description
+fn candle_from_row(row: &Row) -> rusqlite::Result<Candle> {
+    Ok(Candle {
+        symbol: intern(&row.get::<_, String>(0)?),
+        timerange: intern(&row.get::<_, String>(1)?),
+        timestamp: from_micros(row.get(2)?),
+        open: row.get(3)?,
+        high: row.get(4)?,
+        low: row.get(5)?,
+        close: row.get(6)?,
+        volume: row.get(7)?,
+        direction: intern(&row.get::<_, String>(8)?),
+        complete: row.get(9)?,
+        sequence: row.get(10)?,
+    })
+}
+
+fn session_from_row(row: &Row) -> rusqlite::Result<Session> {
+    Ok(Session {
+        symbol: intern(&row.get::<_, String>(0)?),
+        label: intern(&row.get::<_, String>(1)?),
+        start: from_micros(row.get(2)?),
+        end: from_micros(row.get(3)?),
+        high: row.get(4)?,
+        low: row.get(5)?,
+        open: row.get(6)?,
+        close: row.get(7)?,
+        volume: row.get(8)?,
+    })
+}
+
+fn trend_from_row(row: &Row) -> rusqlite::Result<Trend> {
+    let start_time = from_micros(row.get(2)?);
+    let high = row.get(5)?;
+    let low = row.get(6)?;
+
+    Ok(Trend {
+        symbol: intern(&row.get::<_, String>(0)?),
+        timerange: intern(&row.get::<_, String>(1)?),
+        start_time,
+        end_time: from_micros(row.get(3)?),
+        direction: intern(&row.get::<_, String>(4)?),
+        high,
+        low,
+        high_datetime: row.get::<_, Option<i64>>(7)?.map(from_micros).unwrap_or(start_time),
+        low_datetime: row.get::<_, Option<i64>>(8)?.map(from_micros).unwrap_or(start_time),
+        relative_high: row.get::<_, Option<f64>>(9)?.unwrap_or(high),
+        relative_low: row.get::<_, Option<f64>>(10)?.unwrap_or(low),
+    })
+}
+
+fn one_d_structure_from_row(row: &Row) -> rusqlite::Result<OneDStructures> {
+    Ok(OneDStructures {
+        symbol: intern(&row.get::<_, String>(0)?),
+        structure: intern(&row.get::<_, String>(1)?),
+        timerange: intern(&row.get::<_, String>(2)?),
+        timestamp: from_micros(row.get(3)?),
+        price: row.get(4)?,
+        direction: intern(&row.get::<_, String>(5)?),
+        broken: row.get::<_, Option<String>>(6)?.and_then(|uid| uid.parse().ok()),
+        trend: row.get::<_, Option<String>>(7)?.and_then(|uid| uid.parse().ok()),
+    })
+}
+
+fn two_d_structure_from_row(row: &Row) -> rusqlite::Result<TwoDStructures> {
+    Ok(TwoDStructures {
+        symbol: intern(&row.get::<_, String>(0)?),
+        structure: intern(&row.get::<_, String>(1)?),
+        timerange: intern(&row.get::<_, String>(2)?),
+        timestamp: from_micros(row.get(3)?),
+        high: row.get(4)?,
+        low: row.get(5)?,
+        direction: intern(&row.get::<_, String>(6)?),
+        invalidated_at: row.get::<_, Option<i64>>(7)?.map(from_micros),
+        trend: row.get::<_, Option<String>>(8)?.and_then(|uid| uid.parse().ok()),
+        first_candle: row.get::<_, Option<String>>(9)?.and_then(|uid| uid.parse().ok()),
+        last_candle: row.get::<_, Option<String>>(10)?.and_then(|uid| uid.parse().ok()),
+    })
+}
+
+// Timestamps are stored as microseconds since the Unix epoch
+fn micros(datetime: &DateTime<Utc>) -> Value {
+    Value::Integer(datetime.timestamp_micros())
+}
+
+fn from_micros(micros: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp_micros(micros).unwrap_or_default()
+}
+
+fn text(value: &str) -> Value {
+    Value::Text(value.to_string())
+}
+
+// The uids are stored as text
+fn uid(uid: Uuid) -> Value {
+    Value::Text(uid.to_string())
+}
+
+fn optional_uid(value: Option<Uuid>) -> Value {
+    value.map(uid).unwrap_or(Value::Null)
+}
+
+fn candle_values(candle: &Candle) -> Vec<Value> {
+    vec![
+        text(candle.symbol),
+        text(candle.timerange),
+        micros(&candle.timestamp),
+        Value::Real(candle.open),
+        Value::Real(candle.high),
+        Value::Real(candle.low),
+        Value::Real(candle.close),
+        Value::Real(candle.volume),
+        text(candle.direction),
+        Value::Integer(candle.complete as i64),
+        Value::Integer(candle.sequence.into()),
+        uid(candle.uid())
+    ]
+}
+
+fn session_values(session: &Session) -> Vec<Value> {
+    vec![
+        text(session.symbol),
+        text(session.label),
+        micros(&session.start),
+        micros(&session.end),
+        Value::Real(session.high),
+        Value::Real(session.low),
+        Value::Real(session.open),
+        Value::Real(session.close),
+        Value::Real(session.volume),
+        uid(session.uid())
+    ]
+}
+
+fn trend_values(trend: &Trend) -> Vec<Value> {
+    vec![
+        text(trend.symbol),
+        text(trend.timerange),
+        micros(&trend.start_time),
+        micros(&trend.end_time),
+        text(trend.direction),
+        Value::Real(trend.high),
+        Value::Real(trend.low),
+        micros(&trend.high_datetime),
+        micros(&trend.low_datetime),
+        Value::Real(trend.relative_high),
+        Value::Real(trend.relative_low),
+        uid(trend.uid())
+    ]
+}
+
+fn one_d_structure_values(structure: &OneDStructures) -> Vec<Value> {
+    vec![
+        text(structure.symbol),
+        text(structure.structure),
+        text(structure.timerange),
+        micros(&structure.timestamp),
+        Value::Real(structure.price),
+        text(structure.direction),
+        optional_uid(structure.broken),
+        optional_uid(structure.trend),
+        uid(structure.uid())
+    ]
+}
+
+fn two_d_structure_values(structure: &TwoDStructures) -> Vec<Value> {
+    vec![
+        text(structure.symbol),
+        text(structure.structure),
+        text(structure.timerange),
+        micros(&structure.timestamp),
+        Value::Real(structure.high),
+        Value::Real(structure.low),
+        text(structure.direction),
+        structure.invalidated_at.as_ref().map(micros).unwrap_or(Value::Null),
+        optional_uid(structure.trend),
+        optional_uid(structure.first_candle),
+        optional_uid(structure.last_candle),
+        uid(structure.uid())
+    ]
+}
+
+// Inserts or updates the rows in the table with as few queries as possible
+// (split in chunks to stay under the parameters limit)
+fn upsert_rows(transaction: &Transaction<'_>, table: &Table, rows: Vec<Vec<Value>>) -> Result<(), String> {
+    for chunk in rows.chunks(MAX_PARAMETERS / table.columns.len()) {
+        let query = table.upsert_query(chunk.len(), |index| format!("?{}", index));
+
+        transaction.execute(&query, params_from_iter(chunk.iter().flatten()))
+            .map_err(|e| format!("Failed to upsert into {}: {}", table.name, e))?;
+    }
+
+    Ok(())
+}
\ No newline at end of file
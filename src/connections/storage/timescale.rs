@@ -0,0 +1,121 @@
+// Optional TimescaleDB support
+// When the extension is available, the candles become a hypertable (partitioned by time),
+// Old chunks are compressed and the higher timeranges are kept up to date as continuous aggregates
+// Otherwise the plain tables created by the migrations are used as they are
+
+use crate::{
+    config::TimescaleConfig,
+    entities::timerange::{find_aggregated_timerange, BASE_TIMERANGE},
+};
+
+use deadpool_postgres::Client;
+
+// Creates the TimescaleDB objects if the extension can be used
+// Returns whether TimescaleDB is enabled
+// Everything is idempotent, so this runs on every startup with the migrations (database.migrate)
+pub async fn setup_timescale(client: &Client, config: &TimescaleConfig) -> Result<bool, String> {
+    if !config.enabled {
+        return Ok(false);
+    }
+
+    let available = client.query_opt("SELECT 1 FROM pg_available_extensions WHERE name = 'timescaledb'", &[])
+        .await
+        .map_err(|e| format!("Failed to look for TimescaleDB: {}", e))?
+        .is_some();
+
+    if !available {
+        println!("TimescaleDB isn't available, using plain tables");
+        return Ok(false);
+    }
+
+    // The extension may be installed but not loaded (it has to be in shared_preload_libraries)
+    // or the user may not be allowed to create it, we fall back to plain tables in both cases
+    if let Err(e) = client.batch_execute("CREATE EXTENSION IF NOT EXISTS timescaledb").await {
+        eprintln!("Failed to enable TimescaleDB, using plain tables: {}", e);
+        return Ok(false);
+    }
+
+    // The existing candles are moved into the chunks
+    client.execute("SELECT create_hypertable('candles', 'timestamp', chunk_time_interval => $1::text::interval, if_not_exists => TRUE, migrate_data => TRUE)", &[&config.chunk_interval])
+        .await
+        .map_err(|e| format!("Failed to create the candles hypertable: {}", e))?;
+
+    if let Some(compress_after) = &config.compress_after {
+        // The settings can't be changed once chunks have been compressed
+        let compressed: bool = client.query_one("SELECT compression_enabled FROM timescaledb_information.hypertables WHERE hypertable_name = 'candles'", &[])
+            .await
+            .map_err(|e| format!("Failed to read the compression settings: {}", e))?
+            .get(0);
+
+        if !compressed {
+            client.batch_execute("ALTER TABLE candles SET (
+                timescaledb.compress,
+                timescaledb.compress_segmentby = 'symbol, timerange',
+                timescaledb.compress_orderby = 'timestamp DESC'
+            )").await.map_err(|e| format!("Failed to enable the compression of the candles: {}", e))?;
+        }
+
+        client.execute("SELECT add_compression_policy('candles', $1::text::interval, if_not_exists => TRUE)", &[compress_after])
+            .await
+            .map_err(|e| format!("Failed to add the compression policy: {}", e))?;
+    }
+
+    for label in &config.aggregates {
+        create_aggregate(client, label, config).await?;
+    }
+
+    println!("TimescaleDB enabled");
+    Ok(true)
+}
+
+// Creates the continuous aggregate building the candles of a timerange from the 1min candles
+// The view is named after the timerange (e.g. "candles_4h")
+async fn create_aggregate(client: &Client, label: &str, config: &TimescaleConfig) -> Result<(), String> {
+    let timerange = find_aggregated_timerange(label)?;
+    let minutes = timerange.duration_ms / 60_000;
+
+    // The label is only made of letters and digits once it's been parsed, so it's safe in the view name
+    let view = format!("candles_{}", timerange.label);
+
+    // The buckets start on the epoch like the aggregated candles (time_bucket starts them on Monday 2000-01-03 otherwise,
+    // Which shifts the weeks and the periods that don't divide a day)
+    let bucket = format!("time_bucket(INTERVAL '{minutes} minutes', timestamp, TIMESTAMPTZ '1970-01-01 00:00:00+00')");
+
+    let query = format!(
+        "CREATE MATERIALIZED VIEW IF NOT EXISTS {view}
+        WITH (timescaledb.continuous) AS
+        SELECT
+            symbol,
+            {bucket} AS timestamp,
+            first(open, timestamp) AS open,
+            max(high) AS high,
+            min(low) AS low,
+            last(close, timestamp) AS close,
+            sum(volume) AS volume
+        FROM candles
+        WHERE timerange = '{BASE_TIMERANGE}'
+        GROUP BY symbol, {bucket}
+        WITH NO DATA"
+    );
+
+    client.batch_execute(&query)
+        .await
+        .map_err(|e| format!("Failed to create the continuous aggregate {}: {}", view, e))?;
+
+    // Refresh the buckets that may still change (the last `refresh_window` periods),
+    // Every period, leaving the current (unfinished) bucket out
+    let start_offset = format!("{} minutes", minutes * config.refresh_window.max(2) as u128);
+    let end_offset = format!("{} minutes", minutes);
+    let schedule_interval = format!("{} minutes", minutes);
+
+    client.execute(
+        "SELECT add_continuous_aggregate_policy($1::text::regclass,
+            start_offset => $2::text::interval,
+            end_offset => $3::text::interval,
+            schedule_interval => $4::text::interval,
+            if_not_exists => TRUE)",
+        &[&view, &start_offset, &end_offset, &schedule_interval],
+    ).await.map_err(|e| format!("Failed to add the refresh policy of {}: {}", view, e))?;
+
+    Ok(())
+}
\ No newline at end of file
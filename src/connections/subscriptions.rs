@@ -0,0 +1,242 @@
+// Subscriptions of the websocket clients
+// A client only receives the messages matching one of its subscriptions, so a new client receives nothing
+//
+// Requests (JSON text messages), the id is optional and sent back in the reply:
+// {"action": "subscribe", "id": 1, "filter": {"symbols": ["EURUSD"], "timeranges": ["5min"], "types": ["candle", "two_d_structure"], "structures": ["Fair Value Gap"]}}
+// {"action": "subscribe", "id": 2, "filter": {...}, "snapshot": true, "history": 100, "encoding": "msgpack"}
+// {"action": "unsubscribe", "id": 3, "subscription": 1}   (without "subscription", every subscription is removed)
+//
+// Replies (in an envelope, see `messages`):
+// {"type": "ack", "payload": {"id": 1, "action": "subscribe", "subscriptions": [1]}}
+// {"type": "error", "payload": {"id": 3, "message": "Unknown subscription 7"}}
+//
+// With "history", the last stored candles and structures of each symbol and timerange of the filter are sent first
+// With "snapshot", the current state of the engine (sessions, open candles, trends and active zones) comes next
+// Both are announced by {"type": "snapshot", "payload": {"id": 2, "subscription": 2, "history": 250, "state": 12}} right after the ack,
+// Followed by the live messages, without gap or duplicate
+//
+// When the authentication is enabled, the symbols are limited to the ones of the client's token (see `auth`)
+// A filter without symbols gets those, a symbol outside of them is refused with an error
+//
+// The encoding applies to the whole connection, from the ack of the subscription that sets it
+// The requests are always JSON text messages
+
+use crate::connections::messages::{Action, AckReply, Encoding, ErrorReply, Payload};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+// The kinds of messages sent to the clients
+#[derive(Clone, Copy, Debug, Deserialize, Serialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageKind {
+    Candle,
+    Trend,
+    OneDStructure,
+    TwoDStructure,
+    Session,
+}
+
+// What a message is about, to find the clients subscribed to it
+#[derive(Clone, Copy)]
+pub struct Topic {
+    pub kind: MessageKind,
+    pub symbol: &'static str,
+    // None for the sessions, which are the same in every timerange
+    pub timerange: Option<&'static str>,
+    // The kind of structure (e.g. "Order Block"), only for the structures
+    pub structure: Option<&'static str>,
+}
+
+// The messages matching a subscription
+// An empty list matches everything
+#[derive(Clone, Debug, Default, Deserialize, Serialize, JsonSchema)]
+#[serde(default, deny_unknown_fields)]
+pub struct Filter {
+    pub symbols: Vec<String>,
+    pub timeranges: Vec<String>,
+    pub types: Vec<MessageKind>,
+    // Only applies to the structures, the candles and trends go through
+    pub structures: Vec<String>,
+}
+
+impl Filter {
+    pub fn matches(&self, topic: &Topic) -> bool {
+        (self.symbols.is_empty() || self.symbols.iter().any(|symbol| symbol == topic.symbol))
+            && match topic.timerange {
+                Some(timerange) => self.timeranges.is_empty() || self.timeranges.iter().any(|t| t == timerange),
+                None => true,
+            }
+            && (self.types.is_empty() || self.types.contains(&topic.kind))
+            && match topic.structure {
+                Some(structure) => self.structures.is_empty() || self.structures.iter().any(|s| s.eq_ignore_ascii_case(structure)),
+                None => true,
+            }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
+#[serde(tag = "action", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Request {
+    Subscribe {
+        id: Option<u64>,
+        #[serde(default)]
+        filter: Filter,
+        // Send the current state of the engine before the live messages
+        #[serde(default)]
+        snapshot: bool,
+        // Number of stored candles and structures to send (per symbol, timerange and type), up to websocket.max_history
+        #[serde(default)]
+        history: usize,
+        // Changes the encoding of the messages sent to the connection
+        encoding: Option<Encoding>,
+    },
+    Unsubscribe {
+        id: Option<u64>,
+        subscription: Option<u64>,
+    },
+}
+
+// The subscriptions of a client, by id
+#[derive(Default)]
+pub struct Subscriptions {
+    next_id: u64,
+    filters: BTreeMap<u64, Filter>,
+    pub encoding: Encoding,
+}
+
+impl Subscriptions {
+    // Whether the client wants the message
+    pub fn wants(&self, topic: &Topic) -> bool {
+        self.filters.values().any(|filter| filter.matches(topic))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    // Adds a subscription and returns its id
+    pub fn subscribe(&mut self, filter: Filter) -> u64 {
+        self.next_id += 1;
+        self.filters.insert(self.next_id, filter);
+
+        self.next_id
+    }
+
+    // Applies a request and builds the reply
+    // The replay of a subscription is up to the caller
+    pub fn handle(&mut self, request: Request) -> Payload {
+        match request {
+            Request::Subscribe { id, filter, encoding, .. } => {
+                let subscription = self.subscribe(filter);
+                if let Some(encoding) = encoding {
+                    self.encoding = encoding;
+                }
+
+                Payload::Ack(AckReply { id, action: Action::Subscribe, subscriptions: vec![subscription] })
+            }
+            Request::Unsubscribe { id, subscription: Some(subscription) } => match self.filters.remove(&subscription) {
+                Some(_) => Payload::Ack(AckReply { id, action: Action::Unsubscribe, subscriptions: vec![subscription] }),
+                None => Payload::error(id, format!("Unknown subscription {}", subscription)),
+            },
+            Request::Unsubscribe { id, subscription: None } => {
+                let removed = std::mem::take(&mut self.filters).into_keys().collect();
+
+                Payload::Ack(AckReply { id, action: Action::Unsubscribe, subscriptions: removed })
+            }
+        }
+    }
+}
+
+// Parses the text of a message, the error is the reply to send back
+pub fn parse_request(text: &str) -> Result<Request, ErrorReply> {
+    serde_json::from_str::<Request>(text).map_err(|e| ErrorReply {
+        id: request_id(text),
+        message: format!("Invalid request: {}", e),
+    })
+}
+
+// The id of a request that couldn't be parsed, so the error can still be matched with it
+fn request_id(text: &str) -> Option<u64> {
+    serde_json::from_str::<serde_json::Value>(text).ok()?.get("id")?.as_u64()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(json: &str) -> Filter {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn topic(kind: MessageKind, symbol: &'static str, timerange: Option<&'static str>, structure: Option<&'static str>) -> Topic {
+        Topic { kind, symbol, timerange, structure }
+    }
+
+    #[test]
+    fn an_empty_filter_matches_everything() {
+        let filter = Filter::default();
+
+        assert!(filter.matches(&topic(MessageKind::Candle, "EURUSD", Some("5min"), None)));
+        assert!(filter.matches(&topic(MessageKind::Session, "GBPUSD", None, None)));
+        assert!(filter.matches(&topic(MessageKind::TwoDStructure, "EURUSD", Some("1h"), Some("Order Block"))));
+    }
+
+    #[test]
+    fn filters_by_symbol_timerange_and_type() {
+        let filter = filter(r#"{"symbols": ["EURUSD"], "timeranges": ["5min"], "types": ["candle", "trend"]}"#);
+
+        assert!(filter.matches(&topic(MessageKind::Candle, "EURUSD", Some("5min"), None)));
+        assert!(filter.matches(&topic(MessageKind::Trend, "EURUSD", Some("5min"), None)));
+        assert!(!filter.matches(&topic(MessageKind::Candle, "GBPUSD", Some("5min"), None)));
+        assert!(!filter.matches(&topic(MessageKind::Candle, "EURUSD", Some("1h"), None)));
+        assert!(!filter.matches(&topic(MessageKind::OneDStructure, "EURUSD", Some("5min"), Some("BOS"))));
+    }
+
+    #[test]
+    fn sessions_go_through_the_timerange_filter() {
+        let filter = filter(r#"{"symbols": ["EURUSD"], "timeranges": ["5min"]}"#);
+
+        assert!(filter.matches(&topic(MessageKind::Session, "EURUSD", None, None)));
+        assert!(!filter.matches(&topic(MessageKind::Session, "GBPUSD", None, None)));
+    }
+
+    #[test]
+    fn the_structure_filter_only_applies_to_the_structures() {
+        let filter = filter(r#"{"structures": ["fair value gap"]}"#);
+
+        assert!(filter.matches(&topic(MessageKind::TwoDStructure, "EURUSD", Some("5min"), Some("Fair Value Gap"))));
+        assert!(!filter.matches(&topic(MessageKind::TwoDStructure, "EURUSD", Some("5min"), Some("Order Block"))));
+        assert!(filter.matches(&topic(MessageKind::Candle, "EURUSD", Some("5min"), None)));
+    }
+
+    #[test]
+    fn a_client_wants_the_messages_of_any_of_its_subscriptions() {
+        let mut subscriptions = Subscriptions::default();
+        let candle = topic(MessageKind::Candle, "EURUSD", Some("5min"), None);
+        assert!(!subscriptions.wants(&candle));
+
+        let first = subscriptions.subscribe(filter(r#"{"symbols": ["GBPUSD"]}"#));
+        let second = subscriptions.subscribe(filter(r#"{"symbols": ["EURUSD"]}"#));
+        assert!(subscriptions.wants(&candle));
+
+        subscriptions.handle(Request::Unsubscribe { id: None, subscription: Some(second) });
+        assert!(!subscriptions.wants(&candle));
+
+        let Payload::Ack(reply) = subscriptions.handle(Request::Unsubscribe { id: Some(7), subscription: None }) else {
+            panic!("Expected an ack");
+        };
+        assert_eq!((reply.id, reply.subscriptions), (Some(7), vec![first]));
+    }
+
+    #[test]
+    fn invalid_requests_keep_their_id() {
+        let Err(error) = parse_request(r#"{"action": "subscribe", "id": 4, "filter": {"symbol": "EURUSD"}}"#) else {
+            panic!("Expected an error");
+        };
+
+        assert_eq!(error.id, Some(4));
+        assert!(error.message.starts_with("Invalid request"));
+    }
+}
\ No newline at end of file
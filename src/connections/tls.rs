@@ -0,0 +1,93 @@
+// TLS for the servers, with the certificate and key read from PEM files
+// And for the client, which trusts the CA certificate it's given (or the ones of the system)
+
+use crate::config::TlsConfig;
+
+use std::{fs::File, io::BufReader, sync::Arc};
+use tokio_rustls::{
+    rustls::{crypto::ring::default_provider, ClientConfig, RootCertStore, ServerConfig},
+    TlsAcceptor,
+    TlsConnector,
+};
+
+// Returns None if TLS is disabled
+pub fn load_tls(config: &TlsConfig) -> Result<Option<TlsAcceptor>, String> {
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    let mut certificates = BufReader::new(
+        File::open(&config.certificate)
+            .map_err(|e| format!("Failed to open the certificate {}: {}", config.certificate, e))?,
+    );
+    let certificates = rustls_pemfile::certs(&mut certificates)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read the certificate {}: {}", config.certificate, e))?;
+
+    let mut key = BufReader::new(
+        File::open(&config.key)
+            .map_err(|e| format!("Failed to open the key {}: {}", config.key, e))?,
+    );
+    let key = rustls_pemfile::private_key(&mut key)
+        .map_err(|e| format!("Failed to read the key {}: {}", config.key, e))?
+        .ok_or_else(|| format!("No private key in {}", config.key))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {}", e))?
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+        .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+
+    Ok(Some(TlsAcceptor::from(Arc::new(config))))
+}
+
+// The servers often have self-signed or internal certificates, then only the given CA is trusted
+// Without it, the certificates of the system are (for a server with a public certificate)
+pub fn load_client_tls(ca_certificate: Option<&str>) -> Result<TlsConnector, String> {
+    let roots = match ca_certificate {
+        Some(ca_certificate) => load_ca_certificate(ca_certificate)?,
+        None => load_system_roots()?,
+    };
+
+    let config = ClientConfig::builder_with_provider(Arc::new(default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {}", e))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+fn load_ca_certificate(ca_certificate: &str) -> Result<RootCertStore, String> {
+    let mut certificates = BufReader::new(
+        File::open(ca_certificate)
+            .map_err(|e| format!("Failed to open the CA certificate {}: {}", ca_certificate, e))?,
+    );
+
+    let mut roots = RootCertStore::empty();
+    for certificate in rustls_pemfile::certs(&mut certificates) {
+        let certificate = certificate
+            .map_err(|e| format!("Failed to read the CA certificate {}: {}", ca_certificate, e))?;
+        roots.add(certificate)
+            .map_err(|e| format!("Invalid CA certificate {}: {}", ca_certificate, e))?;
+    }
+
+    Ok(roots)
+}
+
+// The certificates that can't be read are skipped, as long as some can
+fn load_system_roots() -> Result<RootCertStore, String> {
+    let certificates = rustls_native_certs::load_native_certs();
+    for e in &certificates.errors {
+        eprintln!("Failed to load a certificate of the system: {}", e);
+    }
+
+    let mut roots = RootCertStore::empty();
+    let (added, _) = roots.add_parsable_certificates(certificates.certs);
+    if added == 0 {
+        return Err("No certificate found on the system, set the CA certificate of the server".to_string());
+    }
+
+    Ok(roots)
+}
\ No newline at end of file
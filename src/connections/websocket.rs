@@ -1,98 +1,640 @@
+use crate::{
+    config::{get_config, WebsocketConfig},
+    connections::{
+        auth::{authenticate, request_token, Permissions},
+        messages::{Encoding, Envelope, Payload, PipelineStatus, SnapshotReply},
+        replay::{current_state, load_history},
+        subscriptions::{parse_request, Filter, Request, Subscriptions, Topic},
+        tls::load_tls,
+    },
+    handlers::{pipeline::{pipeline_status, PIPELINE_LOCK}, warmup::is_warming_up},
+};
+
 use futures_util::{SinkExt, StreamExt, stream::SplitSink};
-use once_cell::sync::Lazy;
-use std::sync::Arc;
-use tokio::sync::Mutex;
-use tokio::net::{TcpStream, TcpListener};
-use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{atomic::{AtomicU64, Ordering}, Arc},
+    time::Duration,
+};
+use tokio::sync::{mpsc::{self, error::TrySendError}, watch, Mutex, Semaphore};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    time::{interval, interval_at, sleep_until, timeout, Instant, MissedTickBehavior},
+};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request as HandshakeRequest, Response},
+        http::StatusCode,
+        Error,
+        Message,
+    },
+    WebSocketStream,
+};
+
+// A client connection, shared between tasks
+pub type Client = Arc<Connection>;
+
+pub struct Connection {
+    pub address: SocketAddr,
+    // Given by the token of the handshake
+    pub permissions: Permissions,
+    // The messages waiting to be written, only filled by the fan-out task
+    queue: mpsc::Sender<Batch>,
+    subscriptions: Mutex<Subscriptions>,
+    // Sequence number of the last message queued
+    // The dropped messages are counted, so the client sees the gap
+    seq: AtomicU64,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    // Set to true to close the connection
+    closing: watch::Sender<bool>,
+    // Milliseconds between the connection and the last message of the client (pongs included)
+    connected: Instant,
+    last_seen: AtomicU64,
+}
+
+// Messages queued at once for a client, with their sequence numbers
+// The encoding is the one of the connection when they were queued
+struct Batch {
+    encoding: Encoding,
+    messages: Vec<(u64, Arc<Payload>)>,
+}
+
+// What happens to a client that doesn't read its messages fast enough
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LaggingPolicy {
+    Drop,       // The messages that don't fit in its queue are lost
+    Disconnect, // The connection is closed
+}
+
+impl Connection {
+    // Queues messages without waiting, the policy applies if the queue is full
+    fn push(&self, messages: Vec<Arc<Payload>>, encoding: Encoding, policy: LaggingPolicy) {
+        let count = messages.len() as u64;
+        let first = self.seq.fetch_add(count, Ordering::Relaxed) + 1;
+        let batch = Batch {
+            encoding,
+            messages: (first..).zip(messages).collect(),
+        };
+
+        match self.queue.try_send(batch) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) if policy == LaggingPolicy::Drop => {
+                self.dropped.fetch_add(count, Ordering::Relaxed);
+            }
+            Err(TrySendError::Full(_)) => {
+                // Logged once, the next messages may arrive before the client is removed
+                if !self.close() {
+                    eprintln!("Disconnecting {}: too slow to read its messages", self.address);
+                }
+            }
+            // The connection is closing
+            Err(TrySendError::Closed(_)) => {}
+        }
+    }
+
+    // Returns whether it was already closing
+    fn close(&self) -> bool {
+        self.closing.send_replace(true)
+    }
+
+    // Milliseconds since the connection
+    fn elapsed(&self) -> u64 {
+        self.connected.elapsed().as_millis() as u64
+    }
+
+    fn seen(&self) {
+        self.last_seen.store(self.elapsed(), Ordering::Relaxed);
+    }
+
+    fn seen_since(&self, elapsed: u64) -> bool {
+        self.last_seen.load(Ordering::Relaxed) >= elapsed
+    }
+
+    pub fn metrics(&self) -> ClientMetrics {
+        ClientMetrics {
+            address: self.address,
+            name: self.permissions.name.clone(),
+            queued: self.queue.max_capacity() - self.queue.capacity(),
+            capacity: self.queue.max_capacity(),
+            sent: self.sent.load(Ordering::Relaxed),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// Queue depth and counters of a client
+#[derive(Clone, Debug, Serialize)]
+pub struct ClientMetrics {
+    pub address: SocketAddr,
+    pub name: String,
+    pub queued: usize,
+    pub capacity: usize,
+    pub sent: u64,
+    pub dropped: u64,
+}
+
+// Queue depth of the fan-out task and of each client
+#[derive(Clone, Debug, Serialize)]
+pub struct BroadcastMetrics {
+    pub fanout_queued: usize,
+    pub clients: Vec<ClientMetrics>,
+}
+
+// What the fan-out task dispatches, in order
+enum Event {
+    // A live message, for the clients subscribed to its topic
+    Broadcast(Topic, Arc<Payload>),
+    // A request of a client, applied between two broadcasts
+    // A subscription can come with the messages to replay before the live ones
+    Request {
+        client: Client,
+        request: Request,
+        replay: Option<Replay>,
+    },
+    // The live messages matching the filter are held for the client until its next subscription,
+    // Which replays them after its history (the history is loaded meanwhile)
+    Hold(Client, Filter),
+    // A reply that doesn't change the subscriptions (it also ends the hold of a replay that failed)
+    Reply(Client, Payload),
+    // For every client, with the symbols it can see
+    Heartbeat(PipelineStatus),
+}
+
+struct Replay {
+    history: Vec<Payload>,
+    state: Vec<Payload>,
+}
+
+static FANOUT: OnceCell<mpsc::Sender<Event>> = OnceCell::new();
 
 // Store the clients connected to the WebSocket server
-pub static CLIENTS: Lazy<Arc<Mutex<Vec<Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>>>>> = Lazy::new(|| Arc::new(Mutex::new(Vec::new())));
+pub static CLIENTS: Lazy<Arc<Mutex<Vec<Client>>>> = Lazy::new(|| Arc::new(Mutex::new(Vec::new())));
 
-pub async fn create_intra_websocket() -> Result<(), String> {
-    // Load the url
-    let url = "127.0.0.1:8080";
+pub async fn create_intra_websocket(config: &WebsocketConfig) -> Result<(), String> {
+    // Loaded first, a wrong path shouldn't wait for the first client to be noticed
+    let tls = load_tls(&config.tls)?;
 
     // Set up a TCP listener
-    let listener = TcpListener::bind(url)
+    let listener = TcpListener::bind(&config.address)
         .await
         .map_err(|e| format!("Unable to bind TCP listener: {}", e))?;
 
+    // The messages are dispatched by a single task,
+    // So the pipeline never waits for the clients
+    let (sender, receiver) = mpsc::channel(config.fanout_queue.max(1));
+    FANOUT.set(sender).map_err(|_| "WebSocket server already started")?;
+    tokio::spawn(run_fanout(receiver, config.lagging_policy));
+
+    if config.heartbeat_interval_ms > 0 {
+        tokio::spawn(send_heartbeats(Duration::from_millis(config.heartbeat_interval_ms)));
+    }
+
+    // Each connection holds a permit until it's closed
+    let permits = Arc::new(Semaphore::new(config.max_connections));
+    let config = Arc::new(config.clone());
+
     // Start the WebSocket server
     // and accept incoming WebSocket connections
-    while let Ok((stream, _)) = listener.accept().await {
-        let ws_stream = accept_async(stream)
-            .await
-            .expect("Error during WebSocket handshake");
-
-        let (write, mut read) = ws_stream.split();
-
-        // Add the new client to the list of clients
-        // Use an Arc and Mutex to share the client between tasks
-        // and ensure thread safety
-        let client = Arc::new(Mutex::new(write));
-        add_client(client.clone()).await;
-
-        // Handle incoming messages from the WebSocket client
-        while let Some(message) = read.next().await {
-            match message {
-                Ok(Message::Text(_)) => {
-                   let mut write = client.lock().await;
-                    write.send(Message::Text("You can't send messages to this server".into()))
-                        .await
-                        .expect("Error sending message");
-
-                    break;
-                }
-                Ok(Message::Close(_)) => {
-                    // Handle the close message
-                    break;
+    loop {
+        let (stream, address) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                // e.g. too many open files, the next connections may work
+                eprintln!("Failed to accept a connection: {}", e);
+                continue;
+            }
+        };
+
+        let Ok(permit) = Arc::clone(&permits).try_acquire_owned() else {
+            eprintln!("Rejecting connection from {}: too many clients", address);
+            continue;
+        };
+
+        // Each client is handled in its own task,
+        // So a slow handshake or a long connection doesn't block the other clients
+        let tls = tls.clone();
+        let config = Arc::clone(&config);
+        tokio::spawn(async move {
+            match tls {
+                Some(acceptor) => match timeout(handshake_timeout(&config), acceptor.accept(stream)).await {
+                    Ok(Ok(stream)) => handle_connection(stream, address, &config).await,
+                    Ok(Err(e)) => eprintln!("TLS handshake with {} failed: {}", address, e),
+                    Err(_) => eprintln!("TLS handshake with {} timed out", address),
+                },
+                None => handle_connection(stream, address, &config).await,
+            }
+            drop(permit);
+        });
+    }
+}
+
+fn handshake_timeout(config: &WebsocketConfig) -> Duration {
+    Duration::from_millis(config.handshake_timeout_ms)
+}
+
+async fn handle_connection<S>(stream: S, address: SocketAddr, config: &WebsocketConfig)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    // The client is authenticated during the handshake, a wrong token gets a 401
+    let mut authentication = None;
+    // The error response is the one tungstenite expects
+    #[allow(clippy::result_large_err)]
+    let authenticate_request = |request: &HandshakeRequest, response: Response| {
+        let token = request_token(
+            |name| request.headers().get(name).and_then(|value| value.to_str().ok()),
+            request.uri().query(),
+        );
+
+        let result = authenticate(&get_config().auth, token);
+        let response = match &result {
+            Ok(_) => Ok(response),
+            Err(message) => {
+                let mut response = ErrorResponse::new(Some(message.clone()));
+                *response.status_mut() = StatusCode::UNAUTHORIZED;
+                Err(response)
+            }
+        };
+
+        authentication = Some(result);
+        response
+    };
+
+    let ws_stream = match timeout(handshake_timeout(config), accept_hdr_async(stream, authenticate_request)).await {
+        Ok(Ok(ws_stream)) => ws_stream,
+        Ok(Err(e)) => {
+            match authentication {
+                Some(Err(message)) => eprintln!("Rejecting connection from {}: {}", address, message),
+                _ => eprintln!("WebSocket handshake with {} failed: {}", address, e),
+            }
+            return;
+        }
+        Err(_) => {
+            eprintln!("WebSocket handshake with {} timed out", address);
+            return;
+        }
+    };
+
+    // Always set once the handshake succeeded
+    let Some(Ok(permissions)) = authentication else {
+        return;
+    };
+
+    let (write, mut read) = ws_stream.split();
+    let (queue, receiver) = mpsc::channel(config.client_queue.max(1));
+    let (closing, mut closed) = watch::channel(false);
+
+    // Add the new client to the list of clients
+    // It doesn't receive anything until it subscribes
+    let client = Arc::new(Connection {
+        address,
+        permissions,
+        queue,
+        subscriptions: Mutex::new(Subscriptions::default()),
+        seq: AtomicU64::new(0),
+        sent: AtomicU64::new(0),
+        dropped: AtomicU64::new(0),
+        closing,
+        connected: Instant::now(),
+        last_seen: AtomicU64::new(0),
+    });
+    add_client(client.clone()).await;
+
+    // The messages are written by another task, so the requests are still read meanwhile
+    let pings = Pings {
+        interval: Duration::from_millis(config.ping_interval_ms),
+        timeout: Duration::from_millis(config.pong_timeout_ms),
+    };
+    let writer = tokio::spawn(write_messages(write, receiver, client.clone(), pings));
+
+    // The pongs don't count, only the requests
+    let idle_timeout = Duration::from_millis(config.idle_timeout_ms);
+    let mut last_request = Instant::now();
+
+    // Handle the requests of the client until it disconnects (or is disconnected)
+    loop {
+        let message = tokio::select! {
+            message = read.next() => Some(message),
+            _ = closed.wait_for(|closing| *closing) => break,
+            _ = sleep_until(last_request + idle_timeout), if !idle_timeout.is_zero() => None,
+        };
+
+        let Some(message) = message else {
+            if client.subscriptions.lock().await.is_empty() {
+                eprintln!("Disconnecting {}: idle for {}ms", address, config.idle_timeout_ms);
+                break;
+            }
+
+            // Still receiving messages
+            last_request = Instant::now();
+            continue;
+        };
+
+        let Some(message) = message else {
+            break;
+        };
+
+        client.seen();
+        if let Ok(Message::Text(_) | Message::Binary(_)) = message {
+            last_request = Instant::now();
+        }
+
+        let event = match message {
+            // The subscriptions are restricted to the symbols of the client
+            Ok(Message::Text(text)) => match parse_request(&text).and_then(|mut request| {
+                client.permissions.authorize(&mut request)?;
+                Ok(request)
+            }) {
+                Ok(request @ Request::Subscribe { snapshot, history, .. }) if snapshot || history > 0 => {
+                    subscribe_with_replay(&client, request, config.max_history).await;
+                    continue;
                 }
-                Err(e) => {
-                    println!("Error: {}", e);
+                Ok(request) => Event::Request { client: client.clone(), request, replay: None },
+                Err(reply) => Event::Reply(client.clone(), Payload::Error(reply)),
+            },
+            Ok(Message::Close(_)) => break,
+            // The pings are answered by tungstenite
+            Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_)) => continue,
+            Ok(Message::Binary(_)) => Event::Reply(client.clone(), Payload::error(None, "The requests have to be JSON text messages".to_string())),
+            Err(e) => {
+                eprintln!("WebSocket error with {}: {}", address, e);
+                break;
+            }
+        };
+
+        dispatch(event).await;
+    }
 
-                    break;
+    // Remove the client from the list of clients
+    // The messages still queued are lost
+    remove_client(client.clone()).await;
+    writer.abort();
+}
+
+// How often a client is pinged, and how long it has to answer
+struct Pings {
+    interval: Duration,
+    timeout: Duration,
+}
+
+// Writes the queued messages of a client, with their sequence numbers, and the pings
+// A client that doesn't answer a ping in time is disconnected,
+// The dead connections would otherwise stay until the OS gives up on them
+async fn write_messages<S>(mut write: SplitSink<WebSocketStream<S>, Message>, mut queue: mpsc::Receiver<Batch>, client: Client, pings: Pings)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut ticks = interval_at(Instant::now() + pings.interval, pings.interval.max(Duration::from_millis(1)));
+    // When the unanswered ping was sent (see `Connection::elapsed`)
+    let mut pending: Option<u64> = None;
+
+    loop {
+        tokio::select! {
+            batch = queue.recv() => {
+                let Some(batch) = batch else {
+                    return;
+                };
+
+                if let Err(e) = write_batch(&mut write, batch, &client).await {
+                    eprintln!("Error sending message to {}: {}", client.address, e);
+                    client.close();
+                    return;
+                }
+            }
+            _ = ticks.tick(), if !pings.interval.is_zero() => {
+                if let Err(e) = write.send(Message::Ping(Default::default())).await {
+                    eprintln!("Error sending ping to {}: {}", client.address, e);
+                    client.close();
+                    return;
                 }
-                _ => {
-                    let mut write = client.lock().await;
-                    write.send(Message::Text("You can't send messages to this server".into()))
-                        .await
-                        .expect("Error sending message");
 
-                    break;
+                pending.get_or_insert_with(|| client.elapsed());
+            }
+            _ = sleep_until(client.connected + Duration::from_millis(pending.unwrap_or(0)) + pings.timeout), if pending.is_some() => {
+                // Any message counts as an answer
+                if pending.is_some_and(|sent| !client.seen_since(sent)) {
+                    if !client.close() {
+                        eprintln!("Disconnecting {}: no pong within {}ms", client.address, pings.timeout.as_millis());
+                    }
+                    return;
                 }
+
+                pending = None;
             }
         }
+    }
+}
+
+async fn write_batch<S>(write: &mut SplitSink<WebSocketStream<S>, Message>, batch: Batch, client: &Client) -> Result<(), Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    for (seq, payload) in batch.messages {
+        let envelope = Envelope::new(seq, (*payload).clone());
 
-        // Remove the client from the list of clients
-        remove_client(client.clone()).await;
+        let bytes = match envelope.encode(batch.encoding) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                eprintln!("{} for {}", e, client.address);
+                continue;
+            }
+        };
+
+        let message = match batch.encoding {
+            // Can't fail, it comes from serde_json
+            Encoding::Json => Message::Text(String::from_utf8(bytes).unwrap_or_default().into()),
+            Encoding::Msgpack | Encoding::Cbor => Message::Binary(bytes.into()),
+        };
+
+        write.send(message).await?;
+        client.sent.fetch_add(1, Ordering::Relaxed);
     }
 
     Ok(())
 }
 
+// Subscribes the client and replays the history and/or the snapshot before the live messages
+async fn subscribe_with_replay(client: &Client, request: Request, max_history: usize) {
+    let Request::Subscribe { id, filter, snapshot, history, .. } = &request else {
+        return;
+    };
+    let (id, snapshot, history) = (*id, *snapshot, (*history).min(max_history));
+
+    // The history is loaded while the pipeline runs,
+    // The messages produced meanwhile are held and sent after it, so nothing is missed
+    let replay_history = if history > 0 {
+        dispatch(Event::Hold(client.clone(), filter.clone())).await;
+
+        match load_history(filter, history).await {
+            Ok(messages) => messages,
+            Err(message) => {
+                dispatch(Event::Reply(client.clone(), Payload::error(id, message))).await;
+                return;
+            }
+        }
+    } else {
+        Vec::new()
+    };
+
+    // Nothing is broadcasted while the state is copied,
+    // And the subscription is queued before the next live messages, so they follow right where it ends
+    let _guard = PIPELINE_LOCK.write().await;
+
+    let replay = Replay {
+        history: replay_history,
+        state: if snapshot { current_state(filter) } else { Vec::new() },
+    };
+
+    dispatch(Event::Request { client: client.clone(), request, replay: Some(replay) }).await;
+}
+
+// Dispatches the events in order, without ever waiting for a client
+async fn run_fanout(mut events: mpsc::Receiver<Event>, policy: LaggingPolicy) {
+    // The messages held for the clients loading a history, by address
+    let mut held: HashMap<SocketAddr, (Filter, Vec<Arc<Payload>>)> = HashMap::new();
+
+    while let Some(event) = events.recv().await {
+        match event {
+            Event::Broadcast(topic, message) => {
+                // Copied so the clients can connect meanwhile
+                let clients = CLIENTS.lock().await.clone();
+
+                for client in clients {
+                    if let Some((filter, messages)) = held.get_mut(&client.address) {
+                        if filter.matches(&topic) {
+                            messages.push(Arc::clone(&message));
+                            continue;
+                        }
+                    }
+
+                    let subscriptions = client.subscriptions.lock().await;
+                    if subscriptions.wants(&topic) {
+                        client.push(vec![Arc::clone(&message)], subscriptions.encoding, policy);
+                    }
+                }
+            }
+            Event::Hold(client, filter) => {
+                held.insert(client.address, (filter, Vec::new()));
+            }
+            Event::Request { client, request, replay } => {
+                // The ack is already in the encoding the request asked for
+                let mut subscriptions = client.subscriptions.lock().await;
+                let reply = subscriptions.handle(request);
+
+                // The messages held while the history was loaded come after it
+                // The rows stored meanwhile are in both, only their live (latest) version is kept
+                let held = held.remove(&client.address).map(|(_, messages)| messages).unwrap_or_default();
+                let live: HashSet<_> = held.iter().filter_map(|message| message.uid()).collect();
+
+                // The replay is announced right after the ack
+                let replay = match (replay, &reply) {
+                    (Some(mut replay), Payload::Ack(ack)) => {
+                        replay.history.retain(|message| message.uid().is_none_or(|uid| !live.contains(&uid)));
+
+                        let mut messages = vec![Arc::new(Payload::Snapshot(SnapshotReply {
+                            id: ack.id,
+                            subscription: ack.subscriptions[0],
+                            history: replay.history.len() + held.len(),
+                            state: replay.state.len(),
+                        }))];
+                        messages.extend(replay.history.into_iter().map(Arc::new));
+                        messages.extend(held);
+                        messages.extend(replay.state.into_iter().map(Arc::new));
+                        messages
+                    }
+                    _ => Vec::new(),
+                };
+
+                let messages = std::iter::once(Arc::new(reply)).chain(replay).collect();
+                client.push(messages, subscriptions.encoding, policy);
+            }
+            Event::Reply(client, reply) => {
+                held.remove(&client.address);
+
+                let encoding = client.subscriptions.lock().await.encoding;
+                client.push(vec![Arc::new(reply)], encoding, policy);
+            }
+            Event::Heartbeat(status) => {
+                let clients = CLIENTS.lock().await.clone();
+                let heartbeat = Arc::new(Payload::Heartbeat(status.clone()));
+
+                for client in clients {
+                    // The symbols it can't subscribe to aren't shown either
+                    let heartbeat = if client.permissions.symbols.is_empty() {
+                        Arc::clone(&heartbeat)
+                    } else {
+                        let mut status = status.clone();
+                        status.last_candles.retain(|symbol, _| client.permissions.allows(symbol));
+                        Arc::new(Payload::Heartbeat(status))
+                    };
+
+                    let encoding = client.subscriptions.lock().await.encoding;
+                    client.push(vec![heartbeat], encoding, policy);
+                }
+            }
+        }
+    }
+}
+
+// Tells the clients the server and the pipeline are alive
+async fn send_heartbeats(period: Duration) {
+    let mut ticks = interval(period);
+    // After a pause, one heartbeat is enough
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        ticks.tick().await;
+        dispatch(Event::Heartbeat(pipeline_status())).await;
+    }
+}
+
+// Sends an event to the fan-out task, waiting if its queue is full
+// Nothing is sent if the server isn't running
+async fn dispatch(event: Event) {
+    if let Some(fanout) = FANOUT.get() {
+        if fanout.send(event).await.is_err() {
+            eprintln!("The WebSocket fan-out task is stopped");
+        }
+    }
+}
+
 // Add a new client to the list of clients
 // Separate function to avoid long locks
-pub async fn add_client(client: Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>) {
+pub async fn add_client(client: Client) {
     let mut clients = CLIENTS.lock().await;
     clients.push(client);
 }
 
 // Same thing as above for removing a client
-pub async fn remove_client(client: Arc<Mutex<SplitSink<WebSocketStream<TcpStream>, Message>>>) {
+pub async fn remove_client(client: Client) {
     let mut clients = CLIENTS.lock().await;
     clients.retain(|c| !Arc::ptr_eq(c, &client));
 }
 
-// Send a message to all connected clients
-pub async fn send_message_to_clients(message: &String) -> Result<(), String> {
-    let clients = CLIENTS.lock().await;
+// Queue depth of the fan-out task and of each client
+pub async fn broadcast_metrics() -> BroadcastMetrics {
+    BroadcastMetrics {
+        fanout_queued: FANOUT.get().map(|f| f.max_capacity() - f.capacity()).unwrap_or(0),
+        clients: CLIENTS.lock().await.iter().map(|client| client.metrics()).collect(),
+    }
+}
 
-    for client in clients.iter() {
-        let mut write = client.lock().await;
-        write.send(Message::Text(message.into()))
-            .await
-            .map_err(|e| format!("Error sending message: {}", e))?;
+// Send a message to the clients subscribed to its topic
+// It's only queued, the clients are written to by their own task
+pub async fn send_message_to_clients(topic: Topic, message: Payload) -> Result<(), String> {
+    // Everything replayed has already been sent
+    if is_warming_up() {
+        return Ok(());
     }
 
+    dispatch(Event::Broadcast(topic, Arc::new(message))).await;
+
     Ok(())
 }
\ No newline at end of file
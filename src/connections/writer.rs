@@ -0,0 +1,229 @@
+// Write-behind persistence
+// The rows to store are sent to a background worker through a bounded queue,
+// It buffers them and writes them in batches when the batch is full or on a timer
+// When the queue is full, the senders wait: this is how the pipeline is slowed down if the database can't keep up
+
+use crate::{
+    config::WriterConfig,
+    connections::database::{write_batch, Batch, Record},
+};
+
+use once_cell::sync::OnceCell;
+use std::time::Duration;
+use tokio::{
+    sync::{mpsc, oneshot, Mutex},
+    task::JoinHandle,
+    time::{interval, sleep, MissedTickBehavior},
+};
+
+pub static WRITER: OnceCell<Writer> = OnceCell::new();
+
+pub struct Writer {
+    sender: mpsc::Sender<Command>,
+    handle: Mutex<Option<JoinHandle<()>>>,
+}
+
+enum Command {
+    Write(Record),
+    // Write everything that's buffered, the answer is whether it succeeded
+    Flush(oneshot::Sender<bool>),
+    // Flush everything that's buffered and stop the worker
+    Shutdown(oneshot::Sender<()>),
+}
+
+// Start the background worker
+pub fn init_writer(config: &WriterConfig) -> Result<(), String> {
+    let (sender, receiver) = mpsc::channel(config.capacity.max(1));
+
+    let handle = tokio::spawn(run_writer(
+        receiver,
+        config.batch_size.max(1),
+        Duration::from_millis(config.flush_interval_ms),
+        config.max_retries,
+    ));
+
+    let writer = Writer {
+        sender,
+        handle: Mutex::new(Some(handle)),
+    };
+
+    WRITER.set(writer).map_err(|_| "Writer already initialized")?;
+    Ok(())
+}
+
+// Sends a row to the worker, waiting if the queue is full
+// The row is given back if the worker isn't running
+pub async fn enqueue(record: Record) -> Result<(), Record> {
+    let Some(writer) = WRITER.get() else {
+        return Err(record);
+    };
+
+    writer.sender.send(Command::Write(record)).await.map_err(|e| match e.0 {
+        Command::Write(record) => record,
+        _ => unreachable!("Only writes are sent here"),
+    })
+}
+
+// Number of rows waiting in the queue (not counting the ones already buffered by the worker)
+pub fn queue_depth() -> usize {
+    WRITER
+        .get()
+        .map(|writer| writer.sender.max_capacity() - writer.sender.capacity())
+        .unwrap_or(0)
+}
+
+// Waits until the rows sent before are written
+// So they can be read back from the storage
+pub async fn flush_writer() -> Result<(), String> {
+    let Some(writer) = WRITER.get() else {
+        return Ok(());
+    };
+
+    let (ack, done) = oneshot::channel();
+
+    // Once stopped, the rows are written directly
+    if writer.sender.send(Command::Flush(ack)).await.is_err() {
+        return Ok(());
+    }
+
+    match done.await {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("Failed to write the pending rows".to_string()),
+        Err(_) => Err("Writer stopped before flushing".to_string()),
+    }
+}
+
+// Writes everything that's pending and stops the worker
+// The rows added afterwards are written directly
+pub async fn shutdown_writer() -> Result<(), String> {
+    let Some(writer) = WRITER.get() else {
+        return Ok(());
+    };
+
+    let (ack, done) = oneshot::channel();
+
+    // The worker may already be stopped
+    if writer.sender.send(Command::Shutdown(ack)).await.is_ok() {
+        done.await.map_err(|_| "Writer stopped before flushing")?;
+    }
+
+    if let Some(handle) = writer.handle.lock().await.take() {
+        handle.await.map_err(|e| format!("Writer panic: {}", e))?;
+    }
+
+    Ok(())
+}
+
+async fn run_writer(mut receiver: mpsc::Receiver<Command>, batch_size: usize, flush_interval: Duration, max_retries: usize) {
+    let mut batch = Batch::default();
+    // Failed writes of the buffered rows in a row
+    let mut failures = 0;
+
+    let mut ticker = interval(flush_interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        // A full batch has to be written before accepting more rows
+        // If the database is down, we keep retrying (up to max_retries) and the queue fills up
+        if batch.len() >= batch_size {
+            if !flush(&mut batch, &mut failures, max_retries).await && !batch.is_empty() {
+                sleep(flush_interval).await;
+            }
+
+            continue;
+        }
+
+        tokio::select! {
+            command = receiver.recv() => match command {
+                Some(Command::Write(record)) => batch.push(record),
+                Some(Command::Flush(ack)) => {
+                    let _ = ack.send(flush(&mut batch, &mut failures, max_retries).await);
+                }
+                Some(Command::Shutdown(ack)) => {
+                    // Stop accepting rows and take the ones already queued
+                    receiver.close();
+                    while let Ok(command) = receiver.try_recv() {
+                        if let Command::Write(record) = command {
+                            batch.push(record);
+                        }
+                    }
+
+                    // Retried until it's written or split (the batch is empty either way)
+                    while !batch.is_empty() {
+                        flush(&mut batch, &mut failures, max_retries).await;
+                    }
+
+                    let _ = ack.send(());
+                    return;
+                }
+                None => {
+                    while !batch.is_empty() {
+                        flush(&mut batch, &mut failures, max_retries).await;
+                    }
+                    return;
+                }
+            },
+            _ = ticker.tick() => {
+                flush(&mut batch, &mut failures, max_retries).await;
+            }
+        }
+    }
+}
+
+// Writes the batch and empties it if it succeeded
+// After max_retries failed writes in a row, the batch is split to write the rows that can be,
+// It's then emptied too, the answer is whether none of its rows were dropped
+async fn flush(batch: &mut Batch, failures: &mut usize, max_retries: usize) -> bool {
+    if batch.is_empty() {
+        return true;
+    }
+
+    match write_batch(batch).await {
+        Ok(()) => {
+            *batch = Batch::default();
+            *failures = 0;
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to write {} rows to the database: {}", batch.len(), e);
+
+            *failures += 1;
+            if *failures <= max_retries {
+                return false;
+            }
+
+            *failures = 0;
+            let dropped = write_split(std::mem::take(batch)).await;
+            dropped == 0
+        }
+    }
+}
+
+// Writes the rows of a batch that keeps failing in halves, down to single rows
+// So the rows that can't be written (e.g. a constraint violation) don't block the others
+// Those are logged and dropped, the answer is how many there were
+async fn write_split(batch: Batch) -> usize {
+    let mut dropped = 0;
+    // The first half is written first, so the rows keep their order
+    let mut pending = vec![batch.into_records()];
+
+    while let Some(records) = pending.pop() {
+        let batch: Batch = records.into_iter().collect();
+        let Err(e) = write_batch(&batch).await else {
+            continue;
+        };
+
+        let mut records = batch.into_records();
+        if records.len() <= 1 {
+            eprintln!("Dropping a row that can't be written to the database: {}", e);
+            dropped += records.len();
+            continue;
+        }
+
+        let second = records.split_off(records.len() / 2);
+        pending.push(second);
+        pending.push(records);
+    }
+
+    dropped
+}
\ No newline at end of file
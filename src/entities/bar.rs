@@ -0,0 +1,42 @@
+use crate::utils::utils::intern;
+
+use serde::Deserialize;
+
+// Non time based bars
+// Each bar type is built from the 1m candles and produces regular candles,
+// So they can be processed by the same detectors as the timeranges
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum BarKind {
+    Volume, // Closes once the traded volume reaches the size
+    Tick,   // Closes after `size` 1m candles (our smallest unit of data)
+    Range,  // Closes once the distance between the high and the low reaches the size
+    Renko,  // Emits a brick each time the close moves by the size beyond the last brick
+}
+
+pub struct BarType {
+    pub label: &'static str, // Used in place of the timerange in the produced candles
+    pub kind: BarKind,
+    pub size: f64,
+}
+
+impl BarType {
+    pub fn new(kind: BarKind, size: f64) -> Result<Self, String> {
+        if size.is_nan() || size <= 0.0 {
+            return Err(format!("Invalid size for {:?} bars: {}", kind, size));
+        }
+
+        let prefix = match kind {
+            BarKind::Volume => "volume",
+            BarKind::Tick => "tick",
+            BarKind::Range => "range",
+            BarKind::Renko => "renko",
+        };
+
+        Ok(BarType {
+            label: intern(&format!("{}-{}", prefix, size)),
+            kind,
+            size,
+        })
+    }
+}
\ No newline at end of file
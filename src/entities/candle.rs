@@ -1,20 +1,35 @@
+use crate::utils::utils::{interned, stable_id, Interned};
+
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Deserialize, Serialize, JsonSchema)]
 pub struct Candle {
-    pub symbol: &'static str,
-    pub timerange: &'static str,
+    #[serde(deserialize_with = "interned")]
+    pub symbol: Interned,
+    #[serde(deserialize_with = "interned")]
+    pub timerange: Interned,
     pub timestamp: DateTime<Utc>,
     pub open: f64,
     pub high: f64,
     pub low: f64,
     pub close: f64,
     pub volume: f64,
-    pub direction: &'static str,
+    #[serde(deserialize_with = "interned")]
+    pub direction: Interned,
+    // False while the candle is still being built (partial),
+    // True once its period is over and it has been finalized
+    pub complete: bool,
+    // Tells apart the bars built from the same 1m candle (several renko bricks share its timestamp)
+    // Always 0 for the other candles
+    #[serde(default)]
+    pub sequence: i32,
 }
 
 impl Candle {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(symbol: &'static str, timerange: &'static str, timestamp: DateTime<Utc>, open: f64, high: f64, low: f64, close: f64, volume: f64,
     ) -> Self {
         Candle {
@@ -26,7 +41,20 @@ impl Candle {
             low,
             close,
             volume,
-            direction: get_direction(open, close)
+            direction: get_direction(open, close),
+            complete: true,
+            sequence: 0,
+        }
+    }
+
+    // Stable id of the candle (the partial and the complete versions share it)
+    // The sequence is only part of it when it's set, so the ids of the other candles don't depend on it
+    pub fn uid(&self) -> Uuid {
+        let timestamp = self.timestamp.timestamp_micros().to_string();
+
+        match self.sequence {
+            0 => stable_id(&["candle", self.symbol, self.timerange, &timestamp]),
+            sequence => stable_id(&["candle", self.symbol, self.timerange, &timestamp, &sequence.to_string()]),
         }
     }
 }
@@ -1,6 +1,7 @@
 // This folder contains shared data and reference types
 // for easy access and reuse throughout the codebase.
 
+pub mod bar;
 pub mod candle;
 pub mod session;
 pub mod structures;
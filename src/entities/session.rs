@@ -1,13 +1,20 @@
+use crate::utils::utils::{interned, stable_id, Interned};
+
 use chrono::{
     DateTime,
     NaiveTime,
     Utc
 };
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-#[derive(Clone)]
+#[derive(Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Session {
-    pub symbol: &'static str,
-    pub label: &'static str,
+    #[serde(deserialize_with = "interned")]
+    pub symbol: Interned,
+    #[serde(deserialize_with = "interned")]
+    pub label: Interned,
     pub start: DateTime<Utc>,
     pub end: DateTime<Utc>,
     pub high: f64,
@@ -17,6 +24,12 @@ pub struct Session {
     pub volume: f64,
 }
 
+impl Session {
+    pub fn uid(&self) -> Uuid {
+        stable_id(&["session", self.symbol, self.label, &self.start.timestamp_micros().to_string()])
+    }
+}
+
 pub struct ReferenceSession {
     pub label: &'static str,
     pub start: NaiveTime,
@@ -1,23 +1,58 @@
+use crate::utils::utils::{interned, stable_id, Interned};
+
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-#[derive(Serialize)]
+#[derive(Clone, Deserialize, Serialize, JsonSchema)]
 pub struct TwoDStructures {
-    pub symbol: &'static str,
-    pub structure: &'static str,
-    pub timerange: &'static str,
+    #[serde(deserialize_with = "interned")]
+    pub symbol: Interned,
+    #[serde(deserialize_with = "interned")]
+    pub structure: Interned,
+    #[serde(deserialize_with = "interned")]
+    pub timerange: Interned,
     pub timestamp: DateTime<Utc>,
     pub high: f64,
     pub low: f64,
-    pub direction: &'static str,
+    #[serde(deserialize_with = "interned")]
+    pub direction: Interned,
+    // When the price went through the zone, None while it's still valid
+    pub invalidated_at: Option<DateTime<Utc>>,
+    // The trend an order block was found in
+    pub trend: Option<Uuid>,
+    // The first and the last candles the structure was built from
+    pub first_candle: Option<Uuid>,
+    pub last_candle: Option<Uuid>,
 }
 
-#[derive(Serialize)]
+#[derive(Clone, Deserialize, Serialize, JsonSchema)]
 pub struct OneDStructures {
-    pub symbol: &'static str,
-    pub structure: &'static str,
-    pub timerange: &'static str,
+    #[serde(deserialize_with = "interned")]
+    pub symbol: Interned,
+    #[serde(deserialize_with = "interned")]
+    pub structure: Interned,
+    #[serde(deserialize_with = "interned")]
+    pub timerange: Interned,
     pub timestamp: DateTime<Utc>,
     pub price: f64,
-    pub direction: &'static str,
+    #[serde(deserialize_with = "interned")]
+    pub direction: Interned,
+    // The swing broken by a break of structure or a change of character
+    pub broken: Option<Uuid>,
+    // The trend the structure was found in
+    pub trend: Option<Uuid>,
+}
+
+impl TwoDStructures {
+    pub fn uid(&self) -> Uuid {
+        stable_id(&["two_d_structure", self.symbol, self.structure, self.timerange, &self.timestamp.timestamp_micros().to_string()])
+    }
+}
+
+impl OneDStructures {
+    pub fn uid(&self) -> Uuid {
+        stable_id(&["one_d_structure", self.symbol, self.structure, self.timerange, &self.timestamp.timestamp_micros().to_string()])
+    }
 }
\ No newline at end of file
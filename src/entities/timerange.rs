@@ -1,8 +1,18 @@
+use crate::utils::utils::intern;
+
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use std::sync::RwLock;
+
 pub struct Timerange {
     pub label: &'static str,
     pub duration_ms: u128, // Corresponding durations in milliseconds for the timeranges
 }
 
+// The timerange of the candles stored from the raw data
+// Every other timerange is aggregated from it
+pub const BASE_TIMERANGE: &str = "1min";
+
 pub static TIMERANGES: &[Timerange] = &[
     Timerange { label: "1min", duration_ms: 60_000 },
     Timerange { label: "5min", duration_ms: 300_000 },
@@ -12,4 +22,213 @@ pub static TIMERANGES: &[Timerange] = &[
     Timerange { label: "4h", duration_ms: 14_400_000 },
     Timerange { label: "1d", duration_ms: 86_400_000 },
     Timerange { label: "1w", duration_ms: 604_800_000 },
-];
\ No newline at end of file
+];
+
+// Every timerange that has been created at runtime, by label
+// They are leaked so they can be shared as &'static like the default ones,
+// And kept here so adding the same timerange again doesn't leak it twice
+static CUSTOM_TIMERANGES: Lazy<DashMap<&'static str, &'static Timerange>> = Lazy::new(DashMap::new);
+
+// The timeranges the pipeline is currently aggregating
+// Defaults to TIMERANGES until `set_timeranges` is called
+static ACTIVE_TIMERANGES: Lazy<RwLock<Vec<&'static Timerange>>> = Lazy::new(|| {
+    RwLock::new(TIMERANGES.iter().collect())
+});
+
+impl Timerange {
+    // Parses a label made of a number and a unit, like "120s", "5min", "2h", "3d" or "1w"
+    // "m" is accepted as a shorthand for "min"
+    // The label is normalized to the largest unit that divides the duration,
+    // So "2m", "2min" and "120s" are the same timerange, and so are "60min" and "1h"
+    pub fn parse(label: &str) -> Result<Timerange, String> {
+        let label = label.trim();
+        let split = label
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(|| format!("Missing unit in timerange: {}", label))?;
+
+        let (value, unit) = label.split_at(split);
+        let value: u128 = value
+            .parse()
+            .map_err(|_| format!("Invalid value in timerange: {}", label))?;
+
+        let unit_ms = match unit {
+            "s" => 1_000,
+            "m" | "min" => 60_000,
+            "h" => 3_600_000,
+            "d" => 86_400_000,
+            "w" => 604_800_000,
+            _ => return Err(format!("Unknown unit in timerange: {}", label)),
+        };
+
+        if value == 0 {
+            return Err(format!("Timerange must not be empty: {}", label));
+        }
+
+        let duration_ms = value
+            .checked_mul(unit_ms)
+            .ok_or_else(|| format!("Timerange is too long: {}", label))?;
+
+        let (unit_ms, unit) = [(604_800_000, "w"), (86_400_000, "d"), (3_600_000, "h"), (60_000, "min")]
+            .into_iter()
+            .find(|(unit_ms, _)| duration_ms % unit_ms == 0)
+            .unwrap_or((1_000, "s"));
+
+        Ok(Timerange {
+            label: intern(&format!("{}{}", duration_ms / unit_ms, unit)),
+            duration_ms,
+        })
+    }
+
+    // The timeranges the pipeline aggregates are built from 1m candles,
+    // So unlike the parsed labels, they have to be a whole number of minutes
+    pub fn check_aggregated(&self) -> Result<(), String> {
+        if !self.duration_ms.is_multiple_of(60_000) {
+            return Err(format!(
+                "Timerange {} can't be aggregated from 1min candles, it isn't a whole number of minutes",
+                self.label
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+// Returns the reference timerange matching the label, once normalized
+// The default timeranges are used first, then the ones created at runtime
+pub fn find_timerange(label: &str) -> Result<&'static Timerange, String> {
+    let timerange = Timerange::parse(label)?;
+
+    if let Some(timerange) = TIMERANGES.iter().find(|t| t.label == timerange.label) {
+        return Ok(timerange);
+    }
+
+    if let Some(timerange) = CUSTOM_TIMERANGES.get(timerange.label) {
+        return Ok(*timerange);
+    }
+
+    let timerange = *CUSTOM_TIMERANGES
+        .entry(timerange.label)
+        .or_insert_with(|| Box::leak(Box::new(timerange)));
+
+    Ok(timerange)
+}
+
+// Same as `find_timerange`, for a timerange that's going to be aggregated from the 1m candles
+pub fn find_aggregated_timerange(label: &str) -> Result<&'static Timerange, String> {
+    let timerange = find_timerange(label)?;
+    timerange.check_aggregated()?;
+    Ok(timerange)
+}
+
+// Returns the active timerange matching the label
+pub fn get_timerange(label: &str) -> Option<&'static Timerange> {
+    get_timeranges().into_iter().find(|t| t.label == label)
+}
+
+// Returns the timeranges the pipeline is currently aggregating
+pub fn get_timeranges() -> Vec<&'static Timerange> {
+    ACTIVE_TIMERANGES
+        .read()
+        .map(|timeranges| timeranges.clone())
+        .unwrap_or_default()
+}
+
+// Replaces the active timeranges (used when loading the configuration)
+pub fn set_timeranges(labels: &[String]) -> Result<(), String> {
+    let mut timeranges = Vec::new();
+
+    for label in labels {
+        let timerange = find_aggregated_timerange(label)?;
+
+        if timeranges.iter().any(|t: &&Timerange| t.label == timerange.label) {
+            return Err(format!("Timerange {} is configured twice", label));
+        }
+
+        timeranges.push(timerange);
+    }
+
+    let mut active = ACTIVE_TIMERANGES.write().map_err(|_| "Timeranges lock poisoned")?;
+    *active = timeranges;
+
+    Ok(())
+}
+
+// Adds a timerange to the active ones
+pub fn activate_timerange(timerange: &'static Timerange) -> Result<(), String> {
+    timerange.check_aggregated()?;
+
+    let mut active = ACTIVE_TIMERANGES.write().map_err(|_| "Timeranges lock poisoned")?;
+
+    if active.iter().any(|t| t.label == timerange.label) {
+        return Err(format!("Timerange {} is already active", timerange.label));
+    }
+
+    active.push(timerange);
+
+    Ok(())
+}
+
+// Removes a timerange from the active ones
+pub fn deactivate_timerange(label: &str) -> Result<&'static Timerange, String> {
+    let mut active = ACTIVE_TIMERANGES.write().map_err(|_| "Timeranges lock poisoned")?;
+
+    let index = active
+        .iter()
+        .position(|t| t.label == label)
+        .ok_or_else(|| format!("Timerange {} is not active", label))?;
+
+    Ok(active.remove(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(label: &str) -> (&'static str, u128) {
+        let timerange = Timerange::parse(label).unwrap();
+        (timerange.label, timerange.duration_ms)
+    }
+
+    #[test]
+    fn parses_every_unit() {
+        assert_eq!(parse("90s"), ("90s", 90_000));
+        assert_eq!(parse("120s"), ("2min", 120_000));
+        assert_eq!(parse("5m"), ("5min", 300_000));
+        assert_eq!(parse("15min"), ("15min", 900_000));
+        assert_eq!(parse("4h"), ("4h", 14_400_000));
+        assert_eq!(parse("1d"), ("1d", 86_400_000));
+        assert_eq!(parse("1w"), ("1w", 604_800_000));
+        assert_eq!(parse(" 30min "), ("30min", 1_800_000));
+    }
+
+    #[test]
+    fn normalizes_to_the_largest_unit() {
+        assert_eq!(parse("60min").0, "1h");
+        assert_eq!(parse("90min").0, "90min");
+        assert_eq!(parse("24h").0, "1d");
+        assert_eq!(parse("14d").0, "2w");
+        assert_eq!(parse("3600s").0, "1h");
+    }
+
+    #[test]
+    fn rejects_invalid_labels() {
+        for label in ["", "5", "min", "0min", "5x", "5 min", "-5min", "99999999999999999999999999999999999999w"] {
+            assert!(Timerange::parse(label).is_err(), "{} should be refused", label);
+        }
+    }
+
+    #[test]
+    fn finds_the_same_timerange_for_equivalent_labels() {
+        assert!(std::ptr::eq(find_timerange("60min").unwrap(), find_timerange("1h").unwrap()));
+        assert!(std::ptr::eq(find_timerange("2m").unwrap(), find_timerange("120s").unwrap()));
+    }
+    #[test]
+    fn only_whole_minutes_can_be_aggregated() {
+        assert!(find_aggregated_timerange("120s").is_ok());
+        assert_eq!(
+            find_aggregated_timerange("90s").err().unwrap(),
+            "Timerange 90s can't be aggregated from 1min candles, it isn't a whole number of minutes"
+        );
+        assert!(activate_timerange(find_timerange("30s").unwrap()).is_err());
+    }
+}
\ No newline at end of file
@@ -1,15 +1,20 @@
-use crate::Candle;
+use crate::{utils::utils::{interned, stable_id, Interned}, Candle};
 
 use chrono::{DateTime, Utc};
-use serde::Serialize;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Deserialize, Serialize, JsonSchema)]
 pub struct Trend {
-    pub symbol: &'static str,
-    pub timerange: &'static str,
+    #[serde(deserialize_with = "interned")]
+    pub symbol: Interned,
+    #[serde(deserialize_with = "interned")]
+    pub timerange: Interned,
     pub start_time: DateTime<Utc>,
     pub end_time: DateTime<Utc>,
-    pub direction: &'static str,
+    #[serde(deserialize_with = "interned")]
+    pub direction: Interned,
     pub high: f64,
     pub low: f64,
     pub high_datetime: DateTime<Utc>,
@@ -18,10 +23,18 @@ pub struct Trend {
     pub relative_low: f64,
 }
 
-#[derive(Clone)]
+impl Trend {
+    // The start of a trend never changes, so every update of the trend keeps the same id
+    pub fn uid(&self) -> Uuid {
+        stable_id(&["trend", self.symbol, self.timerange, &self.start_time.timestamp_micros().to_string()])
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize)]
 pub struct Subtrend {
     pub start_time: DateTime<Utc>,
-    pub direction: &'static str,
+    #[serde(deserialize_with = "interned")]
+    pub direction: Interned,
     pub high: f64,
     pub low: f64,
     pub last_relative_low: f64,
@@ -0,0 +1,272 @@
+// Builds the non time based bars (volume, tick, range and renko) from the 1m candles
+// The bars are regular candles whose timerange is the label of the bar type,
+// So they go through the same storage, broadcast, fair value gap and trend processing
+
+use crate::{
+    config::BarConfig,
+    connections::database::add_candle,
+    entities::candle::get_direction,
+    handlers::{
+        candle::send_candle,
+        structures::{invalidate_zones, processfairvaluegap},
+        trends::process_trend,
+    },
+    BarKind,
+    BarType,
+    Candle,
+};
+
+use dashmap::DashMap;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// The bar being built for each "symbol-label"
+pub static BARS: Lazy<Arc<DashMap<String, BarState>>> = Lazy::new(|| {
+    Arc::new(DashMap::new())
+});
+
+// The bar types built by the pipeline, loaded from the configuration
+pub static BAR_TYPES: OnceCell<Vec<BarType>> = OnceCell::new();
+
+#[derive(Clone, Deserialize, Serialize)]
+pub struct BarState {
+    pub candle: Candle, // The bar being built (unused for renko)
+    pub count: u64,     // Number of 1m candles merged in the bar
+    pub high: f64,      // Top of the last renko brick
+    pub low: f64,       // Bottom of the last renko brick
+}
+
+pub fn init_bar_types(configs: &[BarConfig]) -> Result<(), String> {
+    let bar_types = configs
+        .iter()
+        .map(|config| BarType::new(config.kind, config.size))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    BAR_TYPES.set(bar_types).map_err(|_| "Bar types already initialized")?;
+    Ok(())
+}
+
+pub fn get_bar_types() -> &'static [BarType] {
+    BAR_TYPES.get().map(|b| b.as_slice()).unwrap_or(&[])
+}
+
+// Adds a 1m candle to the bar of the given type
+pub async fn aggregate_bar(candle: Arc<Candle>, symbol: &'static str, bar_type: &'static BarType) {
+    let bars = match bar_type.kind {
+        BarKind::Renko => build_renko_bricks(&candle, symbol, bar_type),
+        _ => build_bar(&candle, symbol, bar_type),
+    };
+
+    for bar in bars {
+        let bar = Arc::new(bar);
+
+        if bar.complete {
+            close_bar(&bar, symbol, bar_type).await;
+        } else if let Err(e) = send_candle(&bar).await {
+            eprintln!("Failed to send bar to websocket: {}", e);
+        }
+
+        process_trend(Arc::clone(&bar), symbol, bar_type.label).await
+            .unwrap_or_else(|e| eprintln!("Failed to process trend: {}", e));
+    }
+}
+
+// Stores, broadcasts and looks for fair value gaps in a finished bar
+// (after invalidating the zones it went through)
+async fn close_bar(bar: &Arc<Candle>, symbol: &'static str, bar_type: &BarType) {
+    if let Err(e) = add_candle(bar).await {
+        eprintln!("Failed to add bar to database: {}", e);
+    }
+
+    if let Err(e) = send_candle(bar).await {
+        eprintln!("Failed to send bar to websocket: {}", e);
+    }
+
+    if let Err(e) = invalidate_zones(bar, symbol, bar_type.label).await {
+        eprintln!("Failed to invalidate zones: {}", e);
+    }
+
+    if let Err(e) = processfairvaluegap(Arc::clone(bar), symbol, bar_type.label).await {
+        eprintln!("Failed to process fair value gap: {}", e);
+    }
+}
+
+// Merges the candle in the current volume, tick or range bar
+// And returns the bar, marked as complete if its threshold has been reached
+fn build_bar(candle: &Candle, symbol: &'static str, bar_type: &BarType) -> Vec<Candle> {
+    let key = format!("{}-{}", symbol, bar_type.label);
+
+    let mut state = match BARS.get(&key) {
+        // Keep building the current bar
+        Some(state) if !state.candle.complete => {
+            let mut state = state.clone();
+
+            state.candle.high = state.candle.high.max(candle.high);
+            state.candle.low = state.candle.low.min(candle.low);
+            state.candle.close = candle.close;
+            state.candle.volume += candle.volume;
+            state.count += 1;
+
+            state
+        }
+        // The last bar is finished (or it's the first one), so we open a new one
+        _ => {
+            let mut bar = candle.clone();
+            bar.timerange = bar_type.label;
+            bar.complete = false;
+
+            BarState { candle: bar, count: 1, high: 0.0, low: 0.0 }
+        }
+    };
+
+    state.candle.complete = match bar_type.kind {
+        BarKind::Volume => state.candle.volume >= bar_type.size,
+        BarKind::Tick => state.count as f64 >= bar_type.size,
+        BarKind::Range => state.candle.high - state.candle.low >= bar_type.size,
+        BarKind::Renko => unreachable!("Renko bricks are built by build_renko_bricks"),
+    };
+
+    // The direction depends on the whole bar, not on its first candle
+    state.candle.direction = get_direction(state.candle.open, state.candle.close);
+
+    let bar = state.candle.clone();
+    BARS.insert(key, state);
+
+    vec![bar]
+}
+
+// Returns the renko bricks completed by the candle's close (if any)
+// A new brick is drawn when the close moves `size` above the top or below the bottom of the last brick,
+// So a reversal needs a move of two bricks from the last close
+fn build_renko_bricks(candle: &Candle, symbol: &'static str, bar_type: &BarType) -> Vec<Candle> {
+    let key = format!("{}-{}", symbol, bar_type.label);
+
+    let mut state = match BARS.get(&key) {
+        Some(state) => state.clone(),
+        // The first close is the reference of the bricks
+        None => {
+            let mut reference = candle.clone();
+            reference.timerange = bar_type.label;
+            reference.volume = 0.0;
+
+            BarState { candle: reference, count: 0, high: candle.close, low: candle.close }
+        }
+    };
+
+    let mut bricks = Vec::new();
+    let mut volume = state.candle.volume + candle.volume;
+
+    loop {
+        let (open, close) = if candle.close >= state.high + bar_type.size {
+            (state.high, state.high + bar_type.size)
+        } else if candle.close <= state.low - bar_type.size {
+            (state.low, state.low - bar_type.size)
+        } else {
+            break;
+        };
+
+        // Several bricks can come from the same candle
+        // They all have its timestamp, and are told apart by their sequence
+        let mut brick = Candle::new(symbol, bar_type.label, candle.timestamp, open, open.max(close), open.min(close), close, volume);
+        brick.sequence = bricks.len() as i32;
+        bricks.push(brick);
+
+        // Only the first brick gets the volume traded since the last one
+        volume = 0.0;
+
+        state.high = open.max(close);
+        state.low = open.min(close);
+    }
+
+    state.candle.volume = volume;
+    state.count += 1;
+    BARS.insert(key, state);
+
+    bricks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::DateTime;
+
+    // Each test has its own symbol, the bars are shared by the tests running in parallel
+    fn candle(symbol: &'static str, minute: i64, open: f64, high: f64, low: f64, close: f64) -> Candle {
+        let timestamp = DateTime::from_timestamp(1_700_000_040 + minute * 60, 0).unwrap();
+        Candle::new(symbol, "1min", timestamp, open, high, low, close, 10.0)
+    }
+
+    #[test]
+    fn tick_bars() {
+        let bar_type = BarType::new(BarKind::Tick, 3.0).unwrap();
+
+        let first = build_bar(&candle("BAR_TICK", 0, 1.0, 1.2, 0.9, 1.1), "BAR_TICK", &bar_type);
+        assert!(!first[0].complete);
+        assert_eq!(first[0].timerange, "tick-3");
+
+        build_bar(&candle("BAR_TICK", 1, 1.1, 1.3, 1.0, 1.2), "BAR_TICK", &bar_type);
+        let bar = build_bar(&candle("BAR_TICK", 2, 1.2, 1.25, 0.8, 0.95), "BAR_TICK", &bar_type).remove(0);
+
+        assert!(bar.complete);
+        assert_eq!((bar.open, bar.high, bar.low, bar.close, bar.volume), (1.0, 1.3, 0.8, 0.95, 30.0));
+        assert_eq!(bar.timestamp, candle("BAR_TICK", 0, 0.0, 0.0, 0.0, 0.0).timestamp);
+        assert_eq!(bar.direction, "bearish");
+
+        // The next candle opens a new bar
+        let next = build_bar(&candle("BAR_TICK", 3, 0.95, 1.0, 0.9, 1.0), "BAR_TICK", &bar_type).remove(0);
+        assert!(!next.complete);
+        assert_eq!((next.open, next.volume), (0.95, 10.0));
+    }
+
+    #[test]
+    fn range_bars() {
+        let bar_type = BarType::new(BarKind::Range, 0.5).unwrap();
+
+        let bar = build_bar(&candle("BAR_RANGE", 0, 1.0, 1.25, 1.0, 1.1), "BAR_RANGE", &bar_type).remove(0);
+        assert!(!bar.complete);
+
+        let bar = build_bar(&candle("BAR_RANGE", 1, 1.1, 1.375, 1.05, 1.2), "BAR_RANGE", &bar_type).remove(0);
+        assert!(!bar.complete);
+
+        let bar = build_bar(&candle("BAR_RANGE", 2, 1.2, 1.5, 1.125, 1.375), "BAR_RANGE", &bar_type).remove(0);
+        assert!(bar.complete);
+        assert_eq!((bar.high, bar.low, bar.close), (1.5, 1.0, 1.375));
+        assert_eq!(bar.direction, "bullish");
+    }
+
+    #[test]
+    fn renko_bricks() {
+        let bar_type = BarType::new(BarKind::Renko, 1.0).unwrap();
+        let bricks = |minute, close| {
+            let bricks = build_renko_bricks(&candle("BAR_RENKO", minute, close, close, close, close), "BAR_RENKO", &bar_type);
+            bricks.iter().map(|b| (b.open, b.close, b.sequence, b.volume)).collect::<Vec<_>>()
+        };
+
+        // The first close is the reference
+        assert!(bricks(0, 10.0).is_empty());
+        assert!(bricks(1, 10.9).is_empty());
+
+        // Several bricks from the same candle, only the first one gets the volume
+        assert_eq!(bricks(2, 13.2), [(10.0, 11.0, 0, 30.0), (11.0, 12.0, 1, 0.0), (12.0, 13.0, 2, 0.0)]);
+
+        // A reversal needs a move of two bricks
+        assert!(bricks(3, 11.5).is_empty());
+        assert_eq!(bricks(4, 10.9), [(12.0, 11.0, 0, 20.0)]);
+        assert_eq!(bricks(5, 9.0), [(11.0, 10.0, 0, 10.0), (10.0, 9.0, 1, 0.0)]);
+    }
+
+    #[test]
+    fn renko_bricks_of_a_candle_have_distinct_ids() {
+        let bar_type = BarType::new(BarKind::Renko, 1.0).unwrap();
+
+        build_renko_bricks(&candle("BAR_RENKO_IDS", 0, 1.0, 1.0, 1.0, 1.0), "BAR_RENKO_IDS", &bar_type);
+        let bricks = build_renko_bricks(&candle("BAR_RENKO_IDS", 1, 1.0, 4.0, 1.0, 4.0), "BAR_RENKO_IDS", &bar_type);
+
+        assert_eq!(bricks.len(), 3);
+        assert!(bricks.iter().all(|b| b.timestamp == bricks[0].timestamp));
+        assert_ne!(bricks[0].uid(), bricks[1].uid());
+        assert_ne!(bricks[1].uid(), bricks[2].uid());
+    }
+}
\ No newline at end of file
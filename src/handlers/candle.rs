@@ -1,21 +1,37 @@
 use crate::{
     Candle,
+    config::read_config,
+    entities::timerange::{
+        activate_timerange,
+        deactivate_timerange,
+        find_aggregated_timerange,
+        find_timerange,
+        get_timerange,
+        get_timeranges,
+        BASE_TIMERANGE,
+    },
     connections::{
-        database::add_candle,
+        database::{add_candle, get_candles_between},
+        messages::Payload, subscriptions::{MessageKind, Topic},
         websocket::send_message_to_clients,
+        writer::flush_writer,
     },
     handlers::{
-        structures::processfairvaluegap,
-        trends::process_trend
+        derived::{process_derived, DERIVED_CANDLES, DERIVED_SERIES},
+        pipeline::PIPELINE_LOCK,
+        structures::{invalidate_zones, processfairvaluegap, ACTIVE_ZONES, LAST_THREE_CANDLES},
+        trends::{process_trend, QUEUE, SUBTRENDS, TRENDS},
+        validation::LAST_CANDLES,
+        warmup::is_warming_up,
     },
     Timerange,
 };
 
-use chrono::{Utc, TimeZone};
-use dashmap::DashMap;
+use chrono::{DateTime, Utc, TimeZone};
+use dashmap::{mapref::entry::Entry, DashMap};
 use once_cell::sync::Lazy;
-use serde_json::{Map, to_value, Value};
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
+use tokio::signal::unix::{signal, SignalKind};
 
 // Here we're using DashMap to allow concurrent access to the candles
 // Because we are sure that we won't use the same key in multiple threads
@@ -35,58 +51,49 @@ pub async fn aggregate_candle(candle: Arc<Candle>, symbol: &'static str, timeran
         .map(|c| Arc::clone(c.value()));
 
     let new_candle;
+    let replaced;
 
     // Check if it's the first candle for this timerange
     // If there is no last candle, we create a new one
     // If there is a last candle, we check if the new candle is in the same timerange 
     if let Some(last_candle) = last_candle {
         if last_candle.timestamp + chrono::Duration::milliseconds(timerange.duration_ms as i64) <= candle.timestamp {
-            // Send the candle to the db and check for errors
-            if let Err(e) = add_candle(&last_candle).await {
-                eprintln!("Failed to add candle to database: {}", e);
-            }
-
-            // Send the candle to the websocket
-            if let Err(e) = send_candle(&last_candle).await {
-                eprintln!("Failed to send candle to websocket: {}", e);
+            // The closer may already have finalized the last candle
+            if !last_candle.complete {
+                let closed_candle = completed(&last_candle);
+                if swap_candle(&key, Some(&last_candle), Arc::clone(&closed_candle)) {
+                    close_candle(closed_candle, symbol, timerange).await;
+                }
             }
 
-            // Search for fair value gaps
-            if let Err(e) = processfairvaluegap(Arc::clone(&last_candle), symbol, timerange).await {
-                eprintln!("Failed to process fair value gap: {}", e);
-            }
+            // Whoever closed it, the complete candle in the map is the one replaced
+            new_candle = Arc::new(open_candle(&candle, timerange));
+            replaced = CANDLES.get(key.as_str()).map(|c| Arc::clone(c.value()));
+        } else if last_candle.complete {
+            // The period has already been closed by the closer
+            // So this candle arrived after the grace period and is dropped
+            eprintln!("Dropping late candle {} for {} (period already closed)", candle.timestamp, key);
 
-            // Update the dashmap with the new candle (change the timerange)
-            let mut modified_candle = (*candle).clone();
-            modified_candle.timerange = timerange.label;
-
-            // Adjust the open price to match the timerange,
-            // So for example the open 
-            modified_candle.timestamp = Utc.timestamp_millis_opt((modified_candle.timestamp.timestamp_millis() / timerange.duration_ms as i64) * timerange.duration_ms as i64).single().expect("Failed to adjust timestamp");
-
-            new_candle = Arc::new(modified_candle);
+            return;
         } else {
             // If the new candle is in the same timerange
             // Take the last candle and update it with the new candle
             let mut modified_candle = (*last_candle).clone();
-
-            // Update the candle with the new values
-            modified_candle.high = modified_candle.high.max(candle.high);
-            modified_candle.low = modified_candle.low.min(candle.low);
-            modified_candle.close = candle.close;
-            modified_candle.volume += candle.volume;
+            merge_candle(&mut modified_candle, &candle);
 
             new_candle = Arc::new(modified_candle);
+            replaced = Some(last_candle);
         }
     } else {
-        // Don't forget to change the timerange of the candle
-        let mut candle = (*candle).clone();
-        candle.timerange = timerange.label;
-        // And adjust the timestamp to match the timerange
-        // This is done by rounding the timestamp to the nearest timerange duration
-        candle.timestamp = Utc.timestamp_millis_opt((candle.timestamp.timestamp_millis() / timerange.duration_ms as i64) * timerange.duration_ms as i64).single().expect("Failed to adjust timestamp");
+        new_candle = Arc::new(open_candle(&candle, timerange));
+        replaced = None;
+    }
+
+    // Update the candle in the DashMap, unless the closer finalized it in the meantime
+    if !swap_candle(&key, replaced.as_ref(), Arc::clone(&new_candle)) {
+        eprintln!("Dropping late candle {} for {} (period closed while aggregating)", candle.timestamp, key);
 
-        new_candle = Arc::new(candle);
+        return;
     }
 
     // Send the candle to the websocket
@@ -94,30 +101,301 @@ pub async fn aggregate_candle(candle: Arc<Candle>, symbol: &'static str, timeran
         eprintln!("Failed to send candle to websocket: {}", e);
     }
 
-    // Insert or update the candle in the DashMap
-    CANDLES
-        .entry(key)
-        .and_modify(|c| *c = Arc::clone(&new_candle))
-        .or_insert_with(|| Arc::clone(&new_candle));
+    // Update the series derived from this timerange
+    process_derived(&new_candle, symbol).await;
 
     process_trend(Arc::clone(&new_candle), symbol, timerange.label).await
         .unwrap_or_else(|e| eprintln!("Failed to process trend: {}", e));
 }
 
+// Updates an aggregated candle with a newer candle of the same period
+pub fn merge_candle(candle: &mut Candle, other: &Candle) {
+    candle.high = candle.high.max(other.high);
+    candle.low = candle.low.min(other.low);
+    candle.close = other.close;
+    candle.volume += other.volume;
+}
+
+// Creates the first (still partial) candle of a period from a 1-minute candle
+pub fn open_candle(candle: &Candle, timerange: &Timerange) -> Candle {
+    // Don't forget to change the timerange of the candle
+    let mut candle = candle.clone();
+    candle.timerange = timerange.label;
+    candle.complete = false;
+
+    // And adjust the timestamp to match the timerange
+    // This is done by rounding the timestamp down to the timerange duration
+    candle.timestamp = Utc.timestamp_millis_opt((candle.timestamp.timestamp_millis() / timerange.duration_ms as i64) * timerange.duration_ms as i64).single().expect("Failed to adjust timestamp");
+
+    candle
+}
+
+// Replaces the candle of a key only if it's still the expected one (None for no candle)
+// The aggregation and the closer both go through it, so a candle is closed once
+// And a complete candle is never overwritten by a partial one
+fn swap_candle(key: &str, expected: Option<&Arc<Candle>>, candle: Arc<Candle>) -> bool {
+    match CANDLES.entry(key.to_string()) {
+        Entry::Occupied(mut current) if expected.is_some_and(|e| Arc::ptr_eq(current.get(), e)) => {
+            current.insert(candle);
+            true
+        }
+        Entry::Vacant(vacant) if expected.is_none() => {
+            vacant.insert(candle);
+            true
+        }
+        _ => false,
+    }
+}
+
+// The complete version of a partial candle
+fn completed(candle: &Candle) -> Arc<Candle> {
+    let mut closed_candle = candle.clone();
+    closed_candle.complete = true;
+
+    Arc::new(closed_candle)
+}
+
+// Finalizes a candle marked as complete: it's stored, broadcasted
+// And handed to the structure detection
+async fn close_candle(closed_candle: Arc<Candle>, symbol: &'static str, timerange: &Timerange) {
+    // Send the candle to the db and check for errors
+    if let Err(e) = add_candle(&closed_candle).await {
+        eprintln!("Failed to add candle to database: {}", e);
+    }
+
+    // Send the candle to the websocket
+    if let Err(e) = send_candle(&closed_candle).await {
+        eprintln!("Failed to send candle to websocket: {}", e);
+    }
+
+    // Finish the series derived from this timerange
+    process_derived(&closed_candle, symbol).await;
+
+    // Invalidate the zones the candle went through
+    if let Err(e) = invalidate_zones(&closed_candle, symbol, timerange.label).await {
+        eprintln!("Failed to invalidate zones: {}", e);
+    }
+
+    // Search for fair value gaps
+    if let Err(e) = processfairvaluegap(closed_candle, symbol, timerange.label).await {
+        eprintln!("Failed to process fair value gap: {}", e);
+    }
+}
+
+// Periodically closes the candles whose period is over
+// So a candle doesn't stay open through a data gap or a market close
+pub async fn run_candle_closer(grace_period: Duration, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+
+    loop {
+        ticker.tick().await;
+
+        // The replayed candles are old, they would all be closed
+        if is_warming_up() {
+            continue;
+        }
+
+        let deadline = Utc::now() - chrono::Duration::milliseconds(grace_period.as_millis() as i64);
+        close_expired_candles(deadline).await;
+    }
+}
+
+// Closes every open candle whose period ended before the deadline
+pub async fn close_expired_candles(deadline: DateTime<Utc>) {
+    let _guard = PIPELINE_LOCK.read().await;
+
+    // Collect the expired candles first
+    // So we don't hold any lock on the DashMap while awaiting
+    let expired: Vec<(String, Arc<Candle>, &'static Timerange)> = CANDLES
+        .iter()
+        .filter(|c| !c.complete)
+        .filter_map(|c| {
+            let timerange = get_timerange(c.timerange)?;
+            let end = c.timestamp + chrono::Duration::milliseconds(timerange.duration_ms as i64);
+
+            (end <= deadline).then(|| (c.key().clone(), Arc::clone(c.value()), timerange))
+        })
+        .collect();
+
+    for (key, candle, timerange) in expired {
+        // Mark the candle as complete in the map
+        // Unless it has been replaced since we collected it
+        let closed_candle = completed(&candle);
+        if swap_candle(&key, Some(&candle), Arc::clone(&closed_candle)) {
+            close_candle(closed_candle, candle.symbol, timerange).await;
+        }
+    }
+}
+
 // Sends a candle to the connected WebSocket clients.
-// By converting the candle to a JSON string, we can send it over the WebSocket connection.
 pub async fn send_candle(candle: &Candle) -> Result<(), String> {
-    let mut data = Map::new();
+    let (topic, message) = candle_message(candle);
+    send_message_to_clients(topic, message).await?;
+
+    Ok(())
+}
+
+// Builds the message of a candle (also used to replay the candles to a new client)
+pub fn candle_message(candle: &Candle) -> (Topic, Payload) {
+    let topic = Topic {
+        kind: MessageKind::Candle,
+        symbol: candle.symbol,
+        timerange: Some(candle.timerange),
+        structure: None,
+    };
+
+    (topic, Payload::candle(candle))
+}
+
+// Starts aggregating a new timerange while the pipeline is running
+// The open candle of each symbol is rebuilt from the stored 1m candles first,
+// So the first candle of the new timerange isn't truncated
+// The pipeline has to be paused (see PIPELINE_LOCK) while it's added
+pub async fn add_timerange(label: &str) -> Result<&'static Timerange, String> {
+    let timerange = find_aggregated_timerange(label)?;
+
+    if get_timerange(timerange.label).is_some() {
+        return Err(format!("Timerange {} is already active", timerange.label));
+    }
+
+    // The 1m candles still buffered by the writer are read back below
+    flush_writer().await?;
+
+    for symbol in known_symbols() {
+        if let Some(candle) = bootstrap_candle(symbol, timerange).await? {
+            CANDLES.insert(format!("{}-{}", symbol, timerange.label), Arc::new(candle));
+        }
+    }
+
+    activate_timerange(timerange)?;
+
+    Ok(timerange)
+}
+
+// Stops aggregating a timerange and drops its state, and the state of the series derived from it
+// The candle being built is discarded, since its period isn't over
+// The pipeline has to be paused (see PIPELINE_LOCK) while it's removed
+pub fn remove_timerange(label: &str) -> Result<&'static Timerange, String> {
+    let timerange = find_timerange(label)?;
+
+    // The other timeranges are built from its candles
+    if timerange.label == BASE_TIMERANGE {
+        return Err(format!("The base timerange {} can't be removed", BASE_TIMERANGE));
+    }
+
+    deactivate_timerange(timerange.label)?;
+
+    let derived = DERIVED_SERIES
+        .get()
+        .into_iter()
+        .flatten()
+        .filter(|series| series.timerange == timerange.label);
+
+    for symbol in known_symbols() {
+        let key = format!("{}-{}", symbol, timerange.label);
+        CANDLES.remove(&key);
+        clear_structures(&key);
+
+        for series in derived.clone() {
+            let key = format!("{}-{}", symbol, series.label);
+            DERIVED_CANDLES.remove(&key);
+            clear_structures(&key);
+        }
+    }
+
+    Ok(timerange)
+}
+
+// Drops the trend and structure detection state of a "symbol-timerange"
+fn clear_structures(key: &str) {
+    LAST_THREE_CANDLES.remove(key);
+    QUEUE.remove(key);
+    TRENDS.remove(key);
+    SUBTRENDS.remove(key);
+    ACTIVE_ZONES.remove(key);
+}
+
+// The symbols that went through the pipeline
+fn known_symbols() -> Vec<&'static str> {
+    LAST_CANDLES.iter().map(|c| c.symbol).collect()
+}
 
-    // Structure the data to send
-    data.insert("type".to_string(), Value::String("candle".to_string()));
-    data.insert("value".to_string(), to_value(candle).unwrap());
+// Applies the timeranges of the configuration file again each time the process receives SIGHUP,
+// So they can be added or removed without restarting (the other settings still need a restart)
+pub async fn run_timerange_reloader() -> Result<(), String> {
+    let mut hangups = signal(SignalKind::hangup())
+        .map_err(|e| format!("Failed to listen for SIGHUP: {}", e))?;
 
-    // Convert the data to a JSON string
-    let json_data = Value::Object(data).to_string();
+    while hangups.recv().await.is_some() {
+        if let Err(e) = reload_timeranges().await {
+            eprintln!("Failed to reload the timeranges: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+// Starts the configured timeranges that aren't aggregated yet, and stops the ones that aren't configured anymore
+// The base timerange is kept, the others are built from it
+async fn reload_timeranges() -> Result<(), String> {
+    let config = read_config()?;
+    let _guard = PIPELINE_LOCK.write().await;
+
+    // Every label is checked before anything changes
+    let configured = config.timeranges
+        .iter()
+        .map(|label| find_aggregated_timerange(label).map(|timerange| timerange.label))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    for timerange in get_timeranges() {
+        if timerange.label != BASE_TIMERANGE && !configured.contains(&timerange.label) {
+            remove_timerange(timerange.label)?;
+            eprintln!("Stopped aggregating {}", timerange.label);
+        }
+    }
 
-    // Send the data to the clients
-    send_message_to_clients(&json_data).await?;
+    for label in configured {
+        if get_timerange(label).is_none() {
+            add_timerange(label).await?;
+            eprintln!("Started aggregating {}", label);
+        }
+    }
 
     Ok(())
+}
+
+// Rebuilds the open candle of a timerange from the stored 1m candles of its current period
+// The current period is the one of the last 1m candle processed (or now if there is none)
+async fn bootstrap_candle(symbol: &'static str, timerange: &Timerange) -> Result<Option<Candle>, String> {
+    // The 1m candle being built isn't stored yet, so we take it from the map
+    let current = CANDLES
+        .get(format!("{}-{}", symbol, BASE_TIMERANGE).as_str())
+        .map(|c| Arc::clone(c.value()));
+
+    let now = current
+        .as_ref()
+        .map(|c| c.timestamp)
+        .unwrap_or_else(Utc::now);
+
+    let duration = timerange.duration_ms as i64;
+    let period_start = Utc.timestamp_millis_opt((now.timestamp_millis() / duration) * duration)
+        .single()
+        .ok_or("Failed to compute the period start")?;
+
+    let mut candles = get_candles_between(symbol, BASE_TIMERANGE, period_start, now).await?;
+    if let Some(current) = current {
+        candles.push((*current).clone());
+    }
+
+    let mut candles = candles.into_iter();
+    let Some(first) = candles.next() else {
+        return Ok(None);
+    };
+
+    let mut candle = open_candle(&first, timerange);
+    for other in candles {
+        merge_candle(&mut candle, &other);
+    }
+
+    Ok(Some(candle))
 }
\ No newline at end of file
@@ -0,0 +1,195 @@
+// Checkpoints of the engine state
+// The detectors keep their state in memory (open candles, last candles, trends...),
+// A snapshot of it is written periodically so a restarted process can resume where it stopped
+// Instead of emitting wrong structures until it has seen enough candles again
+
+use crate::{
+    config::CheckpointConfig,
+    connections::{storage::get_storage, writer::flush_writer},
+    entities::timerange::{get_timeranges, set_timeranges},
+    handlers::{
+        bars::{BarState, BARS},
+        candle,
+        derived::DERIVED_CANDLES,
+        pipeline::PIPELINE_LOCK,
+        sessions::SESSION,
+        structures::{ACTIVE_ZONES, LAST_THREE_CANDLES},
+        trends,
+        validation::LAST_CANDLES,
+    },
+    Candle,
+    Session,
+    Subtrend,
+    Trend,
+    TwoDStructures,
+};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fs, hash::Hash, sync::Arc, time::Duration};
+
+// Bumped when the content of the snapshot changes
+// Snapshots of another version are ignored
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckpointTarget {
+    File,     // A JSON file (see `path`)
+    Database, // The configured storage
+}
+
+// Everything the detectors need to carry on, keyed like the global maps
+#[derive(Deserialize, Serialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    // The timeranges added or removed at runtime are part of the state
+    pub timeranges: Vec<String>,
+    pub candles: HashMap<String, Candle>,
+    pub bars: HashMap<String, BarState>,
+    pub derived_candles: HashMap<String, Candle>,
+    pub sessions: HashMap<String, Session>,
+    pub last_three_candles: HashMap<String, Vec<Candle>>,
+    pub last_candles: HashMap<String, Candle>,
+    pub trend_queue: HashMap<String, Vec<Candle>>,
+    pub trend_candles: HashMap<String, Candle>,
+    pub trends: HashMap<String, Trend>,
+    pub subtrends: HashMap<String, Subtrend>,
+    // Missing from the first snapshots
+    #[serde(default)]
+    pub active_zones: HashMap<String, Vec<TwoDStructures>>,
+}
+
+// Copies the state of the engine
+// The pipeline is paused meanwhile, so the snapshot is consistent
+pub async fn take_snapshot() -> Snapshot {
+    let _guard = PIPELINE_LOCK.write().await;
+
+    snapshot()
+}
+
+fn snapshot() -> Snapshot {
+    Snapshot {
+        version: SNAPSHOT_VERSION,
+        created_at: Utc::now(),
+        timeranges: get_timeranges().iter().map(|t| t.label.to_string()).collect(),
+        candles: copy(&candle::CANDLES, |c| (**c).clone()),
+        bars: copy(&BARS, BarState::clone),
+        derived_candles: copy(&DERIVED_CANDLES, |c| (**c).clone()),
+        sessions: copy(&SESSION, Session::clone),
+        last_three_candles: copy(&LAST_THREE_CANDLES, |candles| candles.iter().map(|c| (**c).clone()).collect()),
+        last_candles: copy(&LAST_CANDLES, Candle::clone),
+        trend_queue: copy(&trends::QUEUE, |candles| candles.iter().map(|c| (**c).clone()).collect()),
+        trend_candles: copy(&trends::CANDLES, |c| (**c).clone()),
+        trends: copy(&trends::TRENDS, |t| (**t).clone()),
+        subtrends: copy(&trends::SUBTRENDS, |s| (**s).clone()),
+        active_zones: copy(&ACTIVE_ZONES, Vec::clone),
+    }
+}
+
+// Replaces the state of the engine with the snapshot
+pub async fn restore_snapshot(snapshot: Snapshot) -> Result<(), String> {
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(format!("Unsupported snapshot version {} (expected {})", snapshot.version, SNAPSHOT_VERSION));
+    }
+
+    let _guard = PIPELINE_LOCK.write().await;
+
+    set_timeranges(&snapshot.timeranges)?;
+
+    fill(&candle::CANDLES, snapshot.candles, Arc::new);
+    fill(&BARS, snapshot.bars, |b| b);
+    fill(&DERIVED_CANDLES, snapshot.derived_candles, Arc::new);
+    fill(&SESSION, snapshot.sessions, |s| s);
+    fill(&LAST_THREE_CANDLES, snapshot.last_three_candles, |candles| candles.into_iter().map(Arc::new).collect());
+    fill(&LAST_CANDLES, snapshot.last_candles, |c| c);
+    fill(&trends::QUEUE, snapshot.trend_queue, |candles| candles.into_iter().map(Arc::new).collect());
+    fill(&trends::CANDLES, snapshot.trend_candles, Arc::new);
+    fill(&trends::TRENDS, snapshot.trends, Arc::new);
+    fill(&trends::SUBTRENDS, snapshot.subtrends, Arc::new);
+    fill(&ACTIVE_ZONES, snapshot.active_zones, |z| z);
+
+    Ok(())
+}
+
+// Writes a checkpoint to the configured target
+// The rows produced before are written first, so the stored history is never behind the checkpoint
+pub async fn save_checkpoint(config: &CheckpointConfig) -> Result<(), String> {
+    let snapshot = {
+        let _guard = PIPELINE_LOCK.write().await;
+
+        flush_writer().await?;
+        snapshot()
+    };
+    let state = serde_json::to_string(&snapshot).map_err(|e| format!("Failed to serialize snapshot: {}", e))?;
+
+    match config.target {
+        CheckpointTarget::File => {
+            // Written next to the checkpoint and renamed, so a crash never leaves a truncated file
+            let temporary = format!("{}.tmp", config.path);
+
+            fs::write(&temporary, state).map_err(|e| format!("Failed to write checkpoint {}: {}", temporary, e))?;
+            fs::rename(&temporary, &config.path).map_err(|e| format!("Failed to write checkpoint {}: {}", config.path, e))?;
+        }
+        CheckpointTarget::Database => get_storage()?.save_checkpoint(&state).await?,
+    }
+
+    Ok(())
+}
+
+// Loads the last checkpoint and restores it
+// Returns the time of the last candle processed for each symbol (None when there is no checkpoint yet),
+// The input up to it has already been processed
+pub async fn restore_checkpoint(config: &CheckpointConfig) -> Result<Option<HashMap<String, DateTime<Utc>>>, String> {
+    let state = match config.target {
+        CheckpointTarget::File => match fs::read_to_string(&config.path) {
+            Ok(state) => Some(state),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("Failed to read checkpoint {}: {}", config.path, e)),
+        },
+        CheckpointTarget::Database => get_storage()?.load_checkpoint().await?,
+    };
+
+    let Some(state) = state else {
+        return Ok(None);
+    };
+
+    let snapshot: Snapshot = serde_json::from_str(&state).map_err(|e| format!("Failed to parse checkpoint: {}", e))?;
+    let created_at = snapshot.created_at;
+    let processed = snapshot.last_candles.iter().map(|(symbol, candle)| (symbol.clone(), candle.timestamp)).collect();
+
+    restore_snapshot(snapshot).await?;
+    println!("Resumed from the checkpoint of {}", created_at);
+
+    Ok(Some(processed))
+}
+
+// Writes a checkpoint every interval
+pub async fn run_checkpoints(config: CheckpointConfig) {
+    let mut ticker = tokio::time::interval(Duration::from_millis(config.interval_ms.max(1)));
+
+    // The first tick completes immediately, there is nothing to save yet
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+
+        if let Err(e) = save_checkpoint(&config).await {
+            eprintln!("Failed to write checkpoint: {}", e);
+        }
+    }
+}
+
+fn copy<K: Clone + Eq + Hash, V, T>(map: &DashMap<K, V>, convert: impl Fn(&V) -> T) -> HashMap<K, T> {
+    map.iter().map(|entry| (entry.key().clone(), convert(entry.value()))).collect()
+}
+
+fn fill<K: Eq + Hash, V, T>(map: &DashMap<K, V>, values: HashMap<K, T>, convert: impl Fn(T) -> V) {
+    map.clear();
+
+    for (key, value) in values {
+        map.insert(key, convert(value));
+    }
+}
\ No newline at end of file
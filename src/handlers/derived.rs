@@ -0,0 +1,194 @@
+// Derived series computed on top of the aggregated candles (Heikin-Ashi, smoothed candles)
+// Each series is stored and broadcasted as its own timerange (e.g. "4h-ha"),
+// And can be fed to the trend engine instead of (or along with) the regular candles
+
+use crate::{
+    config::DerivedConfig,
+    connections::database::add_candle,
+    entities::{candle::get_direction, timerange::find_timerange},
+    handlers::{candle::send_candle, trends::process_trend},
+    utils::utils::intern,
+    Candle,
+};
+
+use dashmap::DashMap;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Deserialize;
+use std::sync::Arc;
+
+// The last finished derived candle of each "symbol-label"
+// It's the reference used to derive the next one
+pub static DERIVED_CANDLES: Lazy<Arc<DashMap<String, Arc<Candle>>>> = Lazy::new(|| {
+    Arc::new(DashMap::new())
+});
+
+// The derived series built by the pipeline, loaded from the configuration
+pub static DERIVED_SERIES: OnceCell<Vec<DerivedSeries>> = OnceCell::new();
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(tag = "transform", rename_all = "kebab-case")]
+pub enum Transform {
+    HeikinAshi,
+    // Exponential smoothing of the open, high, low and close over `period` candles
+    Smoothed { period: u32 },
+}
+
+pub struct DerivedSeries {
+    pub timerange: &'static str, // The timerange the series is derived from
+    pub label: &'static str,     // Used as the timerange of the derived candles
+    pub transform: Transform,
+    pub trends: bool,            // Whether the trend engine runs on the series
+}
+
+pub fn init_derived_series(configs: &[DerivedConfig]) -> Result<(), String> {
+    let mut series = Vec::new();
+
+    for config in configs {
+        let timerange = find_timerange(&config.timerange)?;
+
+        let suffix = match config.transform {
+            Transform::HeikinAshi => "ha".to_string(),
+            Transform::Smoothed { period: 0 } => return Err("Smoothing period must be at least 1".to_string()),
+            Transform::Smoothed { period } => format!("smoothed{}", period),
+        };
+
+        series.push(DerivedSeries {
+            timerange: timerange.label,
+            label: intern(&format!("{}-{}", timerange.label, suffix)),
+            transform: config.transform,
+            trends: config.trends,
+        });
+    }
+
+    DERIVED_SERIES.set(series).map_err(|_| "Derived series already initialized")?;
+    Ok(())
+}
+
+// Derives every configured series from an updated (or finished) candle
+// Finished candles are stored and become the reference of the next derived candle
+pub async fn process_derived(candle: &Candle, symbol: &'static str) {
+    let Some(series) = DERIVED_SERIES.get() else {
+        return;
+    };
+
+    for series in series.iter().filter(|s| s.timerange == candle.timerange) {
+        let key = format!("{}-{}", symbol, series.label);
+
+        let previous = DERIVED_CANDLES
+            .get(&key)
+            .map(|c| Arc::clone(c.value()));
+
+        let derived = Arc::new(derive(candle, previous.as_deref(), series));
+
+        if derived.complete {
+            DERIVED_CANDLES.insert(key, Arc::clone(&derived));
+
+            if let Err(e) = add_candle(&derived).await {
+                eprintln!("Failed to add derived candle to database: {}", e);
+            }
+        }
+
+        if let Err(e) = send_candle(&derived).await {
+            eprintln!("Failed to send derived candle to websocket: {}", e);
+        }
+
+        // Like the regular candles, the trend engine is fed with the candles being built
+        if series.trends && !derived.complete {
+            process_trend(Arc::clone(&derived), symbol, series.label).await
+                .unwrap_or_else(|e| eprintln!("Failed to process trend: {}", e));
+        }
+    }
+}
+
+// Computes the derived candle from the source candle and the previous derived candle
+fn derive(candle: &Candle, previous: Option<&Candle>, series: &DerivedSeries) -> Candle {
+    let mut derived = candle.clone();
+    derived.timerange = series.label;
+
+    match series.transform {
+        Transform::HeikinAshi => {
+            derived.close = (candle.open + candle.high + candle.low + candle.close) / 4.0;
+            derived.open = match previous {
+                Some(previous) => (previous.open + previous.close) / 2.0,
+                None => (candle.open + candle.close) / 2.0,
+            };
+        }
+        Transform::Smoothed { period } => {
+            if let Some(previous) = previous {
+                let alpha = 2.0 / (period as f64 + 1.0);
+                let smooth = |value: f64, previous: f64| alpha * value + (1.0 - alpha) * previous;
+
+                derived.open = smooth(candle.open, previous.open);
+                derived.high = smooth(candle.high, previous.high);
+                derived.low = smooth(candle.low, previous.low);
+                derived.close = smooth(candle.close, previous.close);
+            }
+        }
+    }
+
+    // The shadows have to contain the body
+    derived.high = derived.high.max(derived.open).max(derived.close);
+    derived.low = derived.low.min(derived.open).min(derived.close);
+    derived.direction = get_direction(derived.open, derived.close);
+
+    derived
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use chrono::DateTime;
+
+    fn candle(open: f64, high: f64, low: f64, close: f64) -> Candle {
+        let timestamp = DateTime::from_timestamp(1_700_000_040, 0).unwrap();
+        Candle::new("DERIVED", "1h", timestamp, open, high, low, close, 10.0)
+    }
+
+    fn series(transform: Transform) -> DerivedSeries {
+        DerivedSeries { timerange: "1h", label: "1h-test", transform, trends: false }
+    }
+
+    #[test]
+    fn heikin_ashi_without_previous_candle() {
+        let derived = derive(&candle(1.0, 2.0, 0.5, 1.5), None, &series(Transform::HeikinAshi));
+
+        assert_eq!(derived.timerange, "1h-test");
+        assert_eq!((derived.open, derived.high, derived.low, derived.close), (1.25, 2.0, 0.5, 1.25));
+        assert_eq!(derived.volume, 10.0);
+    }
+
+    #[test]
+    fn heikin_ashi_opens_at_the_middle_of_the_previous_body() {
+        let previous = candle(1.0, 3.0, 0.5, 2.0);
+        let derived = derive(&candle(2.0, 2.5, 1.5, 2.25), Some(&previous), &series(Transform::HeikinAshi));
+
+        assert_eq!(derived.open, 1.5);
+        assert_eq!(derived.close, 2.0625);
+        assert_eq!(derived.direction, "bullish");
+    }
+
+    #[test]
+    fn heikin_ashi_shadows_contain_the_body() {
+        // The open comes from the previous candle, above the high of the current one
+        let previous = candle(4.0, 4.0, 4.0, 4.0);
+        let derived = derive(&candle(1.0, 1.5, 0.5, 1.0), Some(&previous), &series(Transform::HeikinAshi));
+
+        assert_eq!((derived.open, derived.high, derived.low, derived.close), (4.0, 4.0, 0.5, 1.0));
+        assert_eq!(derived.direction, "bearish");
+    }
+
+    #[test]
+    fn smoothed_candles() {
+        let series = series(Transform::Smoothed { period: 3 });
+
+        // The first candle is the start of the smoothing
+        let first = derive(&candle(1.0, 2.0, 0.5, 1.5), None, &series);
+        assert_eq!((first.open, first.high, first.low, first.close), (1.0, 2.0, 0.5, 1.5));
+
+        // With a period of 3, each value moves halfway to the new one
+        let second = derive(&candle(2.0, 3.0, 1.5, 2.5), Some(&first), &series);
+        assert_eq!((second.open, second.high, second.low, second.close), (1.5, 2.5, 1.0, 2.0));
+        assert_eq!(second.direction, "bullish");
+    }
+}
\ No newline at end of file
@@ -1,4 +1,10 @@
+pub mod bars;
 pub mod candle;
+pub mod checkpoint;
+pub mod derived;
+pub mod pipeline;
 pub mod sessions;
 pub mod structures;
-pub mod trends;
\ No newline at end of file
+pub mod trends;
+pub mod validation;
+pub mod warmup;
\ No newline at end of file
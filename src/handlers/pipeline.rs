@@ -0,0 +1,82 @@
+// Entry point of the 1m candles in the detection engine
+// Each candle is validated, then aggregated in every timerange and bar type,
+// And used to update the current session
+
+use crate::{
+    config::get_config,
+    connections::messages::PipelineStatus,
+    entities::timerange::get_timeranges,
+    handlers::{
+        bars::{aggregate_bar, get_bar_types},
+        candle::aggregate_candle,
+        sessions::process_session,
+        validation::{validate_candle, LAST_CANDLES},
+        warmup::is_warming_up,
+    },
+    Candle,
+};
+
+use futures::future::join_all;
+use once_cell::sync::Lazy;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+// Held (shared) while a candle goes through the pipeline
+// Taking it exclusively pauses the pipeline, e.g. to snapshot the state of the engine
+pub static PIPELINE_LOCK: Lazy<RwLock<()>> = Lazy::new(|| RwLock::new(()));
+
+pub async fn process_candle(candle: Candle, symbol: &'static str) {
+    let _guard = PIPELINE_LOCK.read().await;
+
+    // The validation can drop the candle, repair it,
+    // Or add the missing candles before it
+    for candle in validate_candle(candle, &get_config().validation) {
+        process_valid_candle(Arc::new(candle), symbol).await;
+    }
+}
+
+async fn process_valid_candle(candle: Arc<Candle>, symbol: &'static str) {
+    // Spawn a task for each timerange to aggregate the candle
+    let mut handles = Vec::new();
+
+    // The timeranges are read for every candle
+    // So the ones added or removed at runtime are taken into account
+    for timerange in get_timeranges() {
+        let cloned_candle = Arc::clone(&candle);
+        let task = tokio::spawn(async move {
+            aggregate_candle(cloned_candle, symbol, timerange).await
+        });
+
+        handles.push(task);
+    }
+
+    // Same thing for the non time based bars
+    for bar_type in get_bar_types() {
+        let cloned_candle = Arc::clone(&candle);
+        let task = tokio::spawn(async move {
+            aggregate_bar(cloned_candle, symbol, bar_type).await
+        });
+
+        handles.push(task);
+    }
+
+    // And also spawn a task to process the session
+    let cloned_candle = Arc::clone(&candle);
+    let task = tokio::spawn(async move {
+        if let Err(e) = process_session(cloned_candle, symbol).await {
+            eprintln!("Error processing session: {}", e);
+        }
+    });
+    handles.push(task);
+
+    // Wait for all tasks to complete
+    let _ = join_all(handles).await;
+}
+
+// Sent to the clients in the heartbeats
+pub fn pipeline_status() -> PipelineStatus {
+    PipelineStatus {
+        warming_up: is_warming_up(),
+        last_candles: LAST_CANDLES.iter().map(|c| (c.key().clone(), c.timestamp)).collect(),
+    }
+}
\ No newline at end of file
@@ -1,5 +1,6 @@
 use crate::{
-    connections::database::add_session, utils::utils::is_in_timerange, Candle, ReferenceSession, Session, SESSIONS
+    connections::{database::add_session, messages::Payload, subscriptions::{MessageKind, Topic}, websocket::send_message_to_clients},
+    utils::utils::is_in_timerange, Candle, ReferenceSession, Session, SESSIONS
 };
 
 use chrono::{DateTime, NaiveDateTime, Timelike, Utc};
@@ -29,7 +30,7 @@ pub async fn process_session(candle: Arc<Candle>, symbol: &'static str) -> Resul
 
         // Create a new session
         let new_session = Session {
-            symbol: symbol,
+            symbol,
             label: session.label,
             start,
             end,
@@ -41,7 +42,7 @@ pub async fn process_session(candle: Arc<Candle>, symbol: &'static str) -> Resul
         };
 
         // Set the new session in the global state
-        SESSION.entry(key).and_modify(|e| {
+        SESSION.entry(key.clone()).and_modify(|e| {
             *e = new_session.clone();
         }).or_insert(new_session.clone());
 
@@ -64,9 +65,28 @@ pub async fn process_session(candle: Arc<Candle>, symbol: &'static str) -> Resul
         current_session.volume += candle.volume;
     }
 
+    // Copied so the DashMap isn't locked while sending
+    let session = SESSION.get(&key).map(|s| s.clone());
+    if let Some(session) = session {
+        let (topic, message) = session_message(&session);
+        send_message_to_clients(topic, message).await?;
+    }
+
     Ok(())
 }
 
+// Builds the message of the current session, sent each time it's updated
+pub fn session_message(session: &Session) -> (Topic, Payload) {
+    let topic = Topic {
+        kind: MessageKind::Session,
+        symbol: session.symbol,
+        timerange: None,
+        structure: None,
+    };
+
+    (topic, Payload::session(session))
+}
+
 pub async fn should_create_new_session(candle: Arc<Candle>) -> bool {
     // Check if the session is not initialized 
     // or if the current session is not the same as the candle's session
@@ -84,7 +104,7 @@ pub async fn should_create_new_session(candle: Arc<Candle>) -> bool {
     if let Some(session) = session.as_ref() {
         if !is_same_session(session, candle) {
             // Check if errors occurred while adding the session to the database
-            let Ok(()) = add_session(&session).await else {
+            let Ok(()) = add_session(session).await else {
                 eprintln!("Failed to add session to the database");
 
                 return true;
@@ -94,7 +114,7 @@ pub async fn should_create_new_session(candle: Arc<Candle>) -> bool {
         }
     }
 
-    return false;
+    false
 }
 
 // Checks if the candle's timestamp is within the session's start and end
@@ -1,10 +1,9 @@
 use crate::{
-    connections::{database::add_2_d_structures, websocket::send_message_to_clients}, entities::structures::TwoDStructures, Candle, OneDStructures, Timerange
+    connections::{database::add_2_d_structures, messages::Payload, subscriptions::{MessageKind, Topic}, websocket::send_message_to_clients}, entities::structures::TwoDStructures, Candle, OneDStructures
 };
 
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
-use serde_json::{Map, to_value, Value};
 use std::sync::Arc;
 
 // We need to store the last 3 candles of each symbol and timerange
@@ -13,36 +12,104 @@ pub static LAST_THREE_CANDLES: Lazy<Arc<DashMap<String, Vec<Arc<Candle>>>>> = La
     Arc::new(DashMap::new())
 });
 
-// This function sends a TwoDStructures entity to all connected clients via WebSocket
-pub async fn send_two_d_structure(structure: &TwoDStructures) -> Result<(), String> {
-    let mut data = Map::new();
+// The fair value gaps and order blocks that haven't been invalidated yet, for each "symbol-timerange"
+pub static ACTIVE_ZONES: Lazy<Arc<DashMap<String, Vec<TwoDStructures>>>> = Lazy::new(|| {
+    Arc::new(DashMap::new())
+});
+
+// The oldest zones stop being tracked past this number (they are never invalidated)
+const MAX_ACTIVE_ZONES: usize = 100;
+
+// Starts watching a new zone, so it can be invalidated later
+pub fn track_zone(structure: &TwoDStructures) {
+    let key = format!("{}-{}", structure.symbol, structure.timerange);
+    let mut zones = ACTIVE_ZONES.entry(key).or_default();
+
+    zones.push(structure.clone());
+    if zones.len() > MAX_ACTIVE_ZONES {
+        zones.remove(0);
+    }
+}
+
+// Invalidates the zones the closed candle went through:
+// A bullish zone once a candle closes below it, a bearish one once a candle closes above it
+// The invalidated zones are stored and sent again with their invalidation time
+pub async fn invalidate_zones(candle: &Candle, symbol: &'static str, timerange: &'static str) -> Result<(), String> {
+    let key = format!("{}-{}", symbol, timerange);
 
-    data.insert("type".to_string(), Value::String("Two dimension structure".to_string())); 
-    data.insert("value".to_string(), to_value(structure).unwrap());
+    let invalidated: Vec<TwoDStructures> = match ACTIVE_ZONES.get_mut(key.as_str()) {
+        Some(mut zones) => {
+            let (invalidated, active) = zones
+                .drain(..)
+                .partition(|zone| zone.timestamp < candle.timestamp && is_invalidated_by(zone, candle));
+
+            *zones = active;
+            invalidated
+        }
+        None => return Ok(()),
+    };
 
-    let json_data = Value::Object(data).to_string();
+    for mut zone in invalidated {
+        zone.invalidated_at = Some(candle.timestamp);
 
-    send_message_to_clients(&json_data).await?;
+        add_2_d_structures(&zone).await?;
+        send_two_d_structure(&zone).await?;
+    }
 
     Ok(())
 }
 
-// This function sends a OneDStructures entity to all connected clients via WebSocket
-pub async fn send_one_d_structure(structure: &OneDStructures) -> Result<(), String> {
-    let mut data = Map::new();
+fn is_invalidated_by(zone: &TwoDStructures, candle: &Candle) -> bool {
+    if zone.direction.eq_ignore_ascii_case("bullish") {
+        candle.close < zone.low
+    } else if zone.direction.eq_ignore_ascii_case("bearish") {
+        candle.close > zone.high
+    } else {
+        false
+    }
+}
+
+// This function sends a TwoDStructures entity to all connected clients via WebSocket
+pub async fn send_two_d_structure(structure: &TwoDStructures) -> Result<(), String> {
+    let (topic, message) = two_d_structure_message(structure);
+    send_message_to_clients(topic, message).await?;
+
+    Ok(())
+}
 
-    data.insert("type".to_string(), Value::String("One dimension structure".to_string())); 
-    data.insert("value".to_string(), to_value(structure).unwrap());
+pub fn two_d_structure_message(structure: &TwoDStructures) -> (Topic, Payload) {
+    let topic = Topic {
+        kind: MessageKind::TwoDStructure,
+        symbol: structure.symbol,
+        timerange: Some(structure.timerange),
+        structure: Some(structure.structure),
+    };
 
-    let json_data = Value::Object(data).to_string();
+    (topic, Payload::two_d_structure(structure))
+}
 
-    send_message_to_clients(&json_data).await?;
+// This function sends a OneDStructures entity to all connected clients via WebSocket
+pub async fn send_one_d_structure(structure: &OneDStructures) -> Result<(), String> {
+    let (topic, message) = one_d_structure_message(structure);
+    send_message_to_clients(topic, message).await?;
 
     Ok(())
 }
 
-pub async fn processfairvaluegap(candle: Arc<Candle>, symbol: &'static str, timerange: &Timerange) -> Result<(), String> {
-    let key = format!("{}-{}", symbol, timerange.label);
+pub fn one_d_structure_message(structure: &OneDStructures) -> (Topic, Payload) {
+    let topic = Topic {
+        kind: MessageKind::OneDStructure,
+        symbol: structure.symbol,
+        timerange: Some(structure.timerange),
+        structure: Some(structure.structure),
+    };
+
+    (topic, Payload::one_d_structure(structure))
+}
+
+// The timerange is only used as a label, so any series of candles (time based or not) can be processed
+pub async fn processfairvaluegap(candle: Arc<Candle>, symbol: &'static str, timerange: &'static str) -> Result<(), String> {
+    let key = format!("{}-{}", symbol, timerange);
 
     let last_candles = LAST_THREE_CANDLES
         .get_mut(key.as_str());
@@ -58,6 +125,14 @@ pub async fn processfairvaluegap(candle: Arc<Candle>, symbol: &'static str, time
         // Add the new candle to the list
         last_candles.push(candle.clone());
 
+        // Then work on a copy, so the map isn't locked while awaiting below
+        // (a timerange whose key is in the same shard would wait for it forever)
+        let last_candles = {
+            let copy = last_candles.clone();
+            drop(last_candles);
+            copy
+        };
+
         if last_candles.len() < 3 {
             // If we don't have enough candles, we can't find a fair value gap
             return Ok(());
@@ -69,15 +144,15 @@ pub async fn processfairvaluegap(candle: Arc<Candle>, symbol: &'static str, time
         let mut direction: Option<&'static str> = None;
         for candle in last_candles.iter() {
             // We check if the direction is already initialized
-            if let Some(ref direction) = direction {
+            if let Some(direction) = direction {
                 // And if the actual candle has the same direction
-                if *direction != candle.direction && candle.direction != "doji".to_string() {
+                if direction != candle.direction && candle.direction != "doji" {
                     return Ok(());
                 }
             } else {
                 // If not we initialize the direction
                 // We ignore doji candles for the direction
-                if *candle.direction != "doji".to_string() {
+                if candle.direction != "doji" {
                     direction = Some(candle.direction);
                 }
             }
@@ -89,7 +164,7 @@ pub async fn processfairvaluegap(candle: Arc<Candle>, symbol: &'static str, time
         let mut low: Option<f64> = None;
 
         // If we have a direction, we can check for fair value gaps
-        if let Some(direction) = direction.clone() {
+        if let Some(direction) = direction {
             // If it's bullish, we have to find a hole between the first candle shadow and the third candle body
             if direction == "bullish" {
                 if last_candles[0].high < last_candles[2].low {
@@ -97,11 +172,9 @@ pub async fn processfairvaluegap(candle: Arc<Candle>, symbol: &'static str, time
                     low = Some(last_candles[0].high);
                 }
             // If it's bearish, we have to find a hole between the first candle body and the third candle shadow
-            } else if direction == "bearish" {
-                if last_candles[0].low > last_candles[2].high {
-                    high = Some(last_candles[0].low);
-                    low = Some(last_candles[2].high);
-                }
+            } else if direction == "bearish" && last_candles[0].low > last_candles[2].high {
+                high = Some(last_candles[0].low);
+                low = Some(last_candles[2].high);
             }
         }
 
@@ -109,16 +182,21 @@ pub async fn processfairvaluegap(candle: Arc<Candle>, symbol: &'static str, time
         // And we add it to the database
         if let (Some(high), Some(low)) = (high, low) {
             let fair_value_gap = TwoDStructures {
-                symbol: symbol,
+                symbol,
                 structure: "Fair Value Gap",
-                timerange: timerange.label,
+                timerange,
                 timestamp: candle.timestamp,
                 high,
                 low,
                 direction: direction.unwrap_or("doji"), // But this should never happen
+                invalidated_at: None,
+                trend: None,
+                first_candle: Some(last_candles[0].uid()),
+                last_candle: Some(last_candles[2].uid()),
             };
 
             add_2_d_structures(&fair_value_gap).await?;
+            track_zone(&fair_value_gap);
 
             send_two_d_structure(&fair_value_gap).await?;
         }
@@ -126,9 +204,7 @@ pub async fn processfairvaluegap(candle: Arc<Candle>, symbol: &'static str, time
         return Ok(());
     } else {
         // If there are no candles yet, we create a new vector
-        let mut new_candles = Vec::new();
-        new_candles.push(candle);
-        LAST_THREE_CANDLES.insert(key, new_candles);
+        LAST_THREE_CANDLES.insert(key, vec![candle]);
     }
 
     Ok(())
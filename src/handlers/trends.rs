@@ -7,10 +7,11 @@ use crate::{
         add_trends,
         add_1_d_structures,
         add_2_d_structures
-    }, websocket::send_message_to_clients}, 
+    }, messages::Payload, subscriptions::{MessageKind, Topic}, websocket::send_message_to_clients}, 
     handlers::structures::{
         send_one_d_structure,
-        send_two_d_structure
+        send_two_d_structure,
+        track_zone
     }, 
     Candle, 
     OneDStructures,
@@ -22,7 +23,6 @@ use crate::{
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
 use once_cell::sync::Lazy;
-use serde_json::{Map, to_value, Value};
 use std::sync::Arc;
 
 pub static QUEUE: Lazy<Arc<DashMap<String, Vec<Arc<Candle>>>>> = Lazy::new(|| {
@@ -41,16 +41,21 @@ pub static SUBTRENDS: Lazy<Arc<DashMap<String, Arc<Subtrend>>>> = Lazy::new(|| {
 
 // This function sends a Trend entity to all connected clients via WebSocket
 pub async fn send_trend(trend: &Trend) -> Result<(), String> {
-    let mut data = Map::new();
+    let (topic, message) = trend_message(trend);
+    send_message_to_clients(topic, message).await?;
 
-    data.insert("type".to_string(), Value::String("Trend".to_string()));
-    data.insert("value".to_string(), to_value(trend).unwrap());
-
-    let json_data = Value::Object(data).to_string();
+    Ok(())
+}
 
-    send_message_to_clients(&json_data).await?;
+pub fn trend_message(trend: &Trend) -> (Topic, Payload) {
+    let topic = Topic {
+        kind: MessageKind::Trend,
+        symbol: trend.symbol,
+        timerange: Some(trend.timerange),
+        structure: None,
+    };
 
-    Ok(())
+    (topic, Payload::trend(trend))
 }
 
 pub async fn process_trend(candle: Arc<Candle>, symbol: &'static str, timerange: &str) -> Result<(), String> {
@@ -73,7 +78,9 @@ pub async fn process_trend(candle: Arc<Candle>, symbol: &'static str, timerange:
 
             // Check if the queue exists for the given key
             // And iterate through the candles in the queue
-            if let Some(queue) = QUEUE.get(&key) {
+            // It's copied because the queue is modified while processing it
+            let queue = QUEUE.get(&key).map(|queue| queue.clone());
+            if let Some(queue) = queue {
                 for candle in queue.iter() {
 
                     // Process the candle to get the trends
@@ -100,10 +107,11 @@ pub async fn process_trend(candle: Arc<Candle>, symbol: &'static str, timerange:
 // Because the old candles are not relevant anymore
 pub fn process_queue(key: String, datetime: DateTime<Utc>) -> Result<(), String> {
     // Check if the queue exists for the given key
-    let queue_exists = QUEUE.get(&key).ok_or("No queue found for the given key")?;
+    // The read lock is released right away, otherwise modifying the queue below would wait for it forever
+    let queue_is_empty = QUEUE.get(&key).ok_or("No queue found for the given key")?.is_empty();
 
     // This will never happen, but we check it anyway
-    if queue_exists.is_empty() {
+    if queue_is_empty {
         return Ok(());
     }
 
@@ -119,6 +127,10 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
     let mut datetime: Option<DateTime<Utc>> = None;
     let key = format!("{}-{}", candle.symbol, candle.timerange);
 
+    // The structures found are written and sent once the state is updated, after the trend they reference
+    let mut one_d_structures: Vec<OneDStructures> = Vec::new();
+    let mut two_d_structures: Vec<TwoDStructures> = Vec::new();
+
     if let Some(trend) = TRENDS
         .get(key.as_str()) 
         .map(|t| Arc::clone(t.value()))
@@ -144,19 +156,7 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
                         // And we can update the trend
 
                         modified_trend.high = subtrend.high; 
-
-                        let break_of_structure = OneDStructures {
-                            symbol: candle.symbol,
-                            structure: "Break Of Structure",
-                            timerange: candle.timerange,
-                            timestamp: candle.timestamp,
-                            price: subtrend.low,
-                            // reference ?
-                            direction: "Bearish",
-                        };
-
-                        send_one_d_structure(&break_of_structure).await?;
-                        add_1_d_structures(&break_of_structure).await?;
+                        modified_trend.high_datetime = subtrend.last_relative_high_datetime;
 
                         let relative_high = OneDStructures {
                             symbol: candle.symbol,
@@ -165,6 +165,8 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
                             timestamp: subtrend.last_relative_high_datetime,
                             price: subtrend.high,
                             direction: "Relative High",
+                            broken: None,
+                            trend: Some(trend.uid()),
                         };
 
                         let relative_low = OneDStructures {
@@ -174,13 +176,23 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
                             timestamp: subtrend.last_relative_low_datetime,
                             price: subtrend.low,
                             direction: "Relative Low",
+                            broken: None,
+                            trend: Some(trend.uid()),
                         };
 
-                        send_one_d_structure(&relative_high).await?;
-                        send_one_d_structure(&relative_low).await?;
+                        let break_of_structure = OneDStructures {
+                            symbol: candle.symbol,
+                            structure: "Break Of Structure",
+                            timerange: candle.timerange,
+                            timestamp: candle.timestamp,
+                            price: subtrend.low,
+                            direction: "Bearish",
+                            broken: Some(relative_low.uid()),
+                            trend: Some(trend.uid()),
+                        };
 
-                        add_1_d_structures(&relative_high).await?;
-                        add_1_d_structures(&relative_low).await?;  
+                        // The swings come first, so the break of structure comes after the swing it broke
+                        one_d_structures.extend([relative_high, relative_low, break_of_structure]);
 
                         // Remove the subtrend from the map
                         SUBTRENDS.remove(key.as_str());
@@ -202,19 +214,7 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
                     // So now if candle.direction is bearish
                     if candle.close > subtrend.high {
                         modified_trend.low = subtrend.low;
-
-                        let break_of_structure = OneDStructures {
-                            symbol: candle.symbol,
-                            structure: "Break Of Structure",
-                            timerange: candle.timerange,
-                            timestamp: candle.timestamp,
-                            price: subtrend.high,
-                            // reference ?
-                            direction: "Bullish",
-                        };
-
-                        send_one_d_structure(&break_of_structure).await?;
-                        add_1_d_structures(&break_of_structure).await?;
+                        modified_trend.low_datetime = subtrend.last_relative_low_datetime;
 
                         let relative_high = OneDStructures {
                             symbol: candle.symbol,
@@ -223,6 +223,8 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
                             timestamp: subtrend.last_relative_high_datetime,
                             price: subtrend.high,
                             direction: "Relative High",
+                            broken: None,
+                            trend: Some(trend.uid()),
                         };
 
                         let relative_low = OneDStructures {
@@ -232,13 +234,23 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
                             timestamp: subtrend.last_relative_low_datetime,
                             price: subtrend.low,
                             direction: "Relative Low",
+                            broken: None,
+                            trend: Some(trend.uid()),
                         };
 
-                        send_one_d_structure(&relative_high).await?;
-                        send_one_d_structure(&relative_low).await?;
+                        let break_of_structure = OneDStructures {
+                            symbol: candle.symbol,
+                            structure: "Break Of Structure",
+                            timerange: candle.timerange,
+                            timestamp: candle.timestamp,
+                            price: subtrend.high,
+                            direction: "Bullish",
+                            broken: Some(relative_high.uid()),
+                            trend: Some(trend.uid()),
+                        };
 
-                        add_1_d_structures(&relative_high).await?;
-                        add_1_d_structures(&relative_low).await?;
+                        // The swings come first, so the break of structure comes after the swing it broke
+                        one_d_structures.extend([relative_high, relative_low, break_of_structure]);
 
                         // Remove the subtrend from the map
                         SUBTRENDS.remove(key.as_str());
@@ -292,10 +304,26 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
                             high: subtrend.last_candle.high,
                             low: subtrend.last_relative_low,
                             direction: "Bullish",
+                            invalidated_at: None,
+                            trend: Some(trend.uid()),
+                            first_candle: Some(subtrend.last_candle.uid()),
+                            last_candle: Some(subtrend.last_candle.uid()),
                         };
 
-                        send_two_d_structure(&order_block).await?;
-                        add_2_d_structures(&order_block).await?;
+                        track_zone(&order_block);
+                        two_d_structures.push(order_block);
+
+                        // The swing broken by the change of character is the high of the trend
+                        let broken_high = OneDStructures {
+                            symbol: candle.symbol,
+                            structure: "Relative High",
+                            timerange: candle.timerange,
+                            timestamp: trend.high_datetime,
+                            price: trend.high,
+                            direction: "Relative High",
+                            broken: None,
+                            trend: Some(trend.uid()),
+                        };
 
                         // TODO: change (not sure about it)
                         let change_of_character = OneDStructures {
@@ -305,11 +333,11 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
                             timestamp: candle.timestamp,
                             price: trend.relative_high,
                             direction: "Bullish",
-                            // Reference ?
+                            broken: Some(broken_high.uid()),
+                            trend: Some(trend.uid()),
                         };
 
-                        send_one_d_structure(&change_of_character).await?;
-                        add_1_d_structures(&change_of_character).await?;
+                        one_d_structures.extend([broken_high, change_of_character]);
                     }
                 } else if subtrend.direction == "bearish" {
                     modified_trend.end_time = candle.timestamp;
@@ -329,10 +357,26 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
                             high: subtrend.last_candle.high,
                             low: subtrend.last_candle.low,
                             direction: "Bearish",
+                            invalidated_at: None,
+                            trend: Some(trend.uid()),
+                            first_candle: Some(subtrend.last_candle.uid()),
+                            last_candle: Some(subtrend.last_candle.uid()),
                         };
 
-                        send_two_d_structure(&order_block).await?;
-                        add_2_d_structures(&order_block).await?;
+                        track_zone(&order_block);
+                        two_d_structures.push(order_block);
+
+                        // The swing broken by the change of character is the low of the trend
+                        let broken_low = OneDStructures {
+                            symbol: candle.symbol,
+                            structure: "Relative Low",
+                            timerange: candle.timerange,
+                            timestamp: trend.low_datetime,
+                            price: trend.low,
+                            direction: "Relative Low",
+                            broken: None,
+                            trend: Some(trend.uid()),
+                        };
 
                         let change_of_character = OneDStructures {
                             symbol: candle.symbol,
@@ -341,11 +385,11 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
                             timestamp: candle.timestamp,
                             price: trend.relative_low,
                             direction: "Bearish",
-                            // Reference ?
+                            broken: Some(broken_low.uid()),
+                            trend: Some(trend.uid()),
                         };
 
-                        send_one_d_structure(&change_of_character).await?;
-                        add_1_d_structures(&change_of_character).await?;
+                        one_d_structures.extend([broken_low, change_of_character]);
                     }
                 }
             } else {
@@ -402,18 +446,34 @@ pub async fn get_trends(candle: Arc<Candle>) -> Result<Option<DateTime<Utc>>, St
         .map(|t| Arc::clone(t.value()))
         .ok_or("No trend found for the given key")?;
 
-    add_trends(&trend).await?;
-
-    if let Some(_) = datetime {
+    if datetime.is_some() {
         // If we have a datetime,
         // That means that we have an new trend, 
-        // So we can send it and remove it from the map
+        // So we can remove it from the map (it's sent below)
         // Same for the subtrend because it's not longer needed
+        TRENDS.remove(key.as_str());
+        SUBTRENDS.remove(key.as_str());
+    }
 
+    // The trend is written and sent before the structures, so their reference to it is always valid
+    // An ongoing trend is only sent when a structure references it
+    add_trends(&trend).await?;
+
+    let uid = Some(trend.uid());
+    let referenced = two_d_structures.iter().any(|s| s.trend == uid) || one_d_structures.iter().any(|s| s.trend == uid);
+
+    if datetime.is_some() || referenced {
         send_trend(&trend).await?;
+    }
 
-        TRENDS.remove(key.as_str());
-        SUBTRENDS.remove(key.as_str());
+    for structure in &two_d_structures {
+        add_2_d_structures(structure).await?;
+        send_two_d_structure(structure).await?;
+    }
+
+    for structure in &one_d_structures {
+        add_1_d_structures(structure).await?;
+        send_one_d_structure(structure).await?;
     }
 
     Ok(datetime)
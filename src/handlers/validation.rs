@@ -0,0 +1,411 @@
+// Data quality checks on the incoming 1m candles
+// Each kind of issue has its own policy (reject, repair, forward-fill or flag),
+// And every issue found is counted in a report for the current run
+
+use crate::{
+    config::ValidationConfig,
+    entities::candle::get_direction,
+    Candle,
+};
+
+use chrono::{DateTime, Duration, Utc};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, sync::{Arc, Mutex}};
+
+// The last candle accepted for each symbol
+// Used to detect gaps, duplicates, out of order candles and spikes
+pub static LAST_CANDLES: Lazy<Arc<DashMap<String, Candle>>> = Lazy::new(|| {
+    Arc::new(DashMap::new())
+});
+
+// The data quality report of the current run
+pub static REPORT: Lazy<Mutex<DataQualityReport>> = Lazy::new(|| {
+    Mutex::new(DataQualityReport::default())
+});
+
+// How many issues are kept with their details in the report
+const MAX_REPORTED_ISSUES: usize = 100;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Policy {
+    Reject,      // Drop the candle
+    Repair,      // Fix the candle from its own values
+    ForwardFill, // Replace the candle (or the missing ones) with the previous close
+    Flag,        // Keep the candle as it is, only report the issue
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IssueKind {
+    MissingMinutes,
+    Duplicate,
+    OutOfOrder,
+    InconsistentOhlc,
+    InvalidPrice,
+    Spike,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Issue {
+    pub symbol: &'static str,
+    pub timestamp: DateTime<Utc>,
+    pub kind: IssueKind,
+    pub policy: Policy,
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DataQualityReport {
+    pub checked: u64,  // Candles received
+    pub rejected: u64, // Issues whose candle was dropped
+    pub repaired: u64, // Issues fixed from the candle's own values
+    pub filled: u64,   // Issues fixed with the previous close
+    pub flagged: u64,  // Issues kept as they are
+    pub inserted: u64, // Candles created to fill the missing minutes
+    pub issues: BTreeMap<IssueKind, u64>,
+    pub first_issues: Vec<Issue>, // Details of the first issues found
+}
+
+// Checks that every policy of the configuration makes sense for its issue
+pub fn check_policies(config: &ValidationConfig) -> Result<(), String> {
+    let policies = [
+        (IssueKind::MissingMinutes, config.missing_minutes, &[Policy::Reject, Policy::ForwardFill, Policy::Flag][..]),
+        (IssueKind::Duplicate, config.duplicates, &[Policy::Reject, Policy::Flag][..]),
+        (IssueKind::OutOfOrder, config.out_of_order, &[Policy::Reject, Policy::Flag][..]),
+        (IssueKind::InconsistentOhlc, config.inconsistent_ohlc, &[Policy::Reject, Policy::Repair, Policy::ForwardFill, Policy::Flag][..]),
+        (IssueKind::InvalidPrice, config.invalid_prices, &[Policy::Reject, Policy::ForwardFill, Policy::Flag][..]),
+        (IssueKind::Spike, config.spikes, &[Policy::Reject, Policy::ForwardFill, Policy::Flag][..]),
+    ];
+
+    for (kind, policy, allowed) in policies {
+        if !allowed.contains(&policy) {
+            return Err(format!("Policy {:?} can't be used for {:?} issues", policy, kind));
+        }
+    }
+
+    Ok(())
+}
+
+// Validates a 1m candle against the previous one of its symbol
+// Returns the candles to process: none if rejected,
+// Or the missing candles followed by the (maybe repaired) candle
+pub fn validate_candle(candle: Candle, config: &ValidationConfig) -> Vec<Candle> {
+    let last = LAST_CANDLES.get(candle.symbol).map(|c| c.value().clone());
+
+    record_checked();
+
+    let candles = check_candle(candle.clone(), last.as_ref(), config);
+
+    // Only the candles moving forward become the new reference
+    // A rejected one only moves its time forward, otherwise every candle after a rejected gap would be rejected as well,
+    // Its prices aren't used since it could be the spike itself
+    if last.as_ref().is_none_or(|last| candle.timestamp > last.timestamp) {
+        let mut reference = match (candles.last(), last.as_ref()) {
+            (Some(accepted), _) => accepted.clone(),
+            (None, Some(last)) => Candle { timestamp: candle.timestamp, ..last.clone() },
+            (None, None) => return candles,
+        };
+
+        // The close of the reference is used to fill and to detect spikes, so it has to be valid
+        if !reference.close.is_finite() || reference.close <= 0.0 {
+            match last.as_ref() {
+                Some(last) => fill_with(&mut reference, last.close),
+                None => return candles,
+            }
+        }
+
+        LAST_CANDLES.insert(reference.symbol.to_string(), reference);
+    }
+
+    candles
+}
+
+fn check_candle(candle: Candle, last: Option<&Candle>, config: &ValidationConfig) -> Vec<Candle> {
+    let mut candle = candle;
+
+    // Zero, negative or non finite prices
+    let prices = [candle.open, candle.high, candle.low, candle.close];
+    if prices.iter().any(|p| !p.is_finite() || *p <= 0.0) {
+        match apply(&candle, IssueKind::InvalidPrice, config.invalid_prices) {
+            Policy::ForwardFill => match last {
+                Some(last) => fill_with(&mut candle, last.close),
+                None => return reject(),
+            },
+            Policy::Flag => {}
+            _ => return reject(),
+        }
+    }
+
+    // The prices may have been filled above
+    let prices = [candle.open, candle.high, candle.low, candle.close];
+
+    // High below low, or open/close outside of the shadows
+    if candle.high < candle.low || prices.iter().any(|p| *p > candle.high || *p < candle.low) {
+        match apply(&candle, IssueKind::InconsistentOhlc, config.inconsistent_ohlc) {
+            Policy::Repair => {
+                candle.high = prices.iter().cloned().fold(f64::MIN, f64::max);
+                candle.low = prices.iter().cloned().fold(f64::MAX, f64::min);
+            }
+            Policy::ForwardFill => match last {
+                Some(last) => fill_with(&mut candle, last.close),
+                None => return reject(),
+            },
+            Policy::Flag => {}
+            _ => return reject(),
+        }
+    }
+
+    let mut candles = Vec::new();
+
+    if let Some(last) = last {
+        let step = Duration::minutes(1);
+
+        // Duplicates and out of order candles can't be merged safely, they only go through when flagged explicitly
+        if candle.timestamp == last.timestamp {
+            if apply(&candle, IssueKind::Duplicate, config.duplicates) != Policy::Flag {
+                return reject();
+            }
+        } else if candle.timestamp < last.timestamp {
+            if apply(&candle, IssueKind::OutOfOrder, config.out_of_order) != Policy::Flag {
+                return reject();
+            }
+        } else if candle.timestamp - last.timestamp > step {
+            match apply(&candle, IssueKind::MissingMinutes, config.missing_minutes) {
+                Policy::ForwardFill => {
+                    let missing = (candle.timestamp - last.timestamp).num_minutes() - 1;
+
+                    // Long gaps (e.g. weekends) are left as they are
+                    if missing <= config.max_filled_minutes as i64 {
+                        for index in 1..=missing {
+                            let mut filler = last.clone();
+                            filler.timestamp = last.timestamp + step * index as i32;
+                            filler.volume = 0.0;
+                            fill_with(&mut filler, last.close);
+
+                            record(|report| report.inserted += 1);
+                            candles.push(filler);
+                        }
+                    }
+                }
+                Policy::Flag => {}
+                _ => return reject(),
+            }
+        }
+
+        // Moves bigger than the threshold relative to the previous close
+        let threshold = last.close * config.spike_threshold;
+        let move_size = (candle.high - last.close).abs().max((candle.low - last.close).abs());
+        if config.spike_threshold > 0.0 && move_size > threshold {
+            match apply(&candle, IssueKind::Spike, config.spikes) {
+                Policy::ForwardFill => fill_with(&mut candle, last.close),
+                Policy::Flag => {}
+                _ => return reject(),
+            }
+        }
+    }
+
+    candles.push(candle);
+    candles
+}
+
+// Returns a copy of the report of the current run
+pub fn get_report() -> DataQualityReport {
+    REPORT.lock().map(|r| r.clone()).unwrap_or_default()
+}
+
+// Replaces the prices of a candle with a flat candle at the given price
+fn fill_with(candle: &mut Candle, price: f64) {
+    candle.open = price;
+    candle.high = price;
+    candle.low = price;
+    candle.close = price;
+    candle.direction = get_direction(price, price);
+}
+
+// Records an issue in the report and returns the policy to apply
+fn apply(candle: &Candle, kind: IssueKind, policy: Policy) -> Policy {
+    record(|report| {
+        *report.issues.entry(kind).or_default() += 1;
+
+        match policy {
+            Policy::Reject => report.rejected += 1,
+            Policy::Repair => report.repaired += 1,
+            Policy::ForwardFill => report.filled += 1,
+            Policy::Flag => report.flagged += 1,
+        }
+
+        if report.first_issues.len() < MAX_REPORTED_ISSUES {
+            report.first_issues.push(Issue {
+                symbol: candle.symbol,
+                timestamp: candle.timestamp,
+                kind,
+                policy,
+            });
+        }
+    });
+
+    policy
+}
+
+fn record_checked() {
+    record(|report| report.checked += 1);
+}
+
+fn reject() -> Vec<Candle> {
+    Vec::new()
+}
+
+fn record(update: impl FnOnce(&mut DataQualityReport)) {
+    if let Ok(mut report) = REPORT.lock() {
+        update(&mut report);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each test has its own symbol, the last candles are shared by the tests running in parallel
+    fn candle(symbol: &'static str, minute: i64, open: f64, high: f64, low: f64, close: f64) -> Candle {
+        let timestamp = DateTime::from_timestamp(1_700_000_040 + minute * 60, 0).unwrap();
+        Candle::new(symbol, "1min", timestamp, open, high, low, close, 1.0)
+    }
+
+    fn flat(symbol: &'static str, minute: i64, price: f64) -> Candle {
+        candle(symbol, minute, price, price, price, price)
+    }
+
+    fn minutes(candles: &[Candle]) -> Vec<i64> {
+        candles.iter().map(|c| (c.timestamp.timestamp() - 1_700_000_040) / 60).collect()
+    }
+
+    #[test]
+    fn accepts_consecutive_candles() {
+        let config = ValidationConfig::default();
+
+        assert_eq!(minutes(&validate_candle(flat("VAL_OK", 0, 1.0), &config)), [0]);
+        assert_eq!(minutes(&validate_candle(candle("VAL_OK", 1, 1.0, 1.02, 0.99, 1.01), &config)), [1]);
+    }
+
+    #[test]
+    fn missing_minutes() {
+        let config = ValidationConfig { missing_minutes: Policy::ForwardFill, ..ValidationConfig::default() };
+        validate_candle(flat("VAL_GAP_FILL", 0, 1.0), &config);
+
+        let candles = validate_candle(flat("VAL_GAP_FILL", 3, 1.01), &config);
+        assert_eq!(minutes(&candles), [1, 2, 3]);
+        assert!(candles[..2].iter().all(|c| c.close == 1.0 && c.high == 1.0 && c.volume == 0.0));
+
+        // Too long to be filled
+        let config = ValidationConfig { max_filled_minutes: 2, ..config };
+        assert_eq!(minutes(&validate_candle(flat("VAL_GAP_FILL", 7, 1.0), &config)), [7]);
+
+        let config = ValidationConfig { missing_minutes: Policy::Flag, ..ValidationConfig::default() };
+        validate_candle(flat("VAL_GAP_FLAG", 0, 1.0), &config);
+        assert_eq!(minutes(&validate_candle(flat("VAL_GAP_FLAG", 3, 1.0), &config)), [3]);
+    }
+
+    #[test]
+    fn candles_after_a_rejected_gap_are_accepted() {
+        let config = ValidationConfig { missing_minutes: Policy::Reject, ..ValidationConfig::default() };
+        validate_candle(flat("VAL_GAP_REJECT", 0, 1.0), &config);
+
+        assert!(validate_candle(flat("VAL_GAP_REJECT", 5, 1.0), &config).is_empty());
+        // The time of the rejected candle is the new reference, so the next one isn't a gap
+        assert_eq!(minutes(&validate_candle(flat("VAL_GAP_REJECT", 6, 1.0), &config)), [6]);
+    }
+
+    #[test]
+    fn candles_after_a_rejected_spike_are_accepted() {
+        let config = ValidationConfig { spikes: Policy::Reject, ..ValidationConfig::default() };
+        validate_candle(flat("VAL_SPIKE_REJECT", 0, 1.0), &config);
+
+        assert!(validate_candle(flat("VAL_SPIKE_REJECT", 1, 2.0), &config).is_empty());
+        // Compared to the last accepted close, not to the spike
+        assert_eq!(minutes(&validate_candle(flat("VAL_SPIKE_REJECT", 2, 1.0), &config)), [2]);
+        assert_eq!(LAST_CANDLES.get("VAL_SPIKE_REJECT").unwrap().close, 1.0);
+    }
+
+    #[test]
+    fn spikes() {
+        let config = ValidationConfig { spikes: Policy::ForwardFill, ..ValidationConfig::default() };
+        validate_candle(flat("VAL_SPIKE_FILL", 0, 1.0), &config);
+
+        let candles = validate_candle(candle("VAL_SPIKE_FILL", 1, 1.0, 1.5, 1.0, 1.2), &config);
+        assert_eq!((candles[0].open, candles[0].high, candles[0].low, candles[0].close), (1.0, 1.0, 1.0, 1.0));
+
+        // Disabled with a threshold of 0
+        let config = ValidationConfig { spikes: Policy::Reject, spike_threshold: 0.0, ..ValidationConfig::default() };
+        validate_candle(flat("VAL_SPIKE_OFF", 0, 1.0), &config);
+        assert_eq!(validate_candle(flat("VAL_SPIKE_OFF", 1, 3.0), &config).len(), 1);
+    }
+
+    #[test]
+    fn duplicates_and_out_of_order_candles() {
+        let config = ValidationConfig::default();
+        validate_candle(flat("VAL_ORDER_REJECT", 5, 1.0), &config);
+
+        assert!(validate_candle(flat("VAL_ORDER_REJECT", 5, 1.0), &config).is_empty());
+        assert!(validate_candle(flat("VAL_ORDER_REJECT", 4, 1.0), &config).is_empty());
+        assert_eq!(minutes(&validate_candle(flat("VAL_ORDER_REJECT", 6, 1.0), &config)), [6]);
+
+        let config = ValidationConfig { duplicates: Policy::Flag, out_of_order: Policy::Flag, ..ValidationConfig::default() };
+        validate_candle(flat("VAL_ORDER_FLAG", 5, 1.0), &config);
+
+        assert_eq!(minutes(&validate_candle(flat("VAL_ORDER_FLAG", 5, 1.0), &config)), [5]);
+        assert_eq!(minutes(&validate_candle(flat("VAL_ORDER_FLAG", 4, 1.0), &config)), [4]);
+        // The reference doesn't go back
+        assert_eq!(LAST_CANDLES.get("VAL_ORDER_FLAG").unwrap().timestamp, flat("VAL_ORDER_FLAG", 5, 1.0).timestamp);
+    }
+
+    #[test]
+    fn inconsistent_ohlc() {
+        let broken = |symbol| candle(symbol, 1, 1.0, 0.99, 1.01, 1.0);
+
+        let config = ValidationConfig { inconsistent_ohlc: Policy::Repair, ..ValidationConfig::default() };
+        let candles = validate_candle(broken("VAL_OHLC_REPAIR"), &config);
+        assert_eq!((candles[0].high, candles[0].low), (1.01, 0.99));
+
+        let config = ValidationConfig { inconsistent_ohlc: Policy::ForwardFill, ..ValidationConfig::default() };
+        validate_candle(flat("VAL_OHLC_FILL", 0, 1.005), &config);
+        let candles = validate_candle(broken("VAL_OHLC_FILL"), &config);
+        assert_eq!((candles[0].high, candles[0].low, candles[0].close), (1.005, 1.005, 1.005));
+
+        let config = ValidationConfig { inconsistent_ohlc: Policy::Reject, ..ValidationConfig::default() };
+        assert!(validate_candle(broken("VAL_OHLC_REJECT"), &config).is_empty());
+
+        let config = ValidationConfig::default();
+        assert_eq!(validate_candle(broken("VAL_OHLC_FLAG"), &config)[0].high, 0.99);
+    }
+
+    #[test]
+    fn invalid_prices() {
+        let config = ValidationConfig { invalid_prices: Policy::ForwardFill, inconsistent_ohlc: Policy::Reject, ..ValidationConfig::default() };
+
+        // Nothing to fill from yet
+        assert!(validate_candle(candle("VAL_PRICE_FILL", 0, f64::NAN, 1.0, 1.0, 1.0), &config).is_empty());
+
+        validate_candle(flat("VAL_PRICE_FILL", 1, 1.0), &config);
+        let candles = validate_candle(candle("VAL_PRICE_FILL", 2, f64::NAN, 1.2, 1.1, 1.1), &config);
+        assert_eq!((candles[0].open, candles[0].close), (1.0, 1.0));
+
+        let config = ValidationConfig { invalid_prices: Policy::Reject, ..ValidationConfig::default() };
+        assert!(validate_candle(candle("VAL_PRICE_REJECT", 0, 0.0, 1.0, 0.0, 1.0), &config).is_empty());
+
+        // A flagged NaN close isn't used as the reference
+        let config = ValidationConfig::default();
+        validate_candle(flat("VAL_PRICE_FLAG", 0, 1.0), &config);
+        assert_eq!(validate_candle(flat("VAL_PRICE_FLAG", 1, f64::NAN), &config).len(), 1);
+        assert_eq!(LAST_CANDLES.get("VAL_PRICE_FLAG").unwrap().close, 1.0);
+    }
+
+    #[test]
+    fn refuses_the_policies_that_make_no_sense() {
+        assert!(check_policies(&ValidationConfig::default()).is_ok());
+        assert!(check_policies(&ValidationConfig { duplicates: Policy::Repair, ..ValidationConfig::default() }).is_err());
+        assert!(check_policies(&ValidationConfig { missing_minutes: Policy::Repair, ..ValidationConfig::default() }).is_err());
+        assert!(check_policies(&ValidationConfig { out_of_order: Policy::ForwardFill, ..ValidationConfig::default() }).is_err());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,80 @@
+// Warm-up from the stored history
+// Without it, the first 1m candle received after a start would open every higher timerange candle,
+// Giving truncated 4h/1d/1w candles and trends built from nothing
+// So the recent stored 1m candles are replayed through the pipeline before the live data,
+// With the storage and the broadcast muted since everything replayed has already been emitted
+
+use crate::{
+    config::WarmupConfig,
+    connections::database::{get_candles_between, get_latest_candles},
+    entities::timerange::{get_timeranges, BASE_TIMERANGE},
+    handlers::{pipeline::process_candle, validation::LAST_CANDLES},
+    utils::utils::intern,
+};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static WARMING_UP: AtomicBool = AtomicBool::new(false);
+
+// Whether the pipeline is replaying the history
+// Nothing should be stored or sent meanwhile
+pub fn is_warming_up() -> bool {
+    WARMING_UP.load(Ordering::Relaxed)
+}
+
+// Rebuilds the state of the engine for every configured symbol
+pub async fn warm_up(config: &WarmupConfig) -> Result<(), String> {
+    WARMING_UP.store(true, Ordering::Relaxed);
+
+    let mut result = Ok(());
+    for symbol in &config.symbols {
+        result = warm_up_symbol(intern(symbol), config).await;
+
+        if result.is_err() {
+            break;
+        }
+    }
+
+    WARMING_UP.store(false, Ordering::Relaxed);
+    result
+}
+
+async fn warm_up_symbol(symbol: &'static str, config: &WarmupConfig) -> Result<(), String> {
+    let Some(last) = get_latest_candles(symbol, BASE_TIMERANGE, 1).await?.pop() else {
+        println!("No stored candles to warm up {}", symbol);
+        return Ok(());
+    };
+
+    // When the state has been restored from a checkpoint, only the newer candles are missing
+    let start = match LAST_CANDLES.get(symbol) {
+        Some(candle) => candle.timestamp + Duration::milliseconds(1),
+        None => history_start(last.timestamp, config.history_periods)?,
+    };
+
+    let candles = get_candles_between(symbol, BASE_TIMERANGE, start, last.timestamp + Duration::milliseconds(1)).await?;
+    let count = candles.len();
+
+    for candle in candles {
+        process_candle(candle, symbol).await;
+    }
+
+    println!("Warmed up {} with {} candles", symbol, count);
+    Ok(())
+}
+
+// The replay starts at the beginning of the open period of the longest timerange,
+// Plus `history_periods` full periods before it for the trends and structures
+fn history_start(last: DateTime<Utc>, history_periods: usize) -> Result<DateTime<Utc>, String> {
+    let longest = get_timeranges()
+        .iter()
+        .map(|t| t.duration_ms as i64)
+        .max()
+        .unwrap_or(60_000);
+
+    let period_start = (last.timestamp_millis() / longest) * longest;
+
+    Utc.timestamp_millis_opt(period_start - history_periods as i64 * longest)
+        .single()
+        .ok_or_else(|| "Failed to compute the start of the warm-up".to_string())
+}
\ No newline at end of file
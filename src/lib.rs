@@ -1,8 +1,13 @@
+pub mod config;
 pub mod connections;
 pub mod handlers;
 pub mod entities;
 pub mod utils;
 
+pub use entities::bar::{
+    BarKind,
+    BarType,
+};
 pub use entities::candle::Candle;
 pub use entities::session::{
     ReferenceSession,
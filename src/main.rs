@@ -1,18 +1,31 @@
 use paragon::{
+    config::{get_config, init_config},
     connections::{
-        database::init_pool,
-        websocket::create_intra_websocket,
+        auth::{sign_token, Claims},
+        http::create_http_server,
+        messages::{message_schema, request_schema},
+        storage::{
+            get_storage,
+            init_storage,
+            retention::{run_retention, run_retention_task},
+        },
+        websocket::{broadcast_metrics, create_intra_websocket},
+        writer::{init_writer, shutdown_writer},
     },
     handlers::{
-        candle::aggregate_candle,
-        sessions::process_session
+        bars::init_bar_types,
+        candle::{run_candle_closer, run_timerange_reloader},
+        checkpoint::{restore_checkpoint, run_checkpoints, save_checkpoint},
+        derived::init_derived_series,
+        pipeline::process_candle,
+        validation::{check_policies, get_report, LAST_CANDLES},
+        warmup::warm_up,
     },
-    TIMERANGES,
+    entities::timerange::set_timeranges,
     utils::temporary,
 };
 
-use futures::future::join_all;
-use std::sync::Arc;
+use std::{collections::HashMap, time::Duration};
 
 #[tokio::main]
 async fn main() -> Result<(), String> {
@@ -31,18 +44,114 @@ async fn main() -> Result<(), String> {
 }
 
 async fn run_main(perf: bool) -> Result<(), String> {
+    // Load the configuration before anything else
+    init_config()?;
+
+    // `paragon retention [--dry-run]` applies the retention rules once and exits
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("retention") {
+        return run_retention_command(args.iter().any(|arg| arg == "--dry-run")).await;
+    }
+
+    // `paragon schema [requests]` prints the JSON Schema of the websocket messages (or requests)
+    if args.get(1).map(String::as_str) == Some("schema") {
+        let schema = match args.get(2).map(String::as_str) {
+            Some("requests") => request_schema(),
+            _ => message_schema(),
+        };
+
+        let schema = serde_json::to_string_pretty(&schema)
+            .map_err(|e| format!("Failed to serialize the schema: {}", e))?;
+        println!("{}", schema);
+
+        return Ok(());
+    }
+
+    // `paragon token <name> [--symbols EURUSD,GBPUSD] [--expires-in <seconds>] [--admin]` prints a signed token
+    if args.get(1).map(String::as_str) == Some("token") {
+        return run_token_command(&args[2..]);
+    }
+
+    set_timeranges(&get_config().timeranges)?;
+    init_bar_types(&get_config().bars)?;
+    init_derived_series(&get_config().derived)?;
+    check_policies(&get_config().validation)?;
+
+    // Close the candles on the wall clock if enabled
+    let candles_config = &get_config().candles;
+    if candles_config.closer {
+        tokio::spawn(run_candle_closer(
+            Duration::from_millis(candles_config.grace_period_ms),
+            Duration::from_millis(candles_config.closer_interval_ms),
+        ));
+    }
+
+    // Start and stop the timeranges of the configuration file again on SIGHUP
+    tokio::spawn(async {
+        if let Err(e) = run_timerange_reloader().await {
+            eprintln!("{}", e);
+        }
+    });
+
     // Initialize the websocket server
     let intra_websocket = tokio::spawn(async move{
-        create_intra_websocket().await
+        create_intra_websocket(&get_config().websocket).await
             .map_err(|e| format!("WebSocket error: {}", e))
     });
 
     // Spawn the main tasj
     let main_task = tokio::spawn(async move {   
-        // Create the database connection pool
-        init_pool().await
+        // Open the configured storage
+        init_storage(&get_config().database).await
             .map_err(|e| format!("Database connection error: {}", e))?;
 
+        // Bring the schema up to date if enabled
+        if get_config().database.migrate {
+            get_storage()?.migrate().await?;
+        }
+
+        // The HTTP API queries the storage, so it starts once it's open
+        if get_config().http.enabled {
+            tokio::spawn(async {
+                if let Err(e) = create_http_server(&get_config().http).await {
+                    eprintln!("HTTP server error: {}", e);
+                }
+            });
+        }
+
+        // Delete the old rows periodically
+        if get_config().retention.enabled {
+            tokio::spawn(run_retention_task(get_config().retention.clone()));
+        }
+
+        // Start the write-behind persistence
+        if get_config().writer.enabled {
+            init_writer(&get_config().writer)?;
+        }
+
+        // Resume from the last checkpoint and keep writing new ones
+        // The time of the last candle of each symbol already processed (by the checkpoint or the warm-up)
+        let mut processed = HashMap::new();
+
+        let checkpoint_config = &get_config().checkpoint;
+        if checkpoint_config.enabled {
+            if checkpoint_config.restore {
+                processed = restore_checkpoint(checkpoint_config).await?.unwrap_or_default();
+            }
+
+            tokio::spawn(run_checkpoints(checkpoint_config.clone()));
+        }
+
+        // Rebuild the open candles, sessions and trends from the stored history
+        if get_config().warmup.enabled {
+            warm_up(&get_config().warmup).await?;
+
+            // The stored history has been replayed, so the data starts after it
+            for candle in LAST_CANDLES.iter() {
+                processed.insert(candle.key().clone(), candle.timestamp);
+            }
+        }
+
         // Load the data
         let mut data = temporary::get_data().map_err(|e| e.to_string())?;
 
@@ -57,31 +166,31 @@ async fn run_main(perf: bool) -> Result<(), String> {
             let row = data.get_row(index).map_err(|e| e.to_string())?;
             let parsed_candle = temporary::parse_candle(row).map_err(|e| e.to_string())?;
 
-            let candle = Arc::new(parsed_candle);
+            // The data is read from the start again, skip what the checkpoint or the warm-up already covers
+            if processed.get("EURUSD").is_some_and(|last| parsed_candle.timestamp <= *last) {
+                continue;
+            }
 
-            // Spawn a task for each timerange to aggregate the candle
-            let mut handles = Vec::new();
+            process_candle(parsed_candle, "EURUSD").await;
+        }
 
-            for timerange in TIMERANGES.iter() {
-                let cloned_candle = Arc::clone(&candle);
-                let task = tokio::spawn(async move {
-                    aggregate_candle(cloned_candle, "EURUSD", timerange).await
-                });
+        // Make sure everything has been written
+        shutdown_writer().await?;
 
-                handles.push(task);
-            }
+        if checkpoint_config.enabled {
+            save_checkpoint(checkpoint_config).await?;
+        }
 
-            // And also spawn a task to process the session
-            let cloned_candle = Arc::clone(&candle);
-            let task = tokio::spawn(async move {
-                if let Err(e) = process_session(cloned_candle, "EURUSD").await {
-                    eprintln!("Error processing session: {}", e);
-                }
-            });
-            handles.push(task);
+        // Print the data quality report of the run
+        match serde_json::to_string_pretty(&get_report()) {
+            Ok(report) => println!("Data quality report: {}", report),
+            Err(e) => eprintln!("Failed to serialize the data quality report: {}", e),
+        }
 
-            // Wait for all tasks to complete
-            let _ = join_all(handles).await; 
+        // And how the clients kept up with it
+        match serde_json::to_string_pretty(&broadcast_metrics().await) {
+            Ok(metrics) => println!("WebSocket clients: {}", metrics),
+            Err(e) => eprintln!("Failed to serialize the WebSocket metrics: {}", e),
         }
 
         if perf {
@@ -96,14 +205,73 @@ async fn run_main(perf: bool) -> Result<(), String> {
     // Run both tasks concurrently and handle their results
     tokio::select! {
         res = intra_websocket => match res {
-            Ok(Ok(())) => return Err("WebSocket finished without error but too early".into()),
-            Ok(Err(e)) => return Err(format!("WebSocket error: {}", e)),
-            Err(e) => return Err(format!("WebSocket panic : {}", e)),
+            Ok(Ok(())) => Err("WebSocket finished without error but too early".into()),
+            Ok(Err(e)) => Err(format!("WebSocket error: {}", e)),
+            Err(e) => Err(format!("WebSocket panic : {}", e)),
         },
         res = main_task => match res {
-            Ok(Ok(())) => return Err("Main task finished without error but too early".into()),
-            Ok(Err(e)) => return Err(format!("Main task error: {}", e)),
-            Err(e) => return Err(format!("Main panic : {}", e)),
+            Ok(Ok(())) => Err("Main task finished without error but too early".into()),
+            Ok(Err(e)) => Err(format!("Main task error: {}", e)),
+            Err(e) => Err(format!("Main panic : {}", e)),
+        },
+        _ = tokio::signal::ctrl_c() => {
+            // Don't lose the rows that are still buffered
+            shutdown_writer().await?;
+
+            // Nor the state of the engine
+            if get_config().checkpoint.enabled {
+                save_checkpoint(&get_config().checkpoint).await?;
+            }
+
+            Err("Interrupted".into())
         },
     }
+}
+
+// Applies the retention rules once and prints the report
+async fn run_retention_command(dry_run: bool) -> Result<(), String> {
+    init_storage(&get_config().database).await
+        .map_err(|e| format!("Database connection error: {}", e))?;
+
+    if get_config().database.migrate {
+        get_storage()?.migrate().await?;
+    }
+
+    let report = run_retention(&get_config().retention, dry_run).await?;
+
+    let report = serde_json::to_string_pretty(&report)
+        .map_err(|e| format!("Failed to serialize the retention report: {}", e))?;
+    println!("Retention report: {}", report);
+
+    Ok(())
+}
+
+// Signs a token with the secret of the configuration
+fn run_token_command(args: &[String]) -> Result<(), String> {
+    let secret = get_config().auth.secret.as_ref()
+        .ok_or("The signed tokens need auth.secret in the configuration")?;
+
+    let name = args.first().filter(|name| !name.starts_with("--"))
+        .ok_or("Usage: paragon token <name> [--symbols EURUSD,GBPUSD] [--expires-in <seconds>] [--admin]")?;
+    let option = |flag: &str| args.iter().position(|arg| arg == flag).and_then(|i| args.get(i + 1));
+
+    let symbols = option("--symbols")
+        .map(|symbols| symbols.split(',').map(|s| s.trim().to_string()).collect())
+        .unwrap_or_default();
+
+    let exp = match option("--expires-in") {
+        Some(seconds) => {
+            let seconds: i64 = seconds.parse()
+                .map_err(|e| format!("Invalid expiration {}: {}", seconds, e))?;
+            Some(chrono::Utc::now().timestamp() + seconds)
+        }
+        None => None,
+    };
+
+    let admin = args.iter().any(|arg| arg == "--admin");
+
+    let claims = Claims { sub: name.clone(), symbols, exp, admin };
+    println!("{}", sign_token(secret, &claims)?);
+
+    Ok(())
 }
\ No newline at end of file
@@ -1,2 +1,3 @@
 pub mod temporary;
+#[allow(clippy::module_inception)]
 pub mod utils;
\ No newline at end of file
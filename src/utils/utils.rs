@@ -1,4 +1,16 @@
 use chrono::NaiveTime;
+use dashmap::DashSet;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Deserializer};
+use uuid::Uuid;
+
+// Strings that have been turned into &'static str at runtime
+// The entities only hold &'static str, so labels that aren't known at compile time
+// (configured timeranges, values loaded from the database...) are leaked once and reused
+static INTERNED: Lazy<DashSet<&'static str>> = Lazy::new(DashSet::new);
+
+// Namespace of the stable ids of the entities
+static NAMESPACE: Lazy<Uuid> = Lazy::new(|| Uuid::new_v5(&Uuid::NAMESPACE_OID, b"paragon"));
 
 pub fn is_in_timerange(start: NaiveTime, end: NaiveTime, time: NaiveTime) -> bool {
     if start <= end {
@@ -7,4 +19,41 @@ pub fn is_in_timerange(start: NaiveTime, end: NaiveTime, time: NaiveTime) -> boo
         // If the range wraps around midnight
         time >= start || time <= end
     }
+}
+
+// Returns a &'static str with the same content as the value
+// Each distinct value is only leaked once
+pub fn intern(value: &str) -> &'static str {
+    if let Some(interned) = INTERNED.get(value) {
+        return *interned;
+    }
+
+    let leaked: &'static str = Box::leak(value.to_string().into_boxed_str());
+
+    // Another thread may have interned the same value in the meantime
+    // In that case we keep the first one (and lose a few bytes)
+    if !INTERNED.insert(leaked) {
+        if let Some(interned) = INTERNED.get(value) {
+            return *interned;
+        }
+    }
+
+    leaked
+}
+
+// The &'static str fields of the entities that can be deserialized
+// Serde borrows every field written as `&str` from the input, which would require a 'static input,
+// Going through an alias with #[serde(deserialize_with = "interned")] interns the strings instead
+pub type Interned = &'static str;
+
+// Deserializes a string into an interned &'static str
+pub fn interned<'de, D: Deserializer<'de>>(deserializer: D) -> Result<&'static str, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    Ok(intern(&value))
+}
+
+// Builds the stable id of an entity from the fields identifying it (the unique key of its table)
+// The same entity always gets the same id, even when it's rebuilt after a restart or a replay
+pub fn stable_id(parts: &[&str]) -> Uuid {
+    Uuid::new_v5(&NAMESPACE, parts.join("/").as_bytes())
 }
\ No newline at end of file
@@ -0,0 +1,67 @@
+// Helpers of the end-to-end tests of the websocket server and its client
+// The server can only be started once per process, so each test that needs one is in its own file
+
+use paragon::{
+    config::WebsocketConfig,
+    connections::{
+        client::{Event, StreamClient},
+        messages::Payload,
+        subscriptions::{MessageKind, Topic},
+        websocket::{create_intra_websocket, send_message_to_clients},
+    },
+    Candle,
+};
+
+use chrono::DateTime;
+use std::time::Duration;
+use tokio::time::timeout;
+
+// Starts the server in the background, the clients retry until it listens
+pub fn start_server(config: &str) {
+    let config: WebsocketConfig = serde_json::from_str(config).unwrap();
+
+    tokio::spawn(async move {
+        if let Err(e) = create_intra_websocket(&config).await {
+            panic!("Failed to start the WebSocket server: {}", e);
+        }
+    });
+}
+
+// Broadcasts a 5min candle, the minute tells the candles apart
+pub async fn send_candle(symbol: &'static str, minute: i64) {
+    let timestamp = DateTime::from_timestamp(1_700_000_100 + minute * 60, 0).unwrap();
+    let candle = Candle::new(symbol, "5min", timestamp, 1.1, 1.2, 1.0, 1.15, 10.0);
+
+    let topic = Topic {
+        kind: MessageKind::Candle,
+        symbol,
+        timerange: Some("5min"),
+        structure: None,
+    };
+
+    send_message_to_clients(topic, Payload::candle(&candle)).await.unwrap();
+}
+
+// The minute of a candle sent by `send_candle`
+pub fn minute(candle: &Candle) -> i64 {
+    (candle.timestamp.timestamp() - 1_700_000_100) / 60
+}
+
+pub async fn next_event(client: &mut StreamClient) -> Event {
+    timeout(Duration::from_secs(5), client.next())
+        .await
+        .expect("No event received in time")
+        .expect("The client is stopped")
+}
+
+// Skips the events until the subscription is acknowledged (the server acks with a snapshot)
+// The failed attempts to connect while the server starts are skipped too
+pub async fn wait_for_snapshot(client: &mut StreamClient, subscription: u64) {
+    loop {
+        match next_event(client).await {
+            Event::Snapshot(snapshot) if snapshot.id == Some(subscription) => return,
+            Event::Error(error) => panic!("Unexpected error: {}", error.message),
+            _ => {}
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,66 @@
+mod common;
+
+use common::{minute, next_event, send_candle, start_server, wait_for_snapshot};
+use paragon::connections::{
+    client::{ClientOptions, Event, StreamClient},
+    subscriptions::Filter,
+};
+
+// The messages that don't fit in the queue of the client are dropped, so the gaps can be seen
+const CONFIG: &str = r#"{
+    "address": "127.0.0.1:38471",
+    "client_queue": 1,
+    "lagging_policy": "drop",
+    "heartbeat_interval_ms": 0
+}"#;
+
+#[tokio::test]
+async fn subscribes_and_reports_the_gaps() {
+    start_server(CONFIG);
+
+    let mut client = StreamClient::connect(ClientOptions {
+        snapshot: true,
+        reconnect_delay_ms: 50,
+        ..ClientOptions::new("ws://127.0.0.1:38471")
+    });
+    let subscription = client.subscribe(Filter {
+        symbols: vec!["EURUSD".to_string()],
+        ..Filter::default()
+    });
+    wait_for_snapshot(&mut client, subscription).await;
+
+    // Only the candles of the subscription are received
+    send_candle("GBPUSD", 0).await;
+    send_candle("EURUSD", 1).await;
+
+    match next_event(&mut client).await {
+        Event::Candle(candle) => assert_eq!((candle.symbol, minute(&candle)), ("EURUSD", 1)),
+        _ => panic!("Expected the EURUSD candle"),
+    }
+
+    // Sent at once, the queue of the client (a single message) overflows before it's written
+    for minute in 2..12 {
+        send_candle("EURUSD", minute).await;
+    }
+
+    // The gap is only seen with the next message, sent once the queue is written
+    let mut received = 0;
+    let mut missed = 0;
+    let mut last_sent = false;
+    loop {
+        match next_event(&mut client).await {
+            Event::Candle(candle) if minute(&candle) == 12 => break,
+            Event::Candle(_) => received += 1,
+            Event::Missed(count) => missed += count,
+            _ => panic!("Expected the candles or the gaps"),
+        }
+
+        if !last_sent {
+            send_candle("EURUSD", 12).await;
+            last_sent = true;
+        }
+    }
+
+    assert!(missed > 0, "No gap was reported");
+    assert_eq!(received + missed, 10);
+}
\ No newline at end of file
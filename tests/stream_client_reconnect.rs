@@ -0,0 +1,60 @@
+mod common;
+
+use common::{minute, next_event, send_candle, start_server, wait_for_snapshot};
+use paragon::connections::{
+    client::{ClientOptions, Event, StreamClient},
+    subscriptions::Filter,
+};
+
+use std::time::Duration;
+use tokio::time::sleep;
+
+// A client that doesn't answer the pings is disconnected quickly
+const CONFIG: &str = r#"{
+    "address": "127.0.0.1:38472",
+    "ping_interval_ms": 100,
+    "pong_timeout_ms": 200,
+    "heartbeat_interval_ms": 0
+}"#;
+
+#[tokio::test]
+async fn reconnects_and_subscribes_again() {
+    start_server(CONFIG);
+
+    // With a single event buffered, the client stops reading the server until its events are read
+    let mut client = StreamClient::connect(ClientOptions {
+        snapshot: true,
+        reconnect_delay_ms: 50,
+        buffer: 1,
+        ..ClientOptions::new("ws://127.0.0.1:38472")
+    });
+    let subscription = client.subscribe(Filter {
+        symbols: vec!["EURUSD".to_string()],
+        ..Filter::default()
+    });
+    wait_for_snapshot(&mut client, subscription).await;
+
+    // The pings aren't answered while the events wait, so the server closes the connection
+    for minute in 0..3 {
+        send_candle("EURUSD", minute).await;
+    }
+    sleep(Duration::from_secs(1)).await;
+
+    loop {
+        match next_event(&mut client).await {
+            Event::Disconnected(_) => break,
+            Event::Candle(_) => {}
+            _ => panic!("Expected the candles then the disconnection"),
+        }
+    }
+
+    // Subscribed again once reconnected, without calling `subscribe`
+    wait_for_snapshot(&mut client, subscription).await;
+    send_candle("GBPUSD", 10).await;
+    send_candle("EURUSD", 11).await;
+
+    match next_event(&mut client).await {
+        Event::Candle(candle) => assert_eq!((candle.symbol, minute(&candle)), ("EURUSD", 11)),
+        _ => panic!("Expected the EURUSD candle"),
+    }
+}
\ No newline at end of file